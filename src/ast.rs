@@ -0,0 +1,246 @@
+use std::fmt::Write;
+
+use crate::token::Token;
+
+// syntax tree produced by the parser, annotated by the resolver and compiled by the code generator
+
+// source position of a node, taken from the token which introduces it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+impl Span {
+    pub fn of(token: &Token) -> Self {
+        Span {line: token.line, col: token.col, len: token.len}
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    NEGATE, // -
+    NOT,    // !
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    ADD,
+    SUB,
+    MUL,
+    DIV,
+    REM,
+    EQUAL,
+    NOT_EQUAL,
+    LESS,
+    LESS_EQUAL,
+    GREATER,
+    GREATER_EQUAL,
+}
+
+// short-circuiting operators
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogicalOp {
+    AND,
+    OR,
+}
+
+// storage of a variable, filled in by the resolver
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolved {
+    GLOBAL,
+    LOCAL(usize), // stack slot
+}
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: Token,
+    pub resolved: Resolved,
+}
+
+impl Variable {
+    pub fn new(name: Token) -> Self {
+        Variable {name, resolved: Resolved::GLOBAL}
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    NUMBER(f64),
+    STRING(String),
+    BOOL(bool),
+    NIL,
+    VARIABLE(Variable),
+    ASSIGN(Variable, Box<Expr>),
+    UNARY(UnaryOp, Box<Expr>),
+    BINARY(BinaryOp, Box<Expr>, Box<Expr>),
+    LOGICAL(LogicalOp, Box<Expr>, Box<Expr>),
+    CALL(Box<Expr>, Vec<Expr>), // callee, arguments
+    GET(Box<Expr>, Token), // object, property
+    SET(Box<Expr>, Token, Box<Expr>), // object, property, value
+    INVOKE(Box<Expr>, Token, Vec<Expr>), // object, method, arguments
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span, // literal, variable name, operator, `(` of a call or name of a property
+}
+
+// statements between `{` and `}`, `end` is the closing brace where locals of the block are freed
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub statements: Vec<Stmt>,
+    pub end: Span,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+    VAR(Variable, Option<Expr>),
+    PRINT(Expr),
+    EXPRESSION(Expr),
+    BLOCK(Block),
+    IF(Expr, Block, Option<Block>),
+    WHILE(Expr, Block),
+    BREAK,
+    CONTINUE,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span, // first token of the statement
+}
+
+impl UnaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOp::NEGATE => "-",
+            UnaryOp::NOT => "!",
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::ADD => "+",
+            BinaryOp::SUB => "-",
+            BinaryOp::MUL => "*",
+            BinaryOp::DIV => "/",
+            BinaryOp::REM => "%",
+            BinaryOp::EQUAL => "==",
+            BinaryOp::NOT_EQUAL => "!=",
+            BinaryOp::LESS => "<",
+            BinaryOp::LESS_EQUAL => "<=",
+            BinaryOp::GREATER => ">",
+            BinaryOp::GREATER_EQUAL => ">=",
+        }
+    }
+}
+
+impl LogicalOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            LogicalOp::AND => "and",
+            LogicalOp::OR => "or",
+        }
+    }
+}
+
+fn variable(v: &Variable) -> String {
+    match v.resolved {
+        Resolved::GLOBAL => format!("{} (global)", v.name.lexeme),
+        Resolved::LOCAL(slot) => format!("{} (local {})", v.name.lexeme, slot),
+    }
+}
+
+fn dump_expr(out: &mut String, expr: &Expr, indent: usize) {
+    let node = match &expr.kind {
+        ExprKind::NUMBER(x) => format!("NUMBER {}", x),
+        ExprKind::STRING(x) => format!("STRING {:?}", x),
+        ExprKind::BOOL(x) => format!("BOOL {}", x),
+        ExprKind::NIL => "NIL".to_string(),
+        ExprKind::VARIABLE(v) => format!("VARIABLE {}", variable(v)),
+        ExprKind::ASSIGN(v, _) => format!("ASSIGN {}", variable(v)),
+        ExprKind::UNARY(op, _) => format!("UNARY {}", op.symbol()),
+        ExprKind::BINARY(op, _, _) => format!("BINARY {}", op.symbol()),
+        ExprKind::LOGICAL(op, _, _) => format!("LOGICAL {}", op.symbol()),
+        ExprKind::CALL(_, args) => format!("CALL {}", args.len()),
+        ExprKind::GET(_, name) => format!("GET {}", name.lexeme),
+        ExprKind::SET(_, name, _) => format!("SET {}", name.lexeme),
+        ExprKind::INVOKE(_, name, args) => format!("INVOKE {} {}", name.lexeme, args.len()),
+    };
+    let _ = writeln!(out, "{:indent$}{}:{} {}", "", expr.span.line, expr.span.col + 1, node, indent = indent);
+    match &expr.kind {
+        ExprKind::ASSIGN(_, x) | ExprKind::UNARY(_, x) | ExprKind::GET(x, _) => dump_expr(out, x, indent + 2),
+        ExprKind::BINARY(_, a, b) | ExprKind::LOGICAL(_, a, b) | ExprKind::SET(a, _, b) => {
+            dump_expr(out, a, indent + 2);
+            dump_expr(out, b, indent + 2);
+        },
+        ExprKind::CALL(callee, args) | ExprKind::INVOKE(callee, _, args) => {
+            dump_expr(out, callee, indent + 2);
+            for arg in args {
+                dump_expr(out, arg, indent + 2);
+            }
+        },
+        _ => {}
+    }
+}
+
+fn dump_block(out: &mut String, name: &str, block: &Block, indent: usize) {
+    let _ = writeln!(out, "{:indent$}{}", "", name, indent = indent);
+    for stmt in block.statements.iter() {
+        dump_stmt(out, stmt, indent + 2);
+    }
+}
+
+fn dump_stmt(out: &mut String, stmt: &Stmt, indent: usize) {
+    let position = format!("{}:{}", stmt.span.line, stmt.span.col + 1);
+    let _ = match &stmt.kind {
+        StmtKind::VAR(v, _) => writeln!(out, "{:indent$}{} VAR {}", "", position, variable(v), indent = indent),
+        StmtKind::PRINT(_) => writeln!(out, "{:indent$}{} PRINT", "", position, indent = indent),
+        StmtKind::EXPRESSION(_) => writeln!(out, "{:indent$}{} EXPRESSION", "", position, indent = indent),
+        StmtKind::BLOCK(_) => writeln!(out, "{:indent$}{} BLOCK", "", position, indent = indent),
+        StmtKind::IF(..) => writeln!(out, "{:indent$}{} IF", "", position, indent = indent),
+        StmtKind::WHILE(..) => writeln!(out, "{:indent$}{} WHILE", "", position, indent = indent),
+        StmtKind::BREAK => writeln!(out, "{:indent$}{} BREAK", "", position, indent = indent),
+        StmtKind::CONTINUE => writeln!(out, "{:indent$}{} CONTINUE", "", position, indent = indent),
+    };
+    match &stmt.kind {
+        StmtKind::VAR(_, Some(x)) | StmtKind::PRINT(x) | StmtKind::EXPRESSION(x) => dump_expr(out, x, indent + 2),
+        StmtKind::BLOCK(block) => {
+            for stmt in block.statements.iter() {
+                dump_stmt(out, stmt, indent + 2);
+            }
+        },
+        StmtKind::IF(condition, then, otherwise) => {
+            dump_expr(out, condition, indent + 2);
+            dump_block(out, "THEN", then, indent + 2);
+            if let Some(otherwise) = otherwise {
+                dump_block(out, "ELSE", otherwise, indent + 2);
+            }
+        },
+        StmtKind::WHILE(condition, body) => {
+            dump_expr(out, condition, indent + 2);
+            dump_block(out, "DO", body, indent + 2);
+        },
+        _ => {}
+    }
+}
+
+// indented tree of the program with `line:column` of each node, printed by `oxa --dump-ast`
+pub fn dump(statements: &[Stmt]) -> String {
+    let mut out = String::new();
+    for stmt in statements {
+        dump_stmt(&mut out, stmt, 0);
+    }
+    out
+}
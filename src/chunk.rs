@@ -1,12 +1,16 @@
+use crate::intern::{Interner, Symbol};
 use crate::value::Value;
 
+use std::collections::HashMap;
+
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone)]
 pub enum OpCode {
     RETURN,
     CONSTANT(usize),
     POP,
-    DEFINE_GLOBAL(usize),
+    POPN(usize), // pops locals of a block on exit
+    DEFINE_GLOBAL(usize), // slot in `Chunk::globals`
     GET_GLOBAL(usize),
     SET_GLOBAL(usize),
     GET_LOCAL(usize),
@@ -25,6 +29,20 @@ pub enum OpCode {
     EQUAL,
     GREATER,
     LESS,
+    // fused by the peephole optimizer
+    NOT_EQUAL,
+    LESS_EQUAL,
+    GREATER_EQUAL,
+    POP_JUMP_IF_FALSE(usize),
+    // superinstructions for loops, fused at `-O2`
+    INC_LOCAL(usize, usize), // slot, address of the constant added
+    LESS_LOCAL_LOCAL(usize, usize),
+    LESS_LOCAL_CONSTANT(usize, usize),
+    CALL(usize), // number of arguments, pushed after the callee
+    // userdata, operand is the address of the name of the property or method
+    GET_PROPERTY(usize),
+    SET_PROPERTY(usize),
+    INVOKE(usize, usize), // name, number of arguments pushed after the object
     // keywords
     PRINT,
     IF(usize),
@@ -33,6 +51,29 @@ pub enum OpCode {
 }
 
 impl OpCode {
+    // human readable form of the instruction, with constant operands resolved against the chunk
+    pub fn describe(&self, chunk: &Chunk) -> String {
+        match self {
+            OpCode::CONSTANT(addr) => format!("CONSTANT {} ({})", addr, chunk.values[*addr].repr()),
+            OpCode::DEFINE_GLOBAL(slot) => format!("DEFINE_GLOBAL {}", chunk.globals.name(Symbol(*slot))),
+            OpCode::GET_GLOBAL(slot) => format!("GET_GLOBAL {}", chunk.globals.name(Symbol(*slot))),
+            OpCode::SET_GLOBAL(slot) => format!("SET_GLOBAL {}", chunk.globals.name(Symbol(*slot))),
+            OpCode::GET_LOCAL(slot) => format!("GET_LOCAL {}", slot),
+            OpCode::SET_LOCAL(slot) => format!("SET_LOCAL {}", slot),
+            OpCode::IF(jaddr) => format!("IF -> {:0>4}", jaddr),
+            OpCode::IFN(jaddr) => format!("IFN -> {:0>4}", jaddr),
+            OpCode::JMP(jaddr) => format!("JMP -> {:0>4}", jaddr),
+            OpCode::POP_JUMP_IF_FALSE(jaddr) => format!("POP_JUMP_IF_FALSE -> {:0>4}", jaddr),
+            OpCode::GET_PROPERTY(addr) => format!("GET_PROPERTY {}", chunk.values[*addr]),
+            OpCode::SET_PROPERTY(addr) => format!("SET_PROPERTY {}", chunk.values[*addr]),
+            OpCode::INVOKE(addr, argc) => format!("INVOKE {} {}", chunk.values[*addr], argc),
+            OpCode::INC_LOCAL(slot, addr) => format!("INC_LOCAL {} {}", slot, chunk.values[*addr].repr()),
+            OpCode::LESS_LOCAL_LOCAL(a, b) => format!("LESS_LOCAL_LOCAL {} {}", a, b),
+            OpCode::LESS_LOCAL_CONSTANT(slot, addr) => format!("LESS_LOCAL_CONSTANT {} {}", slot, chunk.values[*addr].repr()),
+            _ => format!("{:?}", self),
+        }
+    }
+
     pub fn dissassemble_instruction(&self, chunk: &Chunk, offset: usize) -> usize {
         print!("{:0>4}  ", offset);
         if offset > 0 && chunk.get_line(offset) == chunk.get_line(offset - 1) {
@@ -57,16 +98,64 @@ impl OpCode {
     }
 }
 
-#[derive(Default)]
+// debug info: local variable named `name` lives in stack slot `slot` while `start <= ip < end`
+#[derive(Debug, Clone)]
+pub struct LocalInfo {
+    pub name: String,
+    pub line: usize, // line of declaration
+    pub slot: usize,
+    pub depth: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// debug info: code range of a block statement at scope depth `depth`
+#[derive(Debug, Clone)]
+pub struct ScopeInfo {
+    pub depth: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// identity of a constant, floats compare by their bits so `0` and `-0` stay apart and NaN is found again
+#[allow(non_camel_case_types)]
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    FLOAT(u64),
+    BOOL(bool),
+    STRING(String),
+    NATIVE(String, usize), // name and address of the function
+    USERDATA(usize), // address of the object
+    NIL
+}
+
+impl ConstantKey {
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::FLOAT(x) => ConstantKey::FLOAT(x.to_bits()),
+            Value::BOOL(x) => ConstantKey::BOOL(*x),
+            Value::STRING(x) => ConstantKey::STRING(x.clone()),
+            Value::NATIVE(x) => ConstantKey::NATIVE(x.name.clone(), x.function as usize),
+            Value::USERDATA(x) => ConstantKey::USERDATA(std::rc::Rc::as_ptr(x.object()) as *const () as usize),
+            Value::NIL => ConstantKey::NIL,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct Chunk {
     pub code: Vec<OpCode>, // each instruction is byte long
     values: Vec<Value>, // immediate types
+    constants: HashMap<ConstantKey, usize>, // address of every value in `values`
+    pub globals: Interner, // names of the global variables used by the chunk, numbered by slot
     lines: Vec<usize>, // index: line no, value: no of instructions on that line
+    pub locals: Vec<LocalInfo>,
+    pub scopes: Vec<ScopeInfo>,
 }
 
 impl Chunk {
     pub fn new() -> Self {
-        Chunk {code: vec![], values: vec![], lines: vec![]}
+        Chunk {code: vec![], values: vec![], constants: HashMap::new(), globals: Interner::default(), lines: vec![], locals: vec![], scopes: vec![]}
     }
 
     pub fn read_instruction(&self, ip: &mut usize) -> &OpCode {
@@ -74,8 +163,8 @@ impl Chunk {
         &self.code[*ip - 1]
     }
 
-    pub fn read_value(&self, addr: usize) -> Value {
-        self.values[addr].clone()
+    pub fn values(&self) -> &[Value] {
+        &self.values
     }
 
     pub fn write_chunk(&mut self, byte: OpCode, line: usize) {
@@ -107,14 +196,47 @@ impl Chunk {
         return 0;
     }
 
+    // source line of every instruction
+    pub fn instruction_lines(&self) -> Vec<usize> {
+        let mut lines = vec![];
+        for (i, n) in self.lines.iter().enumerate() {
+            lines.extend(std::iter::repeat_n(i + 1, *n));
+        }
+        lines
+    }
+
+    // replaces the instructions, `code` pairs every instruction with its source line
+    pub fn set_code(&mut self, code: Vec<(OpCode, usize)>) {
+        self.code.clear();
+        self.lines.clear();
+        for (byte, line) in code {
+            self.write_chunk(byte, line);
+        }
+    }
+
+    // checks if any instruction was emitted for source line `line`
+    pub fn has_line(&self, line: usize) -> bool {
+        line > 0 && line <= self.lines.len() && self.lines[line - 1] > 0
+    }
+
+    // local variables which are alive at instruction `offset`, outermost first
+    pub fn live_locals(&self, offset: usize) -> Vec<&LocalInfo> {
+        self.locals.iter().filter(|x| x.start <= offset && offset < x.end).collect()
+    }
+
+    // depth of the innermost block statement containing instruction `offset`
+    pub fn scope_depth(&self, offset: usize) -> usize {
+        self.scopes.iter().filter(|x| x.start <= offset && offset < x.end).map(|x| x.depth).max().unwrap_or(0)
+    }
+
     // pushes value to the value vector, return its index. if value is already in vector, return index
     pub fn write_value(&mut self, value: Value) -> usize {
-        if let Some(x) = self.values.last() {
-            if *x == value {
-                return self.values.len() - 1;
-            }
+        let key = ConstantKey::of(&value);
+        if let Some(addr) = self.constants.get(&key) {
+            return *addr;
         }
         self.values.push(value);
+        self.constants.insert(key, self.values.len() - 1);
         self.values.len() - 1
     }
 
@@ -0,0 +1,279 @@
+use crate::ast::*;
+use crate::chunk::{Chunk, OpCode, LocalInfo, ScopeInfo};
+use crate::value::Value;
+
+// jumps of `break` and `continue` statements of the innermost loop
+struct Loop {
+    start: usize, // offset of the loop condition
+    locals: usize, // number of locals alive when the loop was entered
+    breaks: Vec<usize>,
+}
+
+// emits bytecode for a resolved syntax tree
+#[derive(Default)]
+pub struct Generator {
+    chunk: Chunk,
+    line: usize, // line of the last emitted instruction
+    live: Vec<usize>, // locals alive at the current point, as indices into `chunk.locals`
+    scope_depth: usize,
+    loops: Vec<Loop>,
+}
+
+impl Generator {
+    pub fn new() -> Self {
+        Generator::default()
+    }
+
+    // the line table of `Chunk` only grows, an instruction can't be placed on a line before its predecessor
+    fn write_byte(&mut self, byte: OpCode, line: usize) {
+        self.line = self.line.max(line);
+        self.chunk.write_chunk(byte, self.line);
+    }
+
+    fn write_constant(&mut self, constant: Value, line: usize) {
+        let address = self.chunk.write_value(constant);
+        self.write_byte(OpCode::CONSTANT(address), line);
+    }
+
+    // writes jump with a placeholder target, returns its offset for `patch`
+    fn write_jump(&mut self, byte: OpCode, line: usize) -> usize {
+        self.write_byte(byte, line);
+        self.chunk.code.len() - 1
+    }
+
+    // pops `n` locals off the stack
+    fn write_pops(&mut self, n: usize, line: usize) {
+        match n {
+            0 => {},
+            1 => self.write_byte(OpCode::POP, line),
+            n => self.write_byte(OpCode::POPN(n), line)
+        }
+    }
+
+    // points jump at `index` to the next instruction
+    fn patch(&mut self, index: usize) {
+        let jaddr = self.chunk.code.len();
+        self.chunk.code[index] = match self.chunk.code[index] {
+            OpCode::IF(_) => OpCode::IF(jaddr),
+            OpCode::IFN(_) => OpCode::IFN(jaddr),
+            _ => OpCode::JMP(jaddr),
+        };
+    }
+
+    // value of the last top-level expression statement is left on the stack as the result of the program
+    pub fn program(mut self, statements: &[Stmt]) -> Chunk {
+        for (i, stmt) in statements.iter().enumerate() {
+            match &stmt.kind {
+                StmtKind::EXPRESSION(expr) if i == statements.len() - 1 => self.expression(expr),
+                _ => self.statement(stmt)
+            }
+        }
+        self.write_byte(OpCode::RETURN, 0);
+        self.chunk
+    }
+
+    // chunk evaluating a single expression, used by debuggers
+    pub fn single_expression(mut self, expr: &Expr) -> Chunk {
+        self.expression(expr);
+        self.write_byte(OpCode::RETURN, 0);
+        self.chunk
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        let line = stmt.span.line;
+        match &stmt.kind {
+            StmtKind::VAR(variable, initializer) => {
+                match initializer {
+                    Some(expr) => self.expression(expr),
+                    None => self.write_constant(Value::NIL, line)
+                }
+                match variable.resolved {
+                    Resolved::LOCAL(slot) => {
+                        // value of the initializer stays on the stack as the local
+                        self.chunk.locals.push(LocalInfo {
+                            name: variable.name.lexeme.clone(),
+                            line: variable.name.line,
+                            slot,
+                            depth: self.scope_depth,
+                            start: self.chunk.code.len(),
+                            end: usize::MAX
+                        });
+                        self.live.push(self.chunk.locals.len() - 1);
+                    },
+                    Resolved::GLOBAL => {
+                        let slot = self.chunk.globals.intern(&variable.name.lexeme).0;
+                        self.write_byte(OpCode::DEFINE_GLOBAL(slot), line);
+                    }
+                }
+            },
+            StmtKind::PRINT(expr) => {
+                self.expression(expr);
+                self.write_byte(OpCode::PRINT, line);
+            },
+            StmtKind::EXPRESSION(expr) => {
+                self.expression(expr);
+                self.write_byte(OpCode::POP, line);
+            },
+            StmtKind::BLOCK(block) => self.block(block),
+            StmtKind::IF(condition, then, otherwise) => {
+                self.expression(condition);
+                let index = self.write_jump(OpCode::IF(0), line);
+                self.write_byte(OpCode::POP, line);
+                self.block(then);
+                let exit = self.write_jump(OpCode::JMP(0), then.end.line);
+                // condition is popped on both paths
+                self.patch(index);
+                self.write_byte(OpCode::POP, then.end.line);
+                if let Some(otherwise) = otherwise {
+                    self.block(otherwise);
+                }
+                self.patch(exit);
+            },
+            StmtKind::WHILE(condition, body) => {
+                let start = self.chunk.code.len();
+                self.expression(condition);
+                let index = self.write_jump(OpCode::IF(0), line);
+                self.write_byte(OpCode::POP, line);
+                self.loops.push(Loop {start, locals: self.live.len(), breaks: vec![]});
+                self.block(body);
+                self.write_byte(OpCode::JMP(start), body.end.line);
+                self.patch(index);
+                self.write_byte(OpCode::POP, body.end.line);
+                let lp = self.loops.pop().unwrap();
+                for i in lp.breaks {
+                    self.patch(i);
+                }
+            },
+            StmtKind::BREAK | StmtKind::CONTINUE => {
+                let lp = self.loops.last().unwrap();
+                let (start, locals) = (lp.start, lp.locals);
+                // free locals declared inside the loop body
+                self.write_pops(self.live.len() - locals, line);
+                if let StmtKind::BREAK = stmt.kind {
+                    let index = self.write_jump(OpCode::JMP(0), line);
+                    self.loops.last_mut().unwrap().breaks.push(index);
+                } else {
+                    self.write_byte(OpCode::JMP(start), line);
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        let start = self.chunk.code.len();
+        let live = self.live.len();
+        self.scope_depth += 1;
+        for stmt in block.statements.iter() {
+            self.statement(stmt);
+        }
+        // free stack and local variables
+        let end = self.chunk.code.len();
+        let locals = self.live.split_off(live);
+        for i in locals.iter() {
+            self.chunk.locals[*i].end = end;
+        }
+        self.write_pops(locals.len(), block.end.line);
+        self.chunk.scopes.push(ScopeInfo {depth: self.scope_depth, start, end: self.chunk.code.len()});
+        self.scope_depth -= 1;
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        let line = expr.span.line;
+        match &expr.kind {
+            ExprKind::NUMBER(x) => self.write_constant(Value::FLOAT(*x), line),
+            ExprKind::STRING(x) => self.write_constant(Value::STRING(x.clone()), line),
+            ExprKind::BOOL(x) => self.write_constant(Value::BOOL(*x), line),
+            ExprKind::NIL => self.write_constant(Value::NIL, line),
+            ExprKind::VARIABLE(variable) => match variable.resolved {
+                Resolved::LOCAL(slot) => self.write_byte(OpCode::GET_LOCAL(slot), line),
+                Resolved::GLOBAL => {
+                    let slot = self.chunk.globals.intern(&variable.name.lexeme).0;
+                    self.write_byte(OpCode::GET_GLOBAL(slot), line);
+                }
+            },
+            ExprKind::ASSIGN(variable, value) => {
+                self.expression(value);
+                match variable.resolved {
+                    Resolved::LOCAL(slot) => self.write_byte(OpCode::SET_LOCAL(slot), line),
+                    Resolved::GLOBAL => {
+                        let slot = self.chunk.globals.intern(&variable.name.lexeme).0;
+                        self.write_byte(OpCode::SET_GLOBAL(slot), line);
+                    }
+                }
+            },
+            ExprKind::UNARY(op, operand) => {
+                self.expression(operand);
+                match op {
+                    UnaryOp::NEGATE => self.write_byte(OpCode::NEGATE, line),
+                    UnaryOp::NOT => self.write_byte(OpCode::BANG, line),
+                }
+            },
+            ExprKind::BINARY(op, left, right) => {
+                self.expression(left);
+                self.expression(right);
+                match op {
+                    BinaryOp::ADD => self.write_byte(OpCode::ADD, line),
+                    BinaryOp::SUB => self.write_byte(OpCode::SUB, line),
+                    BinaryOp::MUL => self.write_byte(OpCode::MUL, line),
+                    BinaryOp::DIV => self.write_byte(OpCode::DIV, line),
+                    BinaryOp::REM => self.write_byte(OpCode::REM, line),
+                    BinaryOp::EQUAL => self.write_byte(OpCode::EQUAL, line),
+                    BinaryOp::LESS => self.write_byte(OpCode::LESS, line),
+                    BinaryOp::GREATER => self.write_byte(OpCode::GREATER, line),
+                    BinaryOp::NOT_EQUAL => {
+                        self.write_byte(OpCode::EQUAL, line);
+                        self.write_byte(OpCode::BANG, line);
+                    },
+                    BinaryOp::LESS_EQUAL => {
+                        self.write_byte(OpCode::GREATER, line);
+                        self.write_byte(OpCode::BANG, line);
+                    },
+                    BinaryOp::GREATER_EQUAL => {
+                        self.write_byte(OpCode::LESS, line);
+                        self.write_byte(OpCode::BANG, line);
+                    },
+                }
+            },
+            ExprKind::LOGICAL(op, left, right) => {
+                // lhs which decides the result is left on the stack and the rhs skipped
+                self.expression(left);
+                let index = match op {
+                    LogicalOp::OR => self.write_jump(OpCode::IFN(0), line),
+                    LogicalOp::AND => self.write_jump(OpCode::IF(0), line),
+                };
+                self.expression(right);
+                match op {
+                    LogicalOp::OR => self.write_byte(OpCode::OR, line),
+                    LogicalOp::AND => self.write_byte(OpCode::AND, line),
+                }
+                self.patch(index);
+            },
+            ExprKind::CALL(callee, args) => {
+                self.expression(callee);
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.write_byte(OpCode::CALL(args.len()), line);
+            },
+            ExprKind::GET(object, name) => {
+                self.expression(object);
+                let address = self.chunk.write_value(Value::STRING(name.lexeme.clone()));
+                self.write_byte(OpCode::GET_PROPERTY(address), line);
+            },
+            ExprKind::SET(object, name, value) => {
+                self.expression(object);
+                self.expression(value);
+                let address = self.chunk.write_value(Value::STRING(name.lexeme.clone()));
+                self.write_byte(OpCode::SET_PROPERTY(address), line);
+            },
+            ExprKind::INVOKE(object, name, args) => {
+                self.expression(object);
+                for arg in args {
+                    self.expression(arg);
+                }
+                let address = self.chunk.write_value(Value::STRING(name.lexeme.clone()));
+                self.write_byte(OpCode::INVOKE(address, args.len()), line);
+            },
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use crate::Error;
+use crate::native::{self, Native};
+use crate::userdata::Userdata;
+use crate::value::Value;
+
+// conversions between Rust types and `Value` for embedders, used by `Interpreter::call` and
+// `native::arg`. numbers are floats in the language, converting to an integer type fails for
+// fractions and values out of range. the language has no lists or maps yet, so there are no
+// impls for `Vec` or maps
+
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, Error>;
+}
+
+fn mismatch(expected: &str, value: &Value) -> Error {
+    native::error(format!("TypeError: expected {}, got {}", expected, value.type_name()))
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        Ok(value)
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::FLOAT(self)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::FLOAT(x) => Ok(x),
+            x => Err(mismatch("number", &x))
+        }
+    }
+}
+
+impl IntoValue for f32 {
+    fn into_value(self) -> Value {
+        Value::FLOAT(self as f64)
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        f64::from_value(value).map(|x| x as f32)
+    }
+}
+
+// integers are exact in a float up to 2^53, larger ones are rounded
+macro_rules! integer {
+    ($($t:ty),*) => {$(
+        impl IntoValue for $t {
+            fn into_value(self) -> Value {
+                Value::FLOAT(self as f64)
+            }
+        }
+
+        impl FromValue for $t {
+            fn from_value(value: Value) -> Result<Self, Error> {
+                let x = f64::from_value(value)?;
+                if x.fract() != 0.0 || !x.is_finite() {
+                    return Err(native::error(format!("ValueError: expected integer, got {}", x)));
+                }
+                // `MAX + 1` is a power of two, so it is exact even where `MAX` isn't
+                if x < <$t>::MIN as f64 || x >= <$t>::MAX as f64 + 1.0 {
+                    return Err(native::error(format!("ValueError: {} is out of range for {}", x, stringify!($t))));
+                }
+                Ok(x as $t)
+            }
+        }
+    )*}
+}
+
+integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::BOOL(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::BOOL(x) => Ok(x),
+            x => Err(mismatch("bool", &x))
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::STRING(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::STRING(self.to_string())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::STRING(x) => Ok(x),
+            x => Err(mismatch("string", &x))
+        }
+    }
+}
+
+// nil
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::NIL
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::NIL => Ok(()),
+            x => Err(mismatch("nil", &x))
+        }
+    }
+}
+
+// None is nil
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(x) => x.into_value(),
+            None => Value::NIL
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::NIL => Ok(None),
+            x => T::from_value(x).map(Some)
+        }
+    }
+}
+
+impl IntoValue for Native {
+    fn into_value(self) -> Value {
+        Value::NATIVE(self)
+    }
+}
+
+impl FromValue for Native {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::NATIVE(x) => Ok(x),
+            x => Err(mismatch("native function", &x))
+        }
+    }
+}
+
+impl IntoValue for Userdata {
+    fn into_value(self) -> Value {
+        Value::USERDATA(self)
+    }
+}
+
+impl FromValue for Userdata {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::USERDATA(x) => Ok(x),
+            x => Err(mismatch("userdata", &x))
+        }
+    }
+}
+
+// arguments of a call from the host, a tuple of values convertible with `IntoValue`
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Vec<Value> {
+        self
+    }
+}
+
+macro_rules! args {
+    ($($t:ident),*) => {
+        impl<$($t: IntoValue),*> IntoArgs for ($($t,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Value> {
+                let ($($t,)*) = self;
+                vec![$($t.into_value()),*]
+            }
+        }
+    }
+}
+
+args!();
+args!(A);
+args!(A, B);
+args!(A, B, C);
+args!(A, B, C, D);
+args!(A, B, C, D, E);
+args!(A, B, C, D, E, F);
@@ -0,0 +1,422 @@
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use crate::Error;
+use crate::chunk::Chunk;
+use crate::compiler::Compiler;
+use crate::debugger::{self, DebugState, Resume, StopReason};
+use crate::json::{self, Json};
+use crate::value::Value;
+use crate::vm::{Hook, VM};
+
+// Debug Adapter Protocol server, `oxa dap` talks to the editor over stdin/stdout.
+// oxa programs run on a single thread with a single stack frame.
+
+const THREAD_ID: usize = 1;
+const FRAME_ID: usize = 1;
+const LOCALS_REF: usize = 1;
+const GLOBALS_REF: usize = 2;
+
+struct Transport {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: usize,
+    disconnected: bool,
+}
+
+impl Transport {
+    // next request, requests which can't be parsed are answered and skipped
+    fn read(&mut self) -> Result<Option<Json>, Error> {
+        loop {
+            match json::read_message(&mut self.input)? {
+                Some(Ok(x)) => return Ok(Some(x)),
+                Some(Err(e)) => self.fail(&Json::NULL, &e)?,
+                None => return Ok(None)
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Json) -> Result<(), Error> {
+        self.seq += 1;
+        if let Json::OBJECT(fields) = &mut message {
+            fields.insert("seq".to_string(), self.seq.into());
+        }
+        json::write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> Result<(), Error> {
+        self.send(Json::object(vec![
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").clone()),
+            ("command", request.get("command").clone()),
+            ("success", true.into()),
+            ("body", body),
+        ]))
+    }
+
+    fn fail(&mut self, request: &Json, message: &str) -> Result<(), Error> {
+        self.send(Json::object(vec![
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").clone()),
+            ("command", request.get("command").clone()),
+            ("success", false.into()),
+            ("message", message.into()),
+        ]))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> Result<(), Error> {
+        self.send(Json::object(vec![
+            ("type", "event".into()),
+            ("event", event.into()),
+            ("body", body),
+        ]))
+    }
+}
+
+// forwards output of `print` statements to the editor as `output` events, one event per line
+struct Output {
+    transport: Rc<RefCell<Transport>>,
+    buffer: Vec<u8>,
+}
+
+impl Output {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let output = String::from_utf8_lossy(&self.buffer).to_string();
+        self.buffer.clear();
+        let body = Json::object(vec![("category", "stdout".into()), ("output", output.into())]);
+        match self.transport.borrow_mut().event("output", body) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(io::Error::other("failed to send output event"))
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if buf.contains(&b'\n') {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+// what the request loop should do after a request has been handled
+enum Action {
+    Wait,
+    Run, // `configurationDone`, start the program
+    Resume(Resume),
+    Disconnect,
+}
+
+struct Session {
+    transport: Rc<RefCell<Transport>>,
+    state: DebugState,
+    path: String,
+    chunk: Option<Chunk>, // compiled program, moved into the VM when it starts
+    no_debug: bool,
+    on_entry: bool, // first stop of a program launched with `stopOnEntry`
+}
+
+fn error_message(e: &Error) -> String {
+    match e {
+        Error::COMPILE_ERROR(message, line) | Error::RUNTIME_ERROR(message, line) => format!("[line {}] {}", line, message),
+        e => format!("{:?}", e)
+    }
+}
+
+// moves breakpoint to the first line with code, like the console debugger does
+fn verify_line(chunk: Option<&Chunk>, line: usize) -> Option<usize> {
+    match chunk {
+        Some(chunk) => (line..line + 1000).find(|x| chunk.has_line(*x)),
+        None => Some(line)
+    }
+}
+
+fn variable(name: &str, value: &Value) -> Json {
+    Json::object(vec![
+        ("name", name.into()),
+        ("value", value.repr().into()),
+        ("type", value.type_name().into()),
+        ("variablesReference", 0.into()),
+    ])
+}
+
+impl Session {
+    fn source(&self) -> Json {
+        let name = std::path::Path::new(&self.path).file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+        Json::object(vec![("name", name.into()), ("path", self.path.as_str().into())])
+    }
+
+    fn launch(&mut self, request: &Json) -> Result<(), Error> {
+        let arguments = request.get("arguments");
+        let path = match arguments.get("program").as_str() {
+            Some(x) => x.to_string(),
+            None => return self.transport.borrow_mut().fail(request, "missing `program` launch argument")
+        };
+        let code = match std::fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(_) => return self.transport.borrow_mut().fail(request, &format!("FileNotFound: file `{}` could not be found", path))
+        };
+        let mut compiler = Compiler::new(code);
+        if let Err(e) = compiler.compile() {
+            return self.transport.borrow_mut().fail(request, &error_message(&e));
+        }
+        self.path = path;
+        self.chunk = Some(compiler.chunk);
+        self.no_debug = arguments.get("noDebug").as_bool().unwrap_or(false);
+        self.on_entry = arguments.get("stopOnEntry").as_bool().unwrap_or(false);
+        self.state = DebugState::new(self.on_entry && !self.no_debug);
+        self.transport.borrow_mut().respond(request, Json::NULL)
+    }
+
+    // `lines` holds requested line of every breakpoint and line it was moved to
+    fn set_breakpoints(&mut self, request: &Json, lines: Vec<(usize, Option<usize>)>) -> Result<(), Error> {
+        self.state.breakpoints.clear();
+        let mut breakpoints = vec![];
+        for (line, verified) in lines {
+            match verified {
+                Some(x) => {
+                    self.state.breakpoints.insert(x);
+                    breakpoints.push(Json::object(vec![("verified", true.into()), ("line", x.into())]));
+                },
+                None => breakpoints.push(Json::object(vec![("verified", false.into()), ("line", line.into())]))
+            }
+        }
+        let body = Json::object(vec![("breakpoints", breakpoints.into())]);
+        self.transport.borrow_mut().respond(request, body)
+    }
+
+    fn variables(&mut self, vm: &VM, request: &Json) -> Result<(), Error> {
+        let variables: Vec<Json> = match request.get("arguments").get("variablesReference").as_usize() {
+            Some(LOCALS_REF) => debugger::locals(vm).iter().map(|(name, value)| variable(name, value)).collect(),
+            Some(GLOBALS_REF) => {
+                let mut globals = vm.globals();
+                globals.sort_by(|a, b| a.0.cmp(b.0));
+                globals.iter().map(|(name, value)| variable(name, value)).collect()
+            },
+            _ => vec![]
+        };
+        let body = Json::object(vec![("variables", variables.into())]);
+        self.transport.borrow_mut().respond(request, body)
+    }
+
+    fn set_variable(&mut self, vm: &mut VM, request: &Json) -> Result<(), Error> {
+        let arguments = request.get("arguments");
+        let name = arguments.get("name").as_str().unwrap_or("");
+        let expression = arguments.get("value").as_str().unwrap_or("nil");
+        let is_global = arguments.get("variablesReference").as_usize() == Some(GLOBALS_REF);
+        let result = if is_global {
+            debugger::evaluate(vm, expression).inspect(|value| {
+                vm.set_global(name, value.clone());
+            })
+        } else {
+            debugger::evaluate(vm, &format!("{} = ({})", name, expression))
+        };
+        match result {
+            Ok(value) => {
+                let body = Json::object(vec![("value", value.repr().into()), ("type", value.type_name().into())]);
+                self.transport.borrow_mut().respond(request, body)
+            },
+            Err(e) => self.transport.borrow_mut().fail(request, &error_message(&e))
+        }
+    }
+
+    fn evaluate(&mut self, vm: &mut VM, request: &Json) -> Result<(), Error> {
+        let expression = request.get("arguments").get("expression").as_str().unwrap_or("");
+        match debugger::evaluate(vm, expression) {
+            Ok(value) => {
+                let body = Json::object(vec![
+                    ("result", value.repr().into()),
+                    ("type", value.type_name().into()),
+                    ("variablesReference", 0.into()),
+                ]);
+                self.transport.borrow_mut().respond(request, body)
+            },
+            Err(e) => self.transport.borrow_mut().fail(request, &error_message(&e))
+        }
+    }
+
+    // handles single request, `vm` is only available while the program is paused
+    fn handle(&mut self, vm: Option<&mut VM>, request: &Json) -> Result<Action, Error> {
+        let command = request.get("command").as_str().unwrap_or("");
+        match (command, vm) {
+            ("initialize", _) => {
+                let body = Json::object(vec![
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsEvaluateForHovers", true.into()),
+                    ("supportsSetVariable", true.into()),
+                ]);
+                let mut transport = self.transport.borrow_mut();
+                transport.respond(request, body)?;
+                transport.event("initialized", Json::NULL)?;
+            },
+            ("launch", None) => self.launch(request)?,
+            ("setBreakpoints", vm) => {
+                let chunk = match vm {
+                    Some(vm) => Some(vm.chunk()),
+                    None => self.chunk.as_ref()
+                };
+                let lines: Vec<(usize, Option<usize>)> = request.get("arguments").get("breakpoints").as_array().iter()
+                    .map(|x| x.get("line").as_usize().unwrap_or(0))
+                    .map(|line| (line, verify_line(chunk, line)))
+                    .collect();
+                self.set_breakpoints(request, lines)?;
+            },
+            ("configurationDone", None) => {
+                self.transport.borrow_mut().respond(request, Json::NULL)?;
+                return Ok(Action::Run);
+            },
+            ("threads", _) => {
+                let threads = vec![Json::object(vec![("id", THREAD_ID.into()), ("name", "main".into())])];
+                self.transport.borrow_mut().respond(request, Json::object(vec![("threads", threads.into())]))?;
+            },
+            ("stackTrace", Some(vm)) => {
+                let frame = Json::object(vec![
+                    ("id", FRAME_ID.into()),
+                    ("name", "<script>".into()),
+                    ("source", self.source()),
+                    ("line", vm.chunk().get_line(vm.ip()).into()),
+                    ("column", 1.into()),
+                ]);
+                let body = Json::object(vec![("stackFrames", vec![frame].into()), ("totalFrames", 1.into())]);
+                self.transport.borrow_mut().respond(request, body)?;
+            },
+            ("scopes", Some(_)) => {
+                let scopes = vec![
+                    Json::object(vec![("name", "Locals".into()), ("variablesReference", LOCALS_REF.into()), ("expensive", false.into())]),
+                    Json::object(vec![("name", "Globals".into()), ("variablesReference", GLOBALS_REF.into()), ("expensive", false.into())]),
+                ];
+                self.transport.borrow_mut().respond(request, Json::object(vec![("scopes", scopes.into())]))?;
+            },
+            ("variables", Some(vm)) => self.variables(vm, request)?,
+            ("setVariable", Some(vm)) => self.set_variable(vm, request)?,
+            ("evaluate", Some(vm)) => self.evaluate(vm, request)?,
+            ("continue", Some(_)) => {
+                let body = Json::object(vec![("allThreadsContinued", true.into())]);
+                self.transport.borrow_mut().respond(request, body)?;
+                return Ok(Action::Resume(Resume::Continue));
+            },
+            ("next", Some(_)) | ("stepIn", Some(_)) => {
+                self.transport.borrow_mut().respond(request, Json::NULL)?;
+                return Ok(Action::Resume(Resume::Step));
+            },
+            ("stepOut", Some(_)) => {
+                self.transport.borrow_mut().respond(request, Json::NULL)?;
+                return Ok(Action::Resume(Resume::StepOut(0)));
+            },
+            ("disconnect", _) | ("terminate", _) => {
+                let mut transport = self.transport.borrow_mut();
+                transport.respond(request, Json::NULL)?;
+                transport.disconnected = true;
+                return Ok(Action::Disconnect);
+            },
+            (command, Some(_)) => self.transport.borrow_mut().fail(request, &format!("unsupported request `{}`", command))?,
+            (command, None) => self.transport.borrow_mut().fail(request, &format!("request `{}` requires a paused program", command))?,
+        }
+        Ok(Action::Wait)
+    }
+}
+
+impl Hook for Session {
+    fn before_instruction(&mut self, vm: &mut VM) -> Result<(), Error> {
+        let reason = match self.state.check(vm) {
+            _ if self.no_debug => return Ok(()),
+            Some(StopReason::Breakpoint(_)) => "breakpoint",
+            Some(StopReason::Step) if self.on_entry => "entry",
+            Some(StopReason::Step) => "step",
+            None => return Ok(())
+        };
+        self.on_entry = false;
+        let body = Json::object(vec![
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ]);
+        self.transport.borrow_mut().event("stopped", body)?;
+
+        loop {
+            let request = match self.transport.borrow_mut().read()? {
+                Some(x) => x,
+                None => return Err(Error::IO_ERROR)
+            };
+            match self.handle(Some(vm), &request)? {
+                Action::Resume(resume) => {
+                    self.state.resume(vm, resume);
+                    return Ok(());
+                },
+                // stop the VM, `serve` checks the disconnected flag to tell this apart from program errors
+                Action::Disconnect => return Err(Error::SIGNAL),
+                _ => {}
+            }
+        }
+    }
+}
+
+// runs debug session until the client disconnects
+pub fn serve(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Result<(), Error> {
+    let transport = Rc::new(RefCell::new(Transport {input, output, seq: 0, disconnected: false}));
+    let mut session = Session {
+        transport: transport.clone(),
+        state: DebugState::new(false),
+        path: String::new(),
+        chunk: None,
+        no_debug: false,
+        on_entry: false
+    };
+
+    // configuration: initialize, launch, setBreakpoints until configurationDone
+    loop {
+        let request = match transport.borrow_mut().read()? {
+            Some(x) => x,
+            None => return Ok(())
+        };
+        match session.handle(None, &request)? {
+            Action::Run if session.chunk.is_some() => break,
+            Action::Disconnect => return Ok(()),
+            _ => {}
+        }
+    }
+
+    let mut vm = VM::default();
+    vm.set_chunk(session.chunk.take().unwrap());
+    vm.set_output(Box::new(Output {transport: transport.clone(), buffer: vec![]}));
+    vm.set_hook(Box::new(session));
+    let result = vm.execute(false);
+
+    if transport.borrow().disconnected {
+        return Ok(());
+    }
+    let mut t = transport.borrow_mut();
+    let exit_code = match result {
+        Ok(_) => 0,
+        Err(e) => {
+            let body = Json::object(vec![("category", "stderr".into()), ("output", format!("{}\n", error_message(&e)).into())]);
+            t.event("output", body)?;
+            1
+        }
+    };
+    t.event("exited", Json::object(vec![("exitCode", exit_code.into())]))?;
+    t.event("terminated", Json::NULL)?;
+
+    // program has finished, wait for the client to disconnect
+    loop {
+        let request = match t.read()? {
+            Some(x) => x,
+            None => return Ok(())
+        };
+        match request.get("command").as_str() {
+            Some("disconnect") | Some("terminate") => return t.respond(&request, Json::NULL),
+            Some("threads") => t.respond(&request, Json::object(vec![("threads", Json::ARRAY(vec![]))]))?,
+            _ => t.fail(&request, "program has terminated")?
+        }
+    }
+}
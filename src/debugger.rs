@@ -0,0 +1,261 @@
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+use crate::Error;
+use crate::compiler::Compiler;
+use crate::value::Value;
+use crate::vm::{Hook, VM};
+
+// how execution continues after the debugger was paused
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resume {
+    Continue,
+    Step,          // pause at the next line, oxa has no calls yet so step into and step over are the same
+    StepOut(usize), // pause once block statement at given depth has been left
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    Breakpoint(usize),
+    Step,
+}
+
+// front-end independent part of the debugger: breakpoints and stepping
+pub struct DebugState {
+    pub breakpoints: BTreeSet<usize>,
+    resume: Resume,
+    last: Option<(usize, usize)>, // (ip, line) of the last instruction the debugger has seen
+}
+
+impl DebugState {
+    pub fn new(stop_on_entry: bool) -> Self {
+        let resume = if stop_on_entry { Resume::Step } else { Resume::Continue };
+        DebugState {breakpoints: BTreeSet::new(), resume, last: None}
+    }
+
+    pub fn resume(&mut self, vm: &VM, resume: Resume) {
+        self.resume = match resume {
+            Resume::StepOut(_) => Resume::StepOut(vm.chunk().scope_depth(vm.ip())),
+            x => x
+        };
+    }
+
+    // decides whether the VM should pause before executing the instruction at `vm.ip()`
+    // the debugger only stops at the first instruction of a line, or when a line is re-entered by a jump
+    pub fn check(&mut self, vm: &VM) -> Option<StopReason> {
+        let ip = vm.ip();
+        let line = vm.chunk().get_line(ip);
+        let new_line = match self.last {
+            Some((last_ip, last_line)) => line != last_line || ip <= last_ip,
+            None => true
+        };
+        self.last = Some((ip, line));
+        if !new_line {
+            return None;
+        }
+        if self.breakpoints.contains(&line) {
+            return Some(StopReason::Breakpoint(line));
+        }
+        match self.resume {
+            Resume::Step => Some(StopReason::Step),
+            Resume::StepOut(depth) if vm.chunk().scope_depth(ip) < depth => Some(StopReason::Step),
+            _ => None
+        }
+    }
+}
+
+// local variables alive at the paused instruction, innermost declaration of a name wins
+pub fn locals(vm: &VM) -> Vec<(String, Value)> {
+    let mut locals: Vec<(String, Value)> = vec![];
+    for local in vm.chunk().live_locals(vm.ip()) {
+        let value = match vm.stack().get(local.slot) {
+            Some(x) => x.clone(),
+            None => continue
+        };
+        locals.retain(|(name, _)| *name != local.name);
+        locals.push((local.name.clone(), value));
+    }
+    locals
+}
+
+// evaluates expression `source` in the paused context, locals of the program can be accessed by name
+pub fn evaluate(vm: &mut VM, source: &str) -> Result<Value, Error> {
+    let mut compiler = Compiler::new(source.to_string());
+    compiler.compile_expression(&vm.chunk().live_locals(vm.ip()))?;
+    vm.evaluate(compiler.chunk)
+}
+
+// interactive command line debugger used by `oxa debug`. `quit` and the end of the input stop the
+// program with `Error::INTERRUPTED`, the host decides what happens next
+pub struct Console<R: BufRead, W: Write> {
+    state: DebugState,
+    source: Vec<String>,
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub fn new(source: &str, input: R, output: W) -> Self {
+        let source = source.lines().map(|x| x.to_string()).collect();
+        Console {state: DebugState::new(true), source, input, output}
+    }
+
+    fn show_line(&mut self, line: usize) -> Result<(), Error> {
+        let text = self.source.get(line.wrapping_sub(1)).map(|x| x.as_str()).unwrap_or("");
+        self.write(&format!("{:>4} | {}", line, text))
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), Error> {
+        match writeln!(self.output, "{}", text) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::IO_ERROR)
+        }
+    }
+
+    fn read_command(&mut self) -> Result<Option<String>, Error> {
+        if write!(self.output, "(oxa) ").and_then(|_| self.output.flush()).is_err() {
+            return Err(Error::IO_ERROR);
+        }
+        let mut command = String::new();
+        match self.input.read_line(&mut command) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(command.trim().to_string())),
+            Err(_) => Err(Error::IO_ERROR)
+        }
+    }
+
+    fn set_breakpoint(&mut self, vm: &VM, arg: &str) -> Result<(), Error> {
+        let line: usize = match arg.parse() {
+            Ok(x) => x,
+            Err(_) => return self.write("usage: break <line>")
+        };
+        // breakpoints on lines without code are moved to the next line which has instructions
+        match (line..=self.source.len().max(line)).find(|x| vm.chunk().has_line(*x)) {
+            Some(x) => {
+                self.state.breakpoints.insert(x);
+                self.write(&format!("breakpoint set at line {}", x))
+            },
+            None => self.write(&format!("no code at or after line {}", line))
+        }
+    }
+
+    fn print_locals(&mut self, vm: &VM) -> Result<(), Error> {
+        let locals = locals(vm);
+        if locals.is_empty() {
+            return self.write("no locals");
+        }
+        for (name, value) in locals {
+            self.write(&format!("{} = {}", name, value.repr()))?;
+        }
+        Ok(())
+    }
+
+    fn print_globals(&mut self, vm: &VM) -> Result<(), Error> {
+        let mut globals = vm.globals();
+        globals.sort_by(|a, b| a.0.cmp(b.0));
+        let lines: Vec<String> = globals.iter().map(|(name, value)| format!("{} = {}", name, value.repr())).collect();
+        if lines.is_empty() {
+            return self.write("no globals");
+        }
+        for line in lines {
+            self.write(&line)?;
+        }
+        Ok(())
+    }
+
+    // `set <global> = <expr>`
+    fn set_global(&mut self, vm: &mut VM, arg: &str) -> Result<(), Error> {
+        let (name, expr) = match arg.split_once('=') {
+            Some((name, expr)) => (name.trim(), expr.trim()),
+            None => return self.write("usage: set <global> = <expression>")
+        };
+        if vm.global(name).is_none() {
+            return self.write(&format!("NameError: undefined global `{}`", name));
+        }
+        match evaluate(vm, expr) {
+            Ok(value) => {
+                vm.set_global(name, value.clone());
+                self.write(&format!("{} = {}", name, value.repr()))
+            },
+            Err(e) => self.write(&format!("{:?}", e))
+        }
+    }
+
+    fn help(&mut self) -> Result<(), Error> {
+        self.write("break <line>     set breakpoint            delete <line>  remove breakpoint\n\
+                    step | next      execute until next line   finish         run until current block is left\n\
+                    continue         run until breakpoint      list           show source around current line\n\
+                    stack            print value stack         locals         print local variables\n\
+                    globals          print global variables    set <g> = <e>  assign expression to global\n\
+                    print <expr>     evaluate expression       quit           stop program")
+    }
+}
+
+impl<R: BufRead, W: Write> Hook for Console<R, W> {
+    fn before_instruction(&mut self, vm: &mut VM) -> Result<(), Error> {
+        let line = match self.state.check(vm) {
+            Some(StopReason::Breakpoint(line)) => {
+                self.write(&format!("breakpoint at line {}", line))?;
+                line
+            },
+            Some(StopReason::Step) => vm.chunk().get_line(vm.ip()),
+            None => return Ok(())
+        };
+        self.show_line(line)?;
+
+        loop {
+            let command = match self.read_command()? {
+                Some(x) => x,
+                None => return Err(Error::INTERRUPTED(line)) // input closed
+            };
+            let (command, arg) = match command.split_once(' ') {
+                Some((command, arg)) => (command, arg.trim()),
+                None => (command.as_str(), "")
+            };
+            match command {
+                "" => {},
+                "b" | "break" => self.set_breakpoint(vm, arg)?,
+                "d" | "delete" => {
+                    if let Ok(line) = arg.parse() {
+                        self.state.breakpoints.remove(&line);
+                    }
+                },
+                "s" | "step" | "n" | "next" => {
+                    self.state.resume(vm, Resume::Step);
+                    return Ok(());
+                },
+                "f" | "finish" | "out" => {
+                    self.state.resume(vm, Resume::StepOut(0));
+                    return Ok(());
+                },
+                "c" | "continue" => {
+                    self.state.resume(vm, Resume::Continue);
+                    return Ok(());
+                },
+                "l" | "list" => {
+                    for i in line.saturating_sub(3).max(1)..=line + 3 {
+                        if i <= self.source.len() {
+                            self.show_line(i)?;
+                        }
+                    }
+                },
+                "stack" => {
+                    let stack: Vec<String> = vm.stack().iter().map(|x| x.repr()).collect();
+                    self.write(&format!("[{}]", stack.join(", ")))?;
+                },
+                "locals" => self.print_locals(vm)?,
+                "globals" => self.print_globals(vm)?,
+                "set" => self.set_global(vm, arg)?,
+                "p" | "print" => {
+                    match evaluate(vm, arg) {
+                        Ok(value) => self.write(&value.repr())?,
+                        Err(e) => self.write(&format!("{:?}", e))?
+                    }
+                },
+                "h" | "help" => self.help()?,
+                "q" | "quit" => return Err(Error::INTERRUPTED(line)),
+                _ => self.write(&format!("unknown command `{}`, type `help` for list of commands", command))?
+            }
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use crate::Error;
+use crate::parser::Precendence;
+use crate::scanner::Scanner;
+use crate::token::{Token, TokenType};
+
+// Source formatter used by `oxa fmt`. Source is parsed into a concrete syntax tree which keeps
+// comments, parentheses and the original spelling of literals, and is then printed in canonical style.
+
+pub const DEFAULT_WIDTH: usize = 100;
+const INDENT: &str = "    ";
+
+#[derive(Debug)]
+enum Expr {
+    Atom(String), // literal or identifier, spelled as in source
+    Group(Box<Expr>),
+    Unary(String, Box<Expr>),
+    Binary(Box<Expr>, TokenType, String, Box<Expr>),
+    Assign(String, Box<Expr>), // target is a name or a property
+    Call(Box<Expr>, Vec<Expr>),
+    Property(Box<Expr>, String),
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Var(String, Option<Expr>),
+    Print(Expr),
+    Expression(Expr),
+    Block(Block),
+    If(Expr, Block, Option<Block>),
+    While(Expr, Block),
+    Break,
+    Continue,
+}
+
+#[derive(Debug)]
+enum Item {
+    Comment(String),
+    Stmt(Stmt, Option<String>), // statement and comment following it on the same line
+}
+
+#[derive(Debug, Default)]
+struct Block {
+    items: Vec<(bool, Item)>, // items preceded by a blank line are marked with true
+}
+
+// number of lines a token spans, strings and block comments may contain newlines
+fn extra_lines(token: &Token) -> usize {
+    token.lexeme.matches('\n').count()
+}
+
+// line on which token starts, the scanner reports strings on the line they end on
+fn start_line(token: &Token) -> usize {
+    match token.t {
+        TokenType::STRING => token.line - extra_lines(token),
+        _ => token.line
+    }
+}
+
+fn end_line(token: &Token) -> usize {
+    match token.t {
+        TokenType::COMMENT => token.line + extra_lines(token),
+        _ => token.line
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    current: usize,
+    source: Vec<Vec<char>>, // source lines, used to print number literals as written
+    comments: Vec<Token>, // comments found inside of the statement being parsed
+    last_line: usize, // line of the last consumed token
+}
+
+impl Parser {
+    fn new(source: &str) -> Result<Self, Error> {
+        let mut scanner = Scanner::with_comments(source.to_string());
+        let mut tokens = vec![];
+        loop {
+            let token = scanner.advance()?;
+            let eof = token.t == TokenType::EOF;
+            tokens.push(token);
+            if eof {
+                break;
+            }
+        }
+        let source = source.split('\n').map(|x| x.chars().collect()).collect();
+        Ok(Parser {tokens, current: 0, source, comments: vec![], last_line: 1})
+    }
+
+    fn raw(&self) -> &Token {
+        &self.tokens[self.current]
+    }
+
+    // next non-comment token, comments on the way are set aside
+    fn peek(&mut self) -> &Token {
+        while self.tokens[self.current].t == TokenType::COMMENT {
+            self.comments.push(self.tokens[self.current].clone());
+            self.current += 1;
+        }
+        &self.tokens[self.current]
+    }
+
+    // checks type of next non-comment token without setting comments aside
+    fn lookahead(&self, t: TokenType) -> bool {
+        self.tokens[self.current..].iter().find(|x| x.t != TokenType::COMMENT).map(|x| x.t == t).unwrap_or(false)
+    }
+
+    fn check(&mut self, t: TokenType) -> bool {
+        self.peek().t == t
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token.t != TokenType::EOF {
+            self.current += 1;
+        }
+        self.last_line = end_line(&token);
+        token
+    }
+
+    fn consume(&mut self, t: TokenType, message: &str) -> Result<Token, Error> {
+        if self.check(t) {
+            Ok(self.advance())
+        } else {
+            let token = self.peek().clone();
+            Err(Error::COMPILE_ERROR(format!("{} got `{}`", message, token), token.line))
+        }
+    }
+
+    // spelling of the token in source
+    fn text(&self, token: &Token) -> String {
+        match token.t {
+            TokenType::NUMBER => {
+                let line = &self.source[token.line - 1];
+                line[token.col..(token.col + token.len).min(line.len())].iter().collect()
+            },
+            TokenType::STRING => format!("\"{}\"", token.lexeme),
+            _ => token.lexeme.clone()
+        }
+    }
+
+    // items until `}` or end of file
+    fn items(&mut self, block: &mut Block) -> Result<(), Error> {
+        loop {
+            let blank = start_line(self.raw()) > self.last_line + 1 && !block.items.is_empty();
+            if self.raw().t == TokenType::COMMENT {
+                let comment = self.raw().clone();
+                self.current += 1;
+                self.last_line = end_line(&comment);
+                block.items.push((blank, Item::Comment(comment.lexeme)));
+                continue;
+            }
+            if self.raw().t == TokenType::RIGHT_BRACE || self.raw().t == TokenType::EOF {
+                return Ok(());
+            }
+            let stmt = self.statement()?;
+            // comments which were inside the statement are moved in front of it
+            let mut first = true;
+            for comment in std::mem::take(&mut self.comments) {
+                block.items.push((blank && first, Item::Comment(comment.lexeme)));
+                first = false;
+            }
+            let trailing = if self.raw().t == TokenType::COMMENT && start_line(self.raw()) == self.last_line && extra_lines(self.raw()) == 0 {
+                let comment = self.raw().lexeme.clone();
+                self.current += 1;
+                Some(comment)
+            } else {
+                None
+            };
+            block.items.push((blank && first, Item::Stmt(stmt, trailing)));
+        }
+    }
+
+    fn block(&mut self) -> Result<Block, Error> {
+        self.consume(TokenType::LEFT_BRACE, "Expect `{` at the start of block statement")?;
+        let mut block = Block::default();
+        for comment in std::mem::take(&mut self.comments) {
+            block.items.push((false, Item::Comment(comment.lexeme)));
+        }
+        self.items(&mut block)?;
+        self.consume(TokenType::RIGHT_BRACE, "Expect `}` after block statement")?;
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, Error> {
+        match self.peek().t {
+            TokenType::VAR => {
+                self.advance();
+                let name = self.consume(TokenType::IDENTIFIER, "Expect identifier after `var`")?.lexeme;
+                let value = if self.check(TokenType::EQUAL) {
+                    self.advance();
+                    Some(self.expression()?)
+                } else {
+                    None
+                };
+                self.consume(TokenType::SEMICOLON, "Expect `;` after statement")?;
+                Ok(Stmt::Var(name, value))
+            },
+            TokenType::PRINT => {
+                self.advance();
+                let value = self.expression()?;
+                self.consume(TokenType::SEMICOLON, "Expect `;` after statement")?;
+                Ok(Stmt::Print(value))
+            },
+            TokenType::LEFT_BRACE => Ok(Stmt::Block(self.block()?)),
+            TokenType::IF => {
+                self.advance();
+                let condition = self.expression()?;
+                let mut then = self.block()?;
+                let otherwise = if self.lookahead(TokenType::ELSE) {
+                    // comments between `}` and `else` are kept at the end of the `then` block
+                    for comment in std::mem::take(&mut self.comments) {
+                        then.items.push((false, Item::Comment(comment.lexeme)));
+                    }
+                    self.advance();
+                    Some(self.block()?)
+                } else {
+                    None
+                };
+                Ok(Stmt::If(condition, then, otherwise))
+            },
+            TokenType::WHILE => {
+                self.advance();
+                let condition = self.expression()?;
+                Ok(Stmt::While(condition, self.block()?))
+            },
+            TokenType::BREAK | TokenType::CONTINUE => {
+                let token = self.advance();
+                self.consume(TokenType::SEMICOLON, "Expect `;` after statement")?;
+                Ok(if token.t == TokenType::BREAK { Stmt::Break } else { Stmt::Continue })
+            },
+            _ => {
+                let value = self.expression()?;
+                self.consume(TokenType::SEMICOLON, "Expect `;` after statement")?;
+                Ok(Stmt::Expression(value))
+            }
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, Error> {
+        self.precendence(Precendence::ASSIGNMENT)
+    }
+
+    // precedence climbing, binary operators are left associative and assignment is right associative
+    fn precendence(&mut self, prec: Precendence) -> Result<Expr, Error> {
+        let token = self.advance();
+        let mut expr = match token.t {
+            TokenType::LEFT_PAREN => {
+                let inner = self.expression()?;
+                self.consume(TokenType::RIGHT_PAREN, "Expect `)` after expression")?;
+                Expr::Group(Box::new(inner))
+            },
+            TokenType::MINUS | TokenType::BANG => {
+                let operand = self.precendence(Precendence::UNARY)?;
+                Expr::Unary(token.lexeme, Box::new(operand))
+            },
+            TokenType::IDENTIFIER if prec <= Precendence::ASSIGNMENT && self.check(TokenType::EQUAL) => {
+                self.advance();
+                Expr::Assign(token.lexeme, Box::new(self.expression()?))
+            },
+            TokenType::NUMBER | TokenType::STRING | TokenType::IDENTIFIER | TokenType::TRUE | TokenType::FALSE | TokenType::NIL => {
+                Expr::Atom(self.text(&token))
+            },
+            _ => return Err(Error::COMPILE_ERROR(format!("Expected expression got `{}`", token), token.line))
+        };
+        loop {
+            let next = Precendence::of(&self.peek().t);
+            if prec >= next {
+                return Ok(expr);
+            }
+            let operator = self.advance();
+            if operator.t == TokenType::LEFT_PAREN {
+                let mut args = vec![];
+                if !self.check(TokenType::RIGHT_PAREN) {
+                    args.push(self.expression()?);
+                    while self.check(TokenType::COMMA) {
+                        self.advance();
+                        args.push(self.expression()?);
+                    }
+                }
+                self.consume(TokenType::RIGHT_PAREN, "Expect `)` after arguments")?;
+                expr = Expr::Call(Box::new(expr), args);
+                continue;
+            }
+            if operator.t == TokenType::DOT {
+                let name = self.consume(TokenType::IDENTIFIER, "Expect property name after `.`")?;
+                expr = Expr::Property(Box::new(expr), name.lexeme);
+                if prec <= Precendence::ASSIGNMENT && self.check(TokenType::EQUAL) {
+                    self.advance();
+                    expr = Expr::Assign(flat(&expr, Precendence::NONE), Box::new(self.expression()?));
+                }
+                continue;
+            }
+            let right = self.precendence(next)?;
+            expr = Expr::Binary(Box::new(expr), operator.t, operator.lexeme, Box::new(right));
+        }
+    }
+}
+
+struct Printer {
+    out: String,
+    width: usize,
+    indent: usize,
+}
+
+// precedence of the operator at the root of an expression
+fn root_precendence(expr: &Expr) -> Precendence {
+    match expr {
+        Expr::Binary(_, t, _, _) => Precendence::of(t),
+        Expr::Assign(_, _) => Precendence::ASSIGNMENT,
+        _ => Precendence::PRIMARY
+    }
+}
+
+// prints expression on one line, operators bind tighter than their parent are printed
+// without surrounding spaces when both are arithmetic: `a*b + c`
+fn flat(expr: &Expr, parent: Precendence) -> String {
+    match expr {
+        Expr::Atom(x) => x.clone(),
+        Expr::Group(x) => format!("({})", flat(x, Precendence::NONE)),
+        Expr::Unary(op, x) => format!("{}{}", op, flat(x, Precendence::UNARY)),
+        Expr::Assign(name, x) => format!("{} = {}", name, flat(x, Precendence::ASSIGNMENT)),
+        Expr::Call(callee, args) => {
+            let args: Vec<String> = args.iter().map(|x| flat(x, Precendence::NONE)).collect();
+            format!("{}({})", flat(callee, Precendence::CALL), args.join(", "))
+        },
+        Expr::Property(object, name) => format!("{}.{}", flat(object, Precendence::CALL), name),
+        Expr::Binary(left, t, op, right) => {
+            let prec = Precendence::of(t);
+            let compact = prec == Precendence::FACTOR && parent == Precendence::TERM;
+            let left = flat(left, prec);
+            let right = flat(right, prec);
+            if compact {
+                format!("{}{}{}", left, op, right)
+            } else {
+                format!("{} {} {}", left, op, right)
+            }
+        }
+    }
+}
+
+impl Printer {
+    fn line(&mut self, text: &str) {
+        if text.is_empty() {
+            self.out.push('\n');
+            return;
+        }
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    // expression starting at column `col`, followed by `suffix` characters on the same line;
+    // too long expressions are broken before the operators of the loosest binding chain
+    fn expr(&self, expr: &Expr, col: usize, suffix: usize, indent: usize, parent: Precendence) -> String {
+        let text = flat(expr, parent);
+        if col + text.chars().count() + suffix <= self.width {
+            return text;
+        }
+        match expr {
+            Expr::Group(inner) => format!("({})", self.expr(inner, col + 1, suffix + 1, indent, Precendence::NONE)),
+            Expr::Assign(name, value) => {
+                let prefix = format!("{} = ", name);
+                let value = self.expr(value, col + prefix.len(), suffix, indent, Precendence::ASSIGNMENT);
+                format!("{}{}", prefix, value)
+            },
+            Expr::Binary(_, t, _, _) => {
+                // collect left associative chain of operators with the same precedence
+                let prec = Precendence::of(t);
+                let mut operands = vec![];
+                let mut node = expr;
+                while let Expr::Binary(left, t, op, right) = node {
+                    if Precendence::of(t) != prec {
+                        break;
+                    }
+                    operands.push((op.as_str(), right.as_ref()));
+                    node = left;
+                }
+                operands.reverse();
+                let continuation = INDENT.repeat(indent + 1);
+                let mut text = self.expr(node, col, 0, indent, prec);
+                for (op, operand) in operands {
+                    let start = continuation.len() + op.len() + 1;
+                    let operand = match root_precendence(operand) {
+                        p if p > prec => self.expr(operand, start, suffix, indent + 1, prec),
+                        _ => flat(operand, prec)
+                    };
+                    text.push_str(&format!("\n{}{} {}", continuation, op, operand));
+                }
+                text
+            },
+            _ => text
+        }
+    }
+
+    // writes `prefix expr suffix` as one or more lines
+    fn expr_line(&mut self, prefix: &str, expr: &Expr, suffix: &str, trailing: &Option<String>) {
+        let col = self.indent * INDENT.len() + prefix.len();
+        let mut suffix = suffix.to_string();
+        if let Some(comment) = trailing {
+            suffix = format!("{} {}", suffix, comment);
+        }
+        let text = self.expr(expr, col, suffix.len(), self.indent, Precendence::NONE);
+        self.line(&format!("{}{}{}", prefix, text, suffix));
+    }
+
+    fn block(&mut self, prefix: &str, block: &Block, suffix: &str) {
+        if block.items.is_empty() {
+            self.line(&format!("{}{{}}{}", prefix, suffix));
+            return;
+        }
+        self.line(&format!("{}{{", prefix));
+        self.body(block);
+        self.line(&format!("}}{}", suffix));
+    }
+
+    fn body(&mut self, block: &Block) {
+        self.indent += 1;
+        self.items(block);
+        self.indent -= 1;
+    }
+
+    fn items(&mut self, block: &Block) {
+        for (blank, item) in block.items.iter() {
+            if *blank {
+                self.line("");
+            }
+            match item {
+                Item::Comment(comment) => self.line(comment),
+                Item::Stmt(stmt, trailing) => self.stmt(stmt, trailing),
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, trailing: &Option<String>) {
+        let comment = match trailing {
+            Some(x) => format!(" {}", x),
+            None => String::new()
+        };
+        match stmt {
+            Stmt::Var(name, None) => self.line(&format!("var {};{}", name, comment)),
+            Stmt::Var(name, Some(value)) => self.expr_line(&format!("var {} = ", name), value, ";", trailing),
+            Stmt::Print(value) => self.expr_line("print ", value, ";", trailing),
+            Stmt::Expression(value) => self.expr_line("", value, ";", trailing),
+            Stmt::Block(block) => self.block("", block, &comment),
+            Stmt::If(condition, then, otherwise) => {
+                let condition = self.expr(condition, self.indent * INDENT.len() + 3, 2, self.indent, Precendence::NONE);
+                match otherwise {
+                    Some(otherwise) => {
+                        self.line(&format!("if {} {{", condition));
+                        self.body(then);
+                        if otherwise.items.is_empty() {
+                            self.line(&format!("}} else {{}}{}", comment));
+                        } else {
+                            self.line("} else {");
+                            self.body(otherwise);
+                            self.line(&format!("}}{}", comment));
+                        }
+                    },
+                    None => self.block(&format!("if {} ", condition), then, &comment)
+                }
+            },
+            Stmt::While(condition, body) => {
+                let condition = self.expr(condition, self.indent * INDENT.len() + 6, 2, self.indent, Precendence::NONE);
+                self.block(&format!("while {} ", condition), body, &comment);
+            },
+            Stmt::Break => self.line(&format!("break;{}", comment)),
+            Stmt::Continue => self.line(&format!("continue;{}", comment)),
+        }
+    }
+}
+
+// formats oxa source, lines are wrapped at `width` characters where possible
+pub fn format(source: &str, width: usize) -> Result<String, Error> {
+    let mut parser = Parser::new(source)?;
+    let mut program = Block::default();
+    parser.items(&mut program)?;
+    if parser.raw().t != TokenType::EOF {
+        let token = parser.raw().clone();
+        return Err(Error::COMPILE_ERROR(format!("Unexpected `{}`", token), token.line));
+    }
+    let mut printer = Printer {out: String::new(), width, indent: 0};
+    printer.items(&program);
+    Ok(printer.out)
+}
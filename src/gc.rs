@@ -0,0 +1,156 @@
+use std::fmt;
+
+use crate::native::Native;
+use crate::userdata::Userdata;
+use crate::nanbox::NanBox;
+use crate::value::Value;
+
+// mark and sweep garbage collector for the objects `NanBox` points to
+// the VMs collect between instructions, when every live value is reachable from their stack or
+// registers, globals and constants. a collection starts once the heap has grown past a threshold,
+// which then grows with the live heap, or with `--gc-stress` after every allocation
+
+const INITIAL_THRESHOLD: usize = 1024 * 1024;
+const GROWTH_FACTOR: usize = 2;
+
+// values living on the heap
+pub enum Obj {
+    STRING(String),
+    NATIVE(Native),
+    USERDATA(Userdata), // the host object itself is shared with the host and not counted
+}
+
+pub struct Object {
+    marked: bool,
+    pub obj: Obj,
+}
+
+impl Object {
+    // bytes owned by the object, used to decide when to collect
+    fn size(&self) -> usize {
+        std::mem::size_of::<Object>() + match &self.obj {
+            Obj::STRING(x) => x.capacity(),
+            Obj::NATIVE(x) => x.name.capacity(),
+            Obj::USERDATA(_) => 0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct GcStats {
+    pub allocations: usize,
+    pub collections: usize,
+    pub freed: usize, // objects
+    pub bytes: usize, // currently allocated
+    pub peak_bytes: usize,
+}
+
+impl fmt::Display for GcStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "gc allocations: {}", self.allocations)?;
+        writeln!(f, "gc collections: {}", self.collections)?;
+        writeln!(f, "gc objects freed: {}", self.freed)?;
+        writeln!(f, "gc heap bytes: {}", self.bytes)?;
+        write!(f, "gc peak heap bytes: {}", self.peak_bytes)
+    }
+}
+
+pub struct Heap {
+    objects: Vec<*mut Object>, // owned, allocated with `Box::into_raw`
+    threshold: usize, // smallest heap size in bytes which triggers a collection
+    next_gc: usize, // heap size in bytes which triggers the next collection
+    stress: bool,
+    pending: bool, // allocated since the last collection
+    stats: GcStats,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap {objects: vec![], threshold: INITIAL_THRESHOLD, next_gc: INITIAL_THRESHOLD, stress: false, pending: false, stats: GcStats::default()}
+    }
+}
+
+impl Heap {
+    // collect after every allocation, for finding values the VM forgot to root
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    // heap size in bytes which triggers the first collection, later ones wait for the heap to double
+    pub fn set_threshold(&mut self, bytes: usize) {
+        self.threshold = bytes;
+        self.next_gc = bytes;
+    }
+
+    pub fn stats(&self) -> &GcStats {
+        &self.stats
+    }
+
+    pub(crate) fn string(&mut self, x: String) -> NanBox {
+        self.alloc(Obj::STRING(x))
+    }
+
+    fn alloc(&mut self, obj: Obj) -> NanBox {
+        let object = Box::new(Object {marked: false, obj});
+        self.stats.allocations += 1;
+        self.stats.bytes += object.size();
+        self.stats.peak_bytes = self.stats.peak_bytes.max(self.stats.bytes);
+        self.pending = true;
+        let ptr = Box::into_raw(object);
+        self.objects.push(ptr);
+        NanBox::object(ptr)
+    }
+
+    // moves `value` into a word, strings are allocated on the heap
+    pub(crate) fn manage(&mut self, value: Value) -> NanBox {
+        match value {
+            Value::FLOAT(x) => NanBox::float(x),
+            Value::BOOL(x) => NanBox::bool(x),
+            Value::STRING(x) => self.string(x),
+            Value::NATIVE(x) => self.alloc(Obj::NATIVE(x)),
+            Value::USERDATA(x) => self.alloc(Obj::USERDATA(x)),
+            Value::NIL => NanBox::NIL,
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.pending && (self.stress || self.stats.bytes > self.next_gc)
+    }
+
+    // frees every object which isn't reachable from `roots`
+    pub(crate) fn collect<'a>(&mut self, roots: impl Iterator<Item = &'a NanBox>) {
+        // objects don't reference other objects yet, marking the roots marks everything reachable
+        for root in roots {
+            if let Some(ptr) = root.ptr() {
+                // SAFETY: roots are live words, their objects are owned by this heap
+                unsafe { (*ptr).marked = true };
+            }
+        }
+        let stats = &mut self.stats;
+        self.objects.retain(|ptr| {
+            // SAFETY: every pointer in `objects` is owned by the heap and freed at most once, here or on drop
+            unsafe {
+                if (**ptr).marked {
+                    (**ptr).marked = false;
+                    return true;
+                }
+                let object = Box::from_raw(*ptr);
+                stats.bytes -= object.size();
+                stats.freed += 1;
+            }
+            false
+        });
+        self.stats.collections += 1;
+        self.next_gc = self.threshold.max(self.stats.bytes * GROWTH_FACTOR);
+        self.pending = false;
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for ptr in self.objects.drain(..) {
+            // SAFETY: see `collect`
+            drop(unsafe { Box::from_raw(ptr) });
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// identifier of an interned name, equal names get the same symbol so lookups compare integers
+// symbols are numbered from 0 in order of interning, so they also serve as slots of a table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(pub usize);
+
+// names are stored once, shared by the lookup table and the list indexed by symbol
+#[derive(Default, Clone)]
+pub struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+    names: Vec<Rc<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.names.len());
+        let name: Rc<str> = name.into();
+        self.names.push(name.clone());
+        self.symbols.insert(name, symbol);
+        symbol
+    }
+
+    // symbol of `name` if it was interned before
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0]
+    }
+
+    // interned names in order of their symbols
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(|x| &**x)
+    }
+}
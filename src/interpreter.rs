@@ -0,0 +1,141 @@
+use std::any::Any;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::Error;
+use crate::chunk::Chunk;
+use crate::compiler::Compiler;
+use crate::convert::{FromValue, IntoArgs, IntoValue};
+use crate::limits::Limits;
+use crate::native::{self, Arity, NativeFn};
+use crate::sandbox::{Capability, Sandbox};
+use crate::snapshot::Snapshot;
+use crate::userdata::Class;
+use crate::value::Value;
+use crate::vm::{InterruptHandle, VM};
+
+// handle for embedding the language in a host program. every source string runs on the same VM,
+// so globals defined by one call are visible to the next ones and to the host
+pub struct Interpreter {
+    vm: VM,
+    opt_level: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter {vm: VM::default(), opt_level: 2}
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_opt_level(&mut self, level: usize) {
+        self.opt_level = level;
+    }
+
+    // destination of `print` statements, stdout by default
+    pub fn set_output(&mut self, out: Box<dyn Write>) {
+        self.vm.set_output(out);
+    }
+
+    // caps on the instructions, stack, heap and time of every run, e.g. for scripts of users
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.vm.set_limits(limits);
+    }
+
+    // side effects the scripts may perform, everything is allowed without a sandbox
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) {
+        self.vm.set_sandbox(sandbox);
+    }
+
+    // handle for stopping a running script from another thread
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.vm.interrupt_handle()
+    }
+
+    // compiles and runs `source`, returns value of its last expression statement (nil if there is none)
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        let chunk = self.compile(source)?;
+        self.run(chunk)
+    }
+
+    pub fn compile(&self, source: &str) -> Result<Chunk, Error> {
+        let mut compiler = Compiler::new(source.to_string());
+        compiler.set_opt_level(self.opt_level);
+        compiler.compile()?;
+        Ok(compiler.chunk)
+    }
+
+    // runs chunk compiled by `compile` on top of the globals of earlier runs
+    pub fn run(&mut self, chunk: Chunk) -> Result<Value, Error> {
+        self.vm.set_chunk(chunk);
+        self.vm.execute(false)
+    }
+
+    // state of the running program, for a `vm::Hook` pausing it
+    pub fn snapshot(&self) -> Result<Snapshot, Error> {
+        self.vm.snapshot()
+    }
+
+    // loads program paused by `snapshot`, natives it uses must be registered first
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), Error> {
+        self.vm.restore(snapshot)
+    }
+
+    // continues the program loaded by `restore`
+    pub fn resume(&mut self) -> Result<Value, Error> {
+        self.vm.execute(false)
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.vm.global(name)
+    }
+
+    // defines global variable, or overwrites its value if it is already defined
+    pub fn set_global(&mut self, name: &str, value: impl IntoValue) {
+        self.vm.define_global(name, value.into_value());
+    }
+
+    // calls the function in global `name` with a tuple of arguments, e.g.
+    // `let x: f64 = interpreter.call("hypot", (3, 4))?`
+    pub fn call<R: FromValue>(&mut self, name: &str, args: impl IntoArgs) -> Result<R, Error> {
+        let callee = match self.global(name) {
+            Some(x) => x,
+            None => return Err(Error::RUNTIME_ERROR(format!("NameError: undefined variable `{}`", name), 0))
+        };
+        let value = self.vm.call(&callee, &args.into_args())?;
+        R::from_value(value).map_err(|e| native::at_line(e, name, 0))
+    }
+
+    // makes `function` callable from scripts as global `name`
+    pub fn register_native(&mut self, name: &str, arity: impl Into<Arity>, function: NativeFn) {
+        self.vm.register_native(name, arity, function);
+    }
+
+    // native with a side effect, only called while the sandbox grants `capability`
+    pub fn register_native_requiring(&mut self, name: &str, arity: impl Into<Arity>, capability: Capability, function: NativeFn) {
+        self.vm.register_native_requiring(name, arity, capability, function);
+    }
+
+    // makes `class` available for creating userdata with `userdata`
+    pub fn register_class(&mut self, class: Class) -> Rc<Class> {
+        self.vm.register_class(class)
+    }
+
+    // wraps `object` of the host as userdata of registered class `class`, e.g. to pass it to `set_global`
+    pub fn userdata<T: Any>(&self, class: &str, object: T) -> Result<Value, Error> {
+        self.vm.userdata(class, object)
+    }
+
+    // VM of the interpreter, for installing a tracer or debugger hook and tuning the heap
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+use crate::Error;
+
+// minimal JSON document model used by the editor protocols (DAP, LSP)
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    NULL,
+    BOOL(bool),
+    NUMBER(f64),
+    STRING(String),
+    ARRAY(Vec<Json>),
+    OBJECT(BTreeMap<String, Json>),
+}
+
+static NULL: Json = Json::NULL;
+
+// arrays and objects nested deeper are rejected, the parser recurses for every level
+const MAX_DEPTH: usize = 256;
+
+// longest message body accepted from a client, longer ones are skipped without being stored
+const MAX_LENGTH: usize = 16 * 1024 * 1024;
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::OBJECT(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    // returns field `key` of an object, NULL if field does not exist or value is not an object
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::OBJECT(x) => x.get(key).unwrap_or(&NULL),
+            _ => &NULL
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::STRING(x) => Some(x),
+            _ => None
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::NUMBER(x) if *x >= 0.0 => Some(*x as usize),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::BOOL(x) => Some(*x),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::ARRAY(x) => x,
+            _ => &[]
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Json::NULL
+    }
+
+    pub fn parse(source: &str) -> Result<Json, String> {
+        let mut parser = Parser {source: source.as_bytes(), current: 0, depth: 0};
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.current < parser.source.len() {
+            return Err(format!("trailing characters at {}", parser.current));
+        }
+        Ok(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(x: &str) -> Json {
+        Json::STRING(x.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(x: String) -> Json {
+        Json::STRING(x)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(x: bool) -> Json {
+        Json::BOOL(x)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(x: usize) -> Json {
+        Json::NUMBER(x as f64)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(x: Vec<Json>) -> Json {
+        Json::ARRAY(x)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::NULL => write!(f, "null"),
+            Json::BOOL(x) => write!(f, "{}", x),
+            Json::NUMBER(x) => {
+                if x.is_finite() {
+                    write!(f, "{}", x)
+                } else {
+                    write!(f, "null")
+                }
+            },
+            Json::STRING(x) => write_string(f, x),
+            Json::ARRAY(x) => {
+                write!(f, "[")?;
+                for (i, item) in x.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::OBJECT(x) => {
+                write!(f, "{{")?;
+                for (i, (key, item)) in x.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", item)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a [u8],
+    current: usize,
+    depth: usize, // values being parsed
+}
+
+impl<'a> Parser<'a> {
+    fn whitespace(&mut self) {
+        while self.current < self.source.len() && (self.source[self.current] as char).is_ascii_whitespace() {
+            self.current += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.current).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.current += 1;
+            Ok(())
+        } else {
+            Err(format!("expected `{}` at {}", c as char, self.current))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        if self.source[self.current..].starts_with(keyword.as_bytes()) {
+            self.current += keyword.len();
+            Ok(value)
+        } else {
+            Err(format!("unexpected character at {}", self.current))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        if self.depth == MAX_DEPTH {
+            return Err("too deeply nested".to_string());
+        }
+        self.depth += 1;
+        let value = self.item();
+        self.depth -= 1;
+        value
+    }
+
+    fn item(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'n') => self.keyword("null", Json::NULL),
+            Some(b't') => self.keyword("true", Json::BOOL(true)),
+            Some(b'f') => self.keyword("false", Json::BOOL(false)),
+            Some(b'"') => Ok(Json::STRING(self.string()?)),
+            Some(b'[') => {
+                self.current += 1;
+                let mut items = vec![];
+                self.whitespace();
+                if self.peek() == Some(b']') {
+                    self.current += 1;
+                    return Ok(Json::ARRAY(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.current += 1,
+                        Some(b']') => {
+                            self.current += 1;
+                            return Ok(Json::ARRAY(items));
+                        },
+                        _ => return Err(format!("expected `,` or `]` at {}", self.current))
+                    }
+                }
+            },
+            Some(b'{') => {
+                self.current += 1;
+                let mut fields = BTreeMap::new();
+                self.whitespace();
+                if self.peek() == Some(b'}') {
+                    self.current += 1;
+                    return Ok(Json::OBJECT(fields));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(b':')?;
+                    let value = self.value()?;
+                    fields.insert(key, value);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.current += 1,
+                        Some(b'}') => {
+                            self.current += 1;
+                            return Ok(Json::OBJECT(fields));
+                        },
+                        _ => return Err(format!("expected `,` or `}}` at {}", self.current))
+                    }
+                }
+            },
+            Some(c) if c == b'-' || c.is_ascii_digit() => {
+                let start = self.current;
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_digit() || c == b'-' || c == b'+' || c == b'.' || c == b'e' || c == b'E' {
+                        self.current += 1;
+                    } else {
+                        break;
+                    }
+                }
+                let s = String::from_utf8_lossy(&self.source[start..self.current]);
+                match s.parse() {
+                    Ok(x) => Ok(Json::NUMBER(x)),
+                    Err(_) => Err(format!("invalid number `{}`", s))
+                }
+            },
+            _ => Err(format!("unexpected character at {}", self.current))
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        if self.current + 4 > self.source.len() {
+            return Err("unterminated unicode escape".to_string());
+        }
+        let s = String::from_utf8_lossy(&self.source[self.current..self.current + 4]).to_string();
+        self.current += 4;
+        u32::from_str_radix(&s, 16).map_err(|_| format!("invalid unicode escape `{}`", s))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut bytes = vec![];
+        loop {
+            match self.peek() {
+                None => return Err("unterminated string".to_string()),
+                Some(b'"') => {
+                    self.current += 1;
+                    break;
+                },
+                Some(b'\\') => {
+                    self.current += 1;
+                    let c = self.peek().ok_or("unterminated string")?;
+                    self.current += 1;
+                    let c = match c {
+                        b'n' => '\n',
+                        b't' => '\t',
+                        b'r' => '\r',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => {
+                            let mut code = self.hex()?;
+                            // surrogate pair
+                            if (0xD800..0xDC00).contains(&code) && self.source[self.current..].starts_with(b"\\u") {
+                                self.current += 2;
+                                let low = self.hex()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            char::from_u32(code).unwrap_or('\u{FFFD}')
+                        },
+                        c => c as char
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                },
+                Some(c) => {
+                    bytes.push(c);
+                    self.current += 1;
+                }
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| "invalid utf-8 in string".to_string())
+    }
+}
+
+// reads message framed with `Content-Length` header as used by DAP and LSP, None at end of input.
+// a body which isn't valid JSON or is too long is an error of that message only, the server can
+// answer it and go on reading
+pub fn read_message(input: &mut dyn BufRead) -> Result<Option<Result<Json, String>>, Error> {
+    let mut length: Option<usize> = None;
+    loop {
+        let mut header = String::new();
+        match input.read_line(&mut header) {
+            Ok(0) => return Ok(None),
+            Ok(_) => {},
+            Err(_) => return Err(Error::IO_ERROR)
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.unwrap();
+    if length > MAX_LENGTH {
+        return match std::io::copy(&mut std::io::Read::take(&mut *input, length as u64), &mut std::io::sink()) {
+            Ok(n) if n == length as u64 => Ok(Some(Err(format!("ProtocolError: message of {} bytes is longer than {} bytes", length, MAX_LENGTH)))),
+            _ => Err(Error::IO_ERROR)
+        };
+    }
+    let mut body = vec![0; length];
+    if input.read_exact(&mut body).is_err() {
+        return Err(Error::IO_ERROR);
+    }
+    Ok(Some(Json::parse(&String::from_utf8_lossy(&body)).map_err(|e| format!("ProtocolError: {}", e))))
+}
+
+pub fn write_message(output: &mut dyn Write, message: &Json) -> Result<(), Error> {
+    let body = message.to_string();
+    match write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body).and_then(|_| output.flush()) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::IO_ERROR)
+    }
+}
@@ -0,0 +1,57 @@
+// bytecode interpreter for the oxa language, embedded through `Interpreter`
+// the command line tool in `main.rs` is a client of this library
+
+pub mod chunk;
+pub mod value;
+pub mod nanbox;
+pub mod gc;
+pub mod intern;
+pub mod vm;
+pub mod token;
+pub mod ast;
+mod parser;
+pub mod resolver;
+mod optimizer;
+mod codegen;
+mod peephole;
+pub mod register;
+pub mod register_vm;
+pub mod compiler;
+mod scanner;
+pub mod trace;
+pub mod debugger;
+mod json;
+pub mod dap;
+pub mod lsp;
+pub mod fmt;
+pub mod lint;
+pub mod interpreter;
+pub mod convert;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod native;
+pub mod userdata;
+pub mod limits;
+pub mod sandbox;
+pub mod snapshot;
+#[cfg(test)]
+mod test;
+
+pub use convert::{FromValue, IntoValue};
+pub use interpreter::Interpreter;
+pub use value::Value;
+pub use vm::VM;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+pub enum Error {
+    COMPILE_ERROR(String, usize),
+    RUNTIME_ERROR(String, usize),
+    DIVIDE_BY_ZERO,
+    FILE_NOT_FOUND,
+    IO_ERROR,
+    LIMIT_EXCEEDED(limits::Limit, usize),
+    INTERRUPTED(usize), // by `vm::InterruptHandle` or the debugger, at the line
+    PERMISSION_DENIED(sandbox::Capability, Option<String>, usize), // by `sandbox::Sandbox`, the resource and line
+    SIGNAL
+}
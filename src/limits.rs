@@ -0,0 +1,40 @@
+use std::fmt;
+use std::time::Duration;
+
+// caps on the resources a script may use, for running code the host doesn't trust. every limit is
+// off by default. the VM checks them between instructions, a script over a limit stops with
+// `Error::LIMIT_EXCEEDED` which names the limit, so hosts can tell it apart from errors of the script
+
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub instructions: Option<u64>, // executed by one run
+    pub stack: Option<usize>, // values on the stack
+    pub memory: Option<usize>, // bytes allocated on the heap
+    pub timeout: Option<Duration>, // wall clock time of one run
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_none() && self.stack.is_none() && self.memory.is_none() && self.timeout.is_none()
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    INSTRUCTIONS(u64),
+    STACK(usize),
+    MEMORY(usize),
+    TIMEOUT(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::INSTRUCTIONS(x) => write!(f, "LimitError: more than {} instructions executed", x),
+            Limit::STACK(x) => write!(f, "LimitError: stack grew past {} values", x),
+            Limit::MEMORY(x) => write!(f, "LimitError: heap grew past {} bytes", x),
+            Limit::TIMEOUT(x) => write!(f, "LimitError: ran longer than {} ms", x.as_millis()),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+
+use crate::Error;
+use crate::chunk::OpCode;
+use crate::compiler::Compiler;
+use crate::scanner::Scanner;
+use crate::token::{Token, TokenType};
+
+// Static checks used by `oxa lint`. Scope based rules work on the compiled chunk and the symbols
+// collected by the compiler, syntactic rules work on the token stream.
+
+pub const RULES: [&str; 6] = ["unused-variable", "undefined-global", "unreachable-code", "assignment-in-condition",
+                              "shadowed-variable", "constant-condition"];
+
+pub const CONFIG_FILE: &str = "oxa-lint.conf";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub rule: &'static str,
+    pub message: String,
+    pub line: usize,
+}
+
+// rules which are switched off, read from lines of the form `rule = off` (or `on`), `#` starts a comment
+#[derive(Default)]
+pub struct Config {
+    disabled: HashSet<String>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, Error> {
+        let mut config = Config::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (rule, value) = match line.split_once('=') {
+                Some((rule, value)) => (rule.trim(), value.trim()),
+                None => return Err(Error::COMPILE_ERROR(format!("ConfigError: expected `rule = on|off` got `{}`", line), i + 1))
+            };
+            if !RULES.contains(&rule) {
+                return Err(Error::COMPILE_ERROR(format!("ConfigError: unknown lint rule `{}`", rule), i + 1));
+            }
+            match value {
+                "off" | "false" => config.disabled.insert(rule.to_string()),
+                "on" | "true" => config.disabled.remove(rule),
+                _ => return Err(Error::COMPILE_ERROR(format!("ConfigError: expected `on` or `off` got `{}`", value), i + 1))
+            };
+        }
+        Ok(config)
+    }
+
+    pub fn enabled(&self, rule: &str) -> bool {
+        !self.disabled.contains(rule)
+    }
+}
+
+// rules allowed with `// oxa-allow(rule, ...)` on each line, a comment on its own line applies to the next line
+fn allowed(comments: &[Token], code_lines: &HashSet<usize>) -> Vec<(usize, String)> {
+    let mut allowed = vec![];
+    for comment in comments {
+        let rules = match comment.lexeme.split_once("oxa-allow(") {
+            Some((_, rest)) => rest.split(')').next().unwrap_or(""),
+            None => continue
+        };
+        let line = if code_lines.contains(&comment.line) { comment.line } else { comment.line + 1 };
+        for rule in rules.split(',') {
+            allowed.push((line, rule.trim().to_string()));
+        }
+    }
+    allowed
+}
+
+// `while true` and `while (true)` are the way to write a loop exited with `break`
+fn infinite_loop(token: &Token, condition: &[&Token]) -> bool {
+    token.t == TokenType::WHILE && condition.iter().filter(|x| x.t != TokenType::LEFT_PAREN && x.t != TokenType::RIGHT_PAREN)
+        .map(|x| &x.t).eq([TokenType::TRUE].iter())
+}
+
+// warnings which only need the token stream
+fn syntactic(tokens: &[Token], warnings: &mut Vec<Warning>) {
+    for (i, token) in tokens.iter().enumerate() {
+        match token.t {
+            TokenType::BREAK | TokenType::CONTINUE => {
+                // `break;` must be the last statement of its block
+                if let Some(next) = tokens.get(i + 2) {
+                    if next.t != TokenType::RIGHT_BRACE && next.t != TokenType::EOF {
+                        warnings.push(Warning {
+                            rule: "unreachable-code",
+                            message: format!("unreachable code after `{}`", token.lexeme),
+                            line: next.line
+                        });
+                    }
+                }
+            },
+            TokenType::IF | TokenType::WHILE => {
+                // condition spans all tokens up to the body, which is the first `{`
+                let condition: Vec<&Token> = tokens[i + 1..].iter().take_while(|x| x.t != TokenType::LEFT_BRACE && x.t != TokenType::EOF).collect();
+                if condition.iter().any(|x| x.t == TokenType::EQUAL) {
+                    warnings.push(Warning {
+                        rule: "assignment-in-condition",
+                        message: format!("assignment in `{}` condition, did you mean `==`?", token.lexeme),
+                        line: token.line
+                    });
+                } else if !condition.is_empty() && !condition.iter().any(|x| x.t == TokenType::IDENTIFIER) && !infinite_loop(token, &condition) {
+                    warnings.push(Warning {
+                        rule: "constant-condition",
+                        message: format!("`{}` condition is constant", token.lexeme),
+                        line: token.line
+                    });
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+// warnings which need the scopes resolved by the compiler
+fn scopes(compiler: &Compiler, warnings: &mut Vec<Warning>) {
+    let chunk = &compiler.chunk;
+    for local in chunk.locals.iter() {
+        let end = local.end.min(chunk.code.len());
+        let read = chunk.code[local.start.min(end)..end].iter().any(|x| matches!(x, OpCode::GET_LOCAL(slot) if *slot == local.slot));
+        if !read {
+            warnings.push(Warning {
+                rule: "unused-variable",
+                message: format!("local variable `{}` is never read", local.name),
+                line: local.line
+            });
+        }
+    }
+
+    let symbols = &compiler.symbols;
+    let mut reported = HashSet::new();
+    for (token, declaration) in symbols.references.iter() {
+        if declaration.is_none() && reported.insert(token.lexeme.clone()) {
+            warnings.push(Warning {
+                rule: "undefined-global",
+                message: format!("use of undefined global `{}`", token.lexeme),
+                line: token.line
+            });
+        }
+    }
+
+    for declaration in symbols.declarations.iter() {
+        if let Some(i) = declaration.shadows {
+            let shadowed = &symbols.declarations[i];
+            let kind = if shadowed.depth == 0 { "global" } else { "local" };
+            warnings.push(Warning {
+                rule: "shadowed-variable",
+                message: format!("`{}` shadows {} variable declared on line {}", declaration.name.lexeme, kind, shadowed.name.line),
+                line: declaration.name.line
+            });
+        }
+    }
+}
+
+// lints source, returns warnings sorted by line or the compile error which prevented linting
+pub fn lint(source: &str, config: &Config) -> Result<Vec<Warning>, Error> {
+    let mut compiler = Compiler::new(source.to_string());
+    compiler.compile()?;
+
+    let mut scanner = Scanner::with_comments(source.to_string());
+    let mut tokens = vec![];
+    let mut comments = vec![];
+    loop {
+        let token = scanner.advance()?;
+        match token.t {
+            TokenType::EOF => {
+                tokens.push(token);
+                break;
+            },
+            TokenType::COMMENT => comments.push(token),
+            _ => tokens.push(token)
+        }
+    }
+    let code_lines: HashSet<usize> = tokens.iter().map(|x| x.line).collect();
+    let allowed = allowed(&comments, &code_lines);
+
+    let mut warnings = vec![];
+    syntactic(&tokens, &mut warnings);
+    scopes(&compiler, &mut warnings);
+    warnings.retain(|x| config.enabled(x.rule) && !allowed.iter().any(|(line, rule)| *line == x.line && rule == x.rule));
+    warnings.sort_by_key(|x| x.line);
+    Ok(warnings)
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::Error;
+use crate::compiler::Compiler;
+use crate::resolver::Symbols;
+use crate::json::{self, Json};
+use crate::scanner::Scanner;
+use crate::token::{Token, TokenType};
+
+// Language Server Protocol server, `oxa lsp` talks to the editor over stdin/stdout.
+// Documents are re-analyzed on every request, oxa files are small enough for that to be cheap.
+
+const SEMANTIC_TOKEN_TYPES: [&str; 6] = ["keyword", "variable", "string", "number", "operator", "comment"];
+
+const KEYWORDS: [&str; 15] = ["and", "or", "xor", "if", "else", "while", "break", "continue", "var", "print",
+                              "true", "false", "nil", "import", "from"];
+
+// result of compiling a document
+struct Analysis {
+    symbols: Symbols,
+    error: Option<Error>,
+}
+
+fn analyze(text: &str) -> Analysis {
+    let mut compiler = Compiler::new(text.to_string());
+    let error = compiler.compile().err();
+    Analysis {symbols: compiler.symbols, error}
+}
+
+// the protocol counts columns in UTF-16 code units, tokens count them in characters
+fn utf16_col(line: &str, col: usize) -> usize {
+    line.chars().take(col).map(char::len_utf16).sum()
+}
+
+// column in characters of a client position, positions inside a character round up
+fn char_col(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.chars().enumerate() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.chars().count()
+}
+
+fn source_line<'a>(lines: &[&'a str], line: usize) -> &'a str {
+    lines.get(line).copied().unwrap_or("")
+}
+
+fn position(lines: &[&str], line: usize, col: usize) -> Json {
+    Json::object(vec![("line", line.into()), ("character", utf16_col(source_line(lines, line), col).into())])
+}
+
+fn range(lines: &[&str], token: &Token) -> Json {
+    let line = token.line - 1;
+    Json::object(vec![("start", position(lines, line, token.col)), ("end", position(lines, line, token.col + token.len))])
+}
+
+fn location(uri: &str, lines: &[&str], token: &Token) -> Json {
+    Json::object(vec![("uri", uri.into()), ("range", range(lines, token))])
+}
+
+fn contains(token: &Token, line: usize, character: usize) -> bool {
+    token.line == line + 1 && token.col <= character && character <= token.col + token.len
+}
+
+impl Analysis {
+    // declaration of the variable under the cursor, cursor may be on a use or the declaration itself
+    fn declaration_at(&self, line: usize, character: usize) -> Option<usize> {
+        if let Some(i) = self.symbols.declarations.iter().position(|x| contains(&x.name, line, character)) {
+            return Some(i);
+        }
+        self.symbols.references.iter().find(|x| contains(&x.0, line, character)).and_then(|x| x.1)
+    }
+
+    fn diagnostics(&self, text: &str) -> Vec<Json> {
+        let (message, line) = match &self.error {
+            Some(Error::COMPILE_ERROR(message, line)) | Some(Error::RUNTIME_ERROR(message, line)) => (message.clone(), *line),
+            Some(e) => (format!("{:?}", e), 1),
+            None => return vec![]
+        };
+        let line = line.max(1) - 1;
+        let length = text.lines().nth(line).map(|x| x.encode_utf16().count()).unwrap_or(0);
+        let range = Json::object(vec![
+            ("start", Json::object(vec![("line", line.into()), ("character", 0.into())])),
+            ("end", Json::object(vec![("line", line.into()), ("character", length.into())])),
+        ]);
+        vec![Json::object(vec![("range", range), ("severity", 1.into()), ("source", "oxa".into()), ("message", message.into())])]
+    }
+}
+
+fn semantic_token_type(t: &TokenType) -> Option<usize> {
+    match t {
+        TokenType::IDENTIFIER => Some(1),
+        TokenType::STRING => Some(2),
+        TokenType::NUMBER => Some(3),
+        TokenType::MINUS | TokenType::PLUS | TokenType::SLASH | TokenType::PERCENT | TokenType::STAR | TokenType::BANG |
+        TokenType::BANG_EQUAL | TokenType::EQUAL | TokenType::EQUAL_EQUAL | TokenType::GREATER | TokenType::GREATER_EQUAL |
+        TokenType::LESS | TokenType::LESS_EQUAL => Some(4),
+        TokenType::COMMENT => Some(5),
+        TokenType::LEFT_PAREN | TokenType::RIGHT_PAREN | TokenType::LEFT_BRACE | TokenType::RIGHT_BRACE | TokenType::COMMA |
+        TokenType::DOT | TokenType::SEMICOLON | TokenType::BRA | TokenType::KET | TokenType::EOF => None,
+        _ => Some(0)
+    }
+}
+
+// semantic tokens of the whole document in the relative encoding of the protocol
+fn semantic_tokens(text: &str) -> Vec<Json> {
+    let mut scanner = Scanner::with_comments(text.to_string());
+    let lines: Vec<&str> = text.lines().collect();
+    let mut data = vec![];
+    let (mut line, mut col) = (0, 0);
+    loop {
+        let token = match scanner.advance() {
+            Ok(token) if token.t == TokenType::EOF => break,
+            Ok(token) => token,
+            Err(_) => continue
+        };
+        let t = match semantic_token_type(&token.t) {
+            // tokens can't span multiple lines
+            Some(t) if !token.lexeme.contains('\n') => t,
+            _ => continue
+        };
+        let token_line = token.line - 1;
+        let source = source_line(&lines, token_line);
+        let start = utf16_col(source, token.col);
+        let len = utf16_col(source, token.col + token.len) - start;
+        let delta_col = if token_line == line { start - col } else { start };
+        data.extend([token_line - line, delta_col, len, t, 0].into_iter().map(Json::from));
+        line = token_line;
+        col = start;
+    }
+    data
+}
+
+struct Server {
+    output: Box<dyn Write>,
+    documents: HashMap<String, String>,
+}
+
+impl Server {
+    fn respond(&mut self, id: &Json, result: Json) -> Result<(), Error> {
+        let message = Json::object(vec![("jsonrpc", "2.0".into()), ("id", id.clone()), ("result", result)]);
+        json::write_message(&mut self.output, &message)
+    }
+
+    fn fail(&mut self, id: &Json, code: i32, message: &str) -> Result<(), Error> {
+        let error = Json::object(vec![("code", Json::NUMBER(code as f64)), ("message", message.into())]);
+        let message = Json::object(vec![("jsonrpc", "2.0".into()), ("id", id.clone()), ("error", error)]);
+        json::write_message(&mut self.output, &message)
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> Result<(), Error> {
+        let message = Json::object(vec![("jsonrpc", "2.0".into()), ("method", method.into()), ("params", params)]);
+        json::write_message(&mut self.output, &message)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> Result<(), Error> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(text) => analyze(text).diagnostics(text),
+            None => vec![]
+        };
+        self.notify("textDocument/publishDiagnostics", Json::object(vec![("uri", uri.into()), ("diagnostics", diagnostics.into())]))
+    }
+
+    fn initialize(&mut self, id: &Json) -> Result<(), Error> {
+        let legend = Json::object(vec![
+            ("tokenTypes", SEMANTIC_TOKEN_TYPES.iter().map(|x| Json::from(*x)).collect::<Vec<Json>>().into()),
+            ("tokenModifiers", Json::ARRAY(vec![])),
+        ]);
+        let capabilities = Json::object(vec![
+            ("positionEncoding", "utf-16".into()),
+            ("textDocumentSync", Json::object(vec![
+                ("openClose", true.into()),
+                ("change", 1.into()), // full document is sent on every change
+                ("save", Json::object(vec![("includeText", true.into())])),
+            ])),
+            ("semanticTokensProvider", Json::object(vec![("legend", legend), ("full", true.into())])),
+            ("definitionProvider", true.into()),
+            ("referencesProvider", true.into()),
+            ("hoverProvider", true.into()),
+            ("completionProvider", Json::object(vec![])),
+        ]);
+        let result = Json::object(vec![("capabilities", capabilities), ("serverInfo", Json::object(vec![("name", "oxa".into())]))]);
+        self.respond(id, result)
+    }
+
+    // handles request which refers to a position in a document
+    fn position_request(&mut self, id: &Json, method: &str, params: &Json) -> Result<(), Error> {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("").to_string();
+        let text = match self.documents.get(&uri) {
+            Some(x) => x.clone(),
+            None => return self.fail(id, -32602, &format!("unknown document `{}`", uri))
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        let line = params.get("position").get("line").as_usize().unwrap_or(0);
+        let character = params.get("position").get("character").as_usize().unwrap_or(0);
+        let character = char_col(source_line(&lines, line), character);
+        let analysis = analyze(&text);
+        let symbols = &analysis.symbols;
+        let declaration = analysis.declaration_at(line, character);
+
+        let result = match (method, declaration) {
+            ("textDocument/definition", Some(i)) => location(&uri, &lines, &symbols.declarations[i].name),
+            ("textDocument/references", Some(i)) => {
+                let mut locations = vec![];
+                if params.get("context").get("includeDeclaration").as_bool().unwrap_or(true) {
+                    locations.push(location(&uri, &lines, &symbols.declarations[i].name));
+                }
+                for (token, _) in symbols.references.iter().filter(|x| x.1 == Some(i)) {
+                    locations.push(location(&uri, &lines, token));
+                }
+                locations.into()
+            },
+            ("textDocument/hover", Some(i)) => {
+                let declaration = &symbols.declarations[i];
+                let scope = if declaration.depth == 0 { "global" } else { "local" };
+                let value = format!("```oxa\nvar {}: {}\n```\n{} variable", declaration.name.lexeme, declaration.kind, scope);
+                Json::object(vec![
+                    ("contents", Json::object(vec![("kind", "markdown".into()), ("value", value.into())])),
+                    ("range", range(&lines, &declaration.name)),
+                ])
+            },
+            ("textDocument/completion", _) => {
+                let mut items: Vec<Json> = KEYWORDS.iter().map(|x| Json::object(vec![("label", (*x).into()), ("kind", 14.into())])).collect();
+                let mut names: Vec<(&str, String)> = vec![];
+                for declaration in symbols.declarations.iter() {
+                    if !names.iter().any(|x| x.0 == declaration.name.lexeme) {
+                        names.push((&declaration.name.lexeme, declaration.kind.to_string()));
+                    }
+                }
+                for (name, kind) in names {
+                    items.push(Json::object(vec![("label", name.into()), ("kind", 6.into()), ("detail", kind.into())]));
+                }
+                items.into()
+            },
+            _ => Json::NULL
+        };
+        self.respond(id, result)
+    }
+
+    fn handle(&mut self, message: &Json) -> Result<bool, Error> {
+        let id = message.get("id");
+        let params = message.get("params");
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("").to_string();
+        match message.get("method").as_str().unwrap_or("") {
+            "initialize" => self.initialize(id)?,
+            "shutdown" => self.respond(id, Json::NULL)?,
+            "exit" => return Ok(false),
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str().unwrap_or("").to_string();
+                self.documents.insert(uri.clone(), text);
+                self.publish_diagnostics(&uri)?;
+            },
+            "textDocument/didChange" => {
+                if let Some(change) = params.get("contentChanges").as_array().last() {
+                    let text = change.get("text").as_str().unwrap_or("").to_string();
+                    self.documents.insert(uri, text);
+                }
+            },
+            "textDocument/didSave" => {
+                if let Some(text) = params.get("text").as_str() {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                self.publish_diagnostics(&uri)?;
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri)?;
+            },
+            "textDocument/semanticTokens/full" => {
+                let data = self.documents.get(&uri).map(|x| semantic_tokens(x)).unwrap_or_default();
+                self.respond(id, Json::object(vec![("data", data.into())]))?;
+            },
+            method @ ("textDocument/definition" | "textDocument/references" | "textDocument/hover" | "textDocument/completion") => {
+                self.position_request(id, method, params)?;
+            },
+            method => {
+                // notifications without handler are ignored
+                if !id.is_null() {
+                    self.fail(id, -32601, &format!("unsupported method `{}`", method))?;
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+// runs language server until the client sends `exit`
+pub fn serve(mut input: Box<dyn BufRead>, output: Box<dyn Write>) -> Result<(), Error> {
+    let mut server = Server {output, documents: HashMap::new()};
+    while let Some(message) = json::read_message(&mut input)? {
+        match message {
+            Ok(message) => if !server.handle(&message)? {
+                break;
+            },
+            // the id of a message which can't be parsed is unknown
+            Err(e) => server.fail(&Json::NULL, -32700, &e)?
+        }
+    }
+    Ok(())
+}
@@ -1,49 +1,127 @@
 use std::env;
-use std::io::Write;
-
-mod chunk;
-mod value;
-mod vm;
-mod token;
-mod compiler;
-mod scanner;
-
-use vm::VM;
-use compiler::Compiler;
-
-#[allow(non_camel_case_types)]
-#[derive(Debug)]
-pub enum Error {
-    COMPILE_ERROR(String, usize),
-    RUNTIME_ERROR(String, usize),
-    DIVIDE_BY_ZERO,
-    FILE_NOT_FOUND,
-    IO_ERROR,
-    BREAK(usize),
-    CONTINUE(usize),
-    SIGNAL
-}
-
-fn interpret(code: String) -> Result<(), Error> {
-    // declare virtual machine which will interpret the bytecode
-    let mut vm = VM::default();
-    // instantiate the compiler
-    let mut compiler = Compiler::new(code);
-    // compile the source code into bytecode
-    if let Err(e) = compiler.compile() {
-        return Err(e);
+use std::io::{Write, BufWriter};
+use std::time::Duration;
+
+use oxa::{ast, dap, debugger, fmt, lint, lsp, register, trace};
+use oxa::{Error, Interpreter, VM, Value};
+use oxa::compiler::Compiler;
+use oxa::register_vm::RegisterVM;
+use oxa::trace::Tracer;
+use oxa::gc::Heap;
+use oxa::limits::Limits;
+use oxa::sandbox::Sandbox;
+
+// command line options
+struct Options {
+    trace: bool,
+    trace_file: Option<String>,
+    trace_lines: Option<(usize, usize)>,
+    dump_ast: bool, // print syntax tree instead of running the program
+    opt_level: usize,
+    register_vm: bool, // run on `register_vm` instead of the stack machine
+    gc_stress: bool,
+    gc_threshold: Option<usize>,
+    gc_stats: bool, // print statistics of the garbage collector after the program finished
+    limits: Limits,
+    sandbox: Option<Sandbox>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {trace: false, trace_file: None, trace_lines: None, dump_ast: false, opt_level: 2, register_vm: false, gc_stress: false, gc_threshold: None, gc_stats: false, limits: Limits::default(), sandbox: None}
     }
-    // set VM with chunk of bytecode
-    vm.set_chunk(compiler.chunk);
-    // run the VM
-    vm.execute(true)
+}
+
+impl Options {
+    fn tracer(&self) -> Result<Option<Tracer>, Error> {
+        if !self.trace {
+            return Ok(None);
+        }
+        let out: Box<dyn Write> = match &self.trace_file {
+            Some(path) => match std::fs::File::create(path) {
+                Ok(file) => Box::new(BufWriter::new(file)),
+                Err(_) => {
+                    println!("IOError: could not open trace file `{}`", path);
+                    return Err(Error::IO_ERROR);
+                }
+            },
+            None => Box::new(std::io::stdout()),
+        };
+        Ok(Some(Tracer::new(out, self.trace_lines)))
+    }
+
+    fn configure_heap(&self, heap: &mut Heap) {
+        heap.set_stress(self.gc_stress);
+        if let Some(bytes) = self.gc_threshold {
+            heap.set_threshold(bytes);
+        }
+    }
+
+    fn report_heap(&self, heap: &Heap) {
+        if self.gc_stats {
+            eprintln!("{}", heap.stats());
+        }
+    }
+
+    // interpreter running the stack machine as configured
+    fn interpreter(&self) -> Result<Interpreter, Error> {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_opt_level(self.opt_level);
+        if let Some(tracer) = self.tracer()? {
+            interpreter.vm_mut().set_tracer(tracer);
+        }
+        self.configure_heap(interpreter.vm_mut().heap_mut());
+        interpreter.set_limits(self.limits.clone());
+        if let Some(sandbox) = &self.sandbox {
+            interpreter.set_sandbox(sandbox.clone());
+        }
+        Ok(interpreter)
+    }
+
+    // register machine used by `--vm=register`
+    fn register_machine(&self) -> RegisterVM {
+        let mut vm = RegisterVM::default();
+        self.configure_heap(vm.heap_mut());
+        vm
+    }
+}
+
+// runs `code` on the machine selected by `options`, both keep their globals between calls
+fn interpret(interpreter: &mut Interpreter, register_vm: &mut RegisterVM, code: String, options: &Options) -> Result<Value, Error> {
+    if options.dump_ast || options.register_vm {
+        let mut compiler = Compiler::new(code);
+        compiler.set_opt_level(options.opt_level);
+        compiler.analyze()?;
+        compiler.optimize();
+        if options.dump_ast {
+            print!("{}", ast::dump(&compiler.statements));
+            return Ok(Value::NIL);
+        }
+        let chunk = register::Generator::new().program(&compiler.statements);
+        println!("------------------------------");
+        chunk.dissassemble();
+        println!("------------------------------");
+        let result = register_vm.execute(&chunk);
+        options.report_heap(register_vm.heap());
+        return result;
+    }
+    // compile the source code into bytecode
+    let chunk = interpreter.compile(&code)?;
+    println!("------------------------------");
+    chunk.dissassemble_chunk();
+    println!("------------------------------");
+    // run it on the VM of the interpreter
+    let result = interpreter.run(chunk);
+    options.report_heap(interpreter.vm().heap());
+    result
 }
 
 // reads text from source file and runs it
-fn runfile(filename: &str) -> Result<(), Error> {
+fn runfile(filename: &str, options: &Options) -> Result<(), Error> {
     match std::fs::read_to_string(filename) {
         Ok(code) => {
-            interpret(code)
+            interpret(&mut options.interpreter()?, &mut options.register_machine(), code, options)?;
+            Ok(())
         },
         Err(_) => {
             println!("FileNotFound: file `{}` could not be found", filename);
@@ -53,7 +131,277 @@ fn runfile(filename: &str) -> Result<(), Error> {
     
 }
 
-fn repl() -> Result<(), Error> {
+// formats files in place, with `--check` only reports files which are not formatted
+fn format_files(args: &[String]) -> Result<(), Error> {
+    let mut check = false;
+    let mut width = fmt::DEFAULT_WIDTH;
+    let mut files = vec![];
+    for arg in args {
+        if arg == "--check" {
+            check = true;
+        } else if let Some(n) = arg.strip_prefix("--width=") {
+            match n.parse() {
+                Ok(n) => width = n,
+                Err(_) => usage()
+            }
+        } else if arg.starts_with("--") {
+            usage();
+        } else {
+            files.push(arg);
+        }
+    }
+    if files.is_empty() {
+        usage();
+    }
+
+    let mut unformatted = false;
+    for filename in files {
+        let code = match std::fs::read_to_string(filename) {
+            Ok(code) => code,
+            Err(_) => {
+                println!("FileNotFound: file `{}` could not be found", filename);
+                return Err(Error::FILE_NOT_FOUND);
+            }
+        };
+        let formatted = match fmt::format(&code, width) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("{}: {:?}", filename, e);
+                return Err(e);
+            }
+        };
+        if formatted == code {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", filename);
+            unformatted = true;
+        } else if std::fs::write(filename, formatted).is_err() {
+            return Err(Error::IO_ERROR);
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// lints files, config is read from `--config=path` or `oxa-lint.conf` in the working directory
+fn lint_files(args: &[String]) -> Result<(), Error> {
+    let mut config_file = None;
+    let mut files = vec![];
+    for arg in args {
+        if let Some(path) = arg.strip_prefix("--config=") {
+            config_file = Some(path.to_string());
+        } else if arg.starts_with("--") {
+            usage();
+        } else {
+            files.push(arg);
+        }
+    }
+    if files.is_empty() {
+        usage();
+    }
+
+    let config = match config_file {
+        Some(path) => match std::fs::read_to_string(&path) {
+            Ok(text) => lint::Config::parse(&text),
+            Err(_) => {
+                println!("FileNotFound: file `{}` could not be found", path);
+                return Err(Error::FILE_NOT_FOUND);
+            }
+        },
+        None => match std::fs::read_to_string(lint::CONFIG_FILE) {
+            Ok(text) => lint::Config::parse(&text),
+            Err(_) => Ok(lint::Config::default())
+        }
+    };
+    let config = match config {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{:?}", e);
+            return Err(e);
+        }
+    };
+
+    let mut found = false;
+    for filename in files {
+        let code = match std::fs::read_to_string(filename) {
+            Ok(code) => code,
+            Err(_) => {
+                println!("FileNotFound: file `{}` could not be found", filename);
+                return Err(Error::FILE_NOT_FOUND);
+            }
+        };
+        let warnings = match lint::lint(&code, &config) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("{}: {:?}", filename, e);
+                return Err(e);
+            }
+        };
+        for warning in warnings {
+            println!("{}:{}: warning: {} [{}]", filename, warning.line, warning.message, warning.rule);
+            found = true;
+        }
+    }
+    if found {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// sandbox granting the capabilities listed in the file at `path`
+fn sandbox(path: &str) -> Result<Sandbox, Error> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => {
+            println!("FileNotFound: file `{}` could not be found", path);
+            return Err(Error::FILE_NOT_FOUND);
+        }
+    };
+    match Sandbox::parse(&text) {
+        Ok(x) => Ok(x),
+        Err(e) => {
+            println!("{}: {:?}", path, e);
+            Err(e)
+        }
+    }
+}
+
+// value of a numeric flag, e.g. `--max-stack=256`
+fn number<T: std::str::FromStr>(arg: &str) -> T {
+    match arg.parse() {
+        Ok(x) => x,
+        Err(_) => usage()
+    }
+}
+
+// parses `-O0`, `-O1` and `-O2`
+fn opt_level(arg: &str) -> Option<usize> {
+    match arg {
+        "-O0" => Some(0),
+        "-O1" => Some(1),
+        "-O2" => Some(2),
+        _ => None
+    }
+}
+
+// runs each file at every optimization level and on the register machine, reports the run times, output of the programs is discarded
+fn bench(files: &[String]) -> Result<(), Error> {
+    if files.is_empty() {
+        usage();
+    }
+    const RUNS: u32 = 5;
+    println!("{:<32} {:>10} {:>10} {:>10} {:>10} {:>8}", "benchmark", "-O0 ms", "-O1 ms", "-O2 ms", "register", "speedup");
+    for filename in files {
+        let code = match std::fs::read_to_string(filename) {
+            Ok(code) => code,
+            Err(_) => {
+                println!("FileNotFound: file `{}` could not be found", filename);
+                return Err(Error::FILE_NOT_FOUND);
+            }
+        };
+        let mut times = vec![];
+        for level in 0..3 {
+            let mut compiler = Compiler::new(code.clone());
+            compiler.set_opt_level(level);
+            compiler.compile()?;
+            // fastest of several runs, the first ones warm up caches
+            let mut best = f64::MAX;
+            for _ in 0..RUNS {
+                let mut vm = VM::default();
+                vm.set_output(Box::new(std::io::sink()));
+                vm.set_chunk(compiler.chunk.clone());
+                let start = std::time::Instant::now();
+                vm.execute(false)?;
+                best = best.min(start.elapsed().as_secs_f64() * 1000.0);
+            }
+            times.push(best);
+        }
+        // register machine with the same syntax tree optimizations as -O1
+        let mut compiler = Compiler::new(code.clone());
+        compiler.set_opt_level(1);
+        compiler.analyze()?;
+        compiler.optimize();
+        let chunk = register::Generator::new().program(&compiler.statements);
+        let mut best = f64::MAX;
+        for _ in 0..RUNS {
+            let mut vm = RegisterVM::default();
+            vm.set_output(Box::new(std::io::sink()));
+            let start = std::time::Instant::now();
+            vm.execute(&chunk)?;
+            best = best.min(start.elapsed().as_secs_f64() * 1000.0);
+        }
+        times.push(best);
+        let fastest = times.iter().cloned().fold(f64::MAX, f64::min);
+        println!("{:<32} {:>10.2} {:>10.2} {:>10.2} {:>10.2} {:>7.2}x", filename, times[0], times[1], times[2], times[3], times[0] / fastest);
+    }
+    Ok(())
+}
+
+// runs source file under the interactive debugger
+fn debug(filename: &str) -> Result<(), Error> {
+    let code = match std::fs::read_to_string(filename) {
+        Ok(code) => code,
+        Err(_) => {
+            println!("FileNotFound: file `{}` could not be found", filename);
+            return Err(Error::FILE_NOT_FOUND);
+        }
+    };
+    let mut vm = VM::default();
+    let console = debugger::Console::new(&code, std::io::stdin().lock(), std::io::stdout());
+    vm.set_hook(Box::new(console));
+    let mut compiler = Compiler::new(code);
+    compiler.compile()?;
+    vm.set_chunk(compiler.chunk);
+    match vm.execute(false) {
+        // `quit` or end of input
+        Err(Error::INTERRUPTED(_)) => Ok(()),
+        result => result.map(|_| ())
+    }
+}
+
+// Ctrl-C interrupts the program running in the REPL instead of killing the process
+#[cfg(unix)]
+mod sigint {
+    use std::sync::OnceLock;
+
+    use oxa::vm::InterruptHandle;
+
+    const SIGINT: i32 = 2;
+
+    static HANDLE: OnceLock<InterruptHandle> = OnceLock::new();
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+
+    // only sets an atomic flag, which is safe in a signal handler
+    extern "C" fn interrupt(_: i32) {
+        if let Some(handle) = HANDLE.get() {
+            handle.interrupt();
+        }
+    }
+
+    pub fn forward(handle: InterruptHandle) {
+        if HANDLE.set(handle).is_ok() {
+            // SAFETY: `interrupt` only does an atomic store
+            unsafe { signal(SIGINT, interrupt) };
+        }
+    }
+}
+
+// globals defined by a line stay defined for the following ones
+fn repl(options: &Options) -> Result<(), Error> {
+    let mut interpreter = options.interpreter()?;
+    let mut register_vm = options.register_machine();
+    let interrupt = interpreter.interrupt_handle();
+    // the register machine can't be interrupted, Ctrl-C keeps killing it
+    #[cfg(unix)]
+    if !options.register_vm {
+        sigint::forward(interrupt.clone());
+    }
     loop {
         print!(">> ");
         // necessary due to line-buffering of stdout
@@ -63,25 +411,120 @@ fn repl() -> Result<(), Error> {
         };
         let mut instruction = String::new();
         match std::io::stdin().read_line(&mut instruction) {
+            // end of input
+            Ok(0) => return Ok(()),
             Ok(_) => {},
             Err(_) => return Err(Error::IO_ERROR)
         };
-        if !instruction.trim().is_empty() {
-            interpret(instruction)?;
+        if instruction.trim().is_empty() {
+            continue;
+        }
+        // Ctrl-C at the prompt doesn't carry over to the next line
+        interrupt.clear();
+        match interpret(&mut interpreter, &mut register_vm, instruction, options) {
+            Err(Error::INTERRUPTED(line)) => println!("Interrupted at line {}", line),
+            result => { result?; }
         }
     }
 }
 
+fn usage() -> ! {
+    println!("Usage: oxa [--trace] [--trace-file=path] [--trace-lines=from-to] [--dump-ast] [-O0|-O1|-O2] [--vm=stack|register]");
+    println!("           [--gc-stress] [--gc-threshold=bytes] [--gc-stats]");
+    println!("           [--max-instructions=n] [--max-stack=n] [--max-memory=bytes] [--timeout=ms]");
+    println!("           [--sandbox[=allowlist]] [filename]");
+    println!("       oxa debug <filename>");
+    println!("       oxa dap");
+    println!("       oxa lsp");
+    println!("       oxa fmt [--check] [--width=n] <filename>...");
+    println!("       oxa lint [--config=path] <filename>...");
+    println!("       oxa bench <filename>...");
+    std::process::exit(64);
+}
+
 fn main() -> Result<(), Error> {
-    let argv: Vec<_> = env::args().collect();
-    let argc = argv.len();
-
-    if argc > 2 {
-        println!("Usage: oxa [filename]");
-        std::process::exit(64); 
-    } else if argc == 2 {
-        runfile(&argv[1])
+    let argv: Vec<String> = env::args().collect();
+    if argv.len() > 1 && argv[1] == "debug" {
+        if argv.len() != 3 {
+            usage();
+        }
+        return debug(&argv[2]);
+    }
+    if argv.len() == 2 && argv[1] == "dap" {
+        return dap::serve(Box::new(std::io::stdin().lock()), Box::new(std::io::stdout()));
+    }
+    if argv.len() > 1 && argv[1] == "fmt" {
+        return format_files(&argv[2..]);
+    }
+    if argv.len() > 1 && argv[1] == "bench" {
+        return bench(&argv[2..]);
+    }
+    if argv.len() > 1 && argv[1] == "lint" {
+        return lint_files(&argv[2..]);
+    }
+    if argv.len() == 2 && argv[1] == "lsp" {
+        return lsp::serve(Box::new(std::io::stdin().lock()), Box::new(std::io::stdout()));
+    }
+
+    let mut options = Options::default();
+    let mut files = vec![];
+
+    for arg in argv.into_iter().skip(1) {
+        if arg == "--trace" {
+            options.trace = true;
+        } else if arg == "--gc-stress" {
+            options.gc_stress = true;
+        } else if arg == "--gc-stats" {
+            options.gc_stats = true;
+        } else if let Some(bytes) = arg.strip_prefix("--gc-threshold=") {
+            match bytes.parse() {
+                Ok(bytes) => options.gc_threshold = Some(bytes),
+                Err(_) => usage()
+            }
+        } else if let Some(n) = arg.strip_prefix("--max-instructions=") {
+            options.limits.instructions = Some(number(n));
+        } else if let Some(n) = arg.strip_prefix("--max-stack=") {
+            options.limits.stack = Some(number(n));
+        } else if let Some(bytes) = arg.strip_prefix("--max-memory=") {
+            options.limits.memory = Some(number(bytes));
+        } else if let Some(ms) = arg.strip_prefix("--timeout=") {
+            options.limits.timeout = Some(Duration::from_millis(number(ms)));
+        } else if arg == "--sandbox" {
+            options.sandbox = Some(Sandbox::deny_all());
+        } else if let Some(path) = arg.strip_prefix("--sandbox=") {
+            options.sandbox = Some(sandbox(path)?);
+        } else if arg == "--dump-ast" {
+            options.dump_ast = true;
+        } else if let Some(backend) = arg.strip_prefix("--vm=") {
+            match backend {
+                "stack" => options.register_vm = false,
+                "register" => options.register_vm = true,
+                _ => usage()
+            }
+        } else if let Some(level) = opt_level(&arg) {
+            options.opt_level = level;
+        } else if let Some(path) = arg.strip_prefix("--trace-file=") {
+            options.trace = true;
+            options.trace_file = Some(path.to_string());
+        } else if let Some(range) = arg.strip_prefix("--trace-lines=") {
+            options.trace = true;
+            match trace::parse_line_range(range) {
+                Some(range) => options.trace_lines = Some(range),
+                None => usage()
+            }
+        } else if arg.starts_with("--") {
+            usage();
+        } else {
+            files.push(arg);
+        }
+    }
+
+    // tracing, limits and the sandbox hook into the stack machine
+    if files.len() > 1 || ((options.trace || !options.limits.is_empty() || options.sandbox.is_some()) && options.register_vm) {
+        usage();
+    } else if files.len() == 1 {
+        runfile(&files[0], &options)
     } else {
-        repl()
+        repl(&options)
     }
 }
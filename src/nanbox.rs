@@ -0,0 +1,226 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Add, Sub, Mul, Div, Rem, Neg, BitOr, BitAnd};
+
+use crate::Error;
+use crate::gc::{Obj, Object};
+use crate::native::Native;
+use crate::userdata::Userdata;
+use crate::value::Value;
+
+// runtime representation of `Value` in a single 64-bit word, used by the stack, globals and registers
+// of the VMs. floats are stored as they are, every other value is a quiet NaN whose low bits tag
+// nil and the bools, or with the sign bit set, hold a pointer to an object on the `gc::Heap`.
+// words are plain copies, a pointer stays valid as long as the VM keeps it reachable for the collector.
+// only the crate makes words pointing to objects, the VMs hand `Value`s to the host, so such a word
+// never outlives its object
+// operators have fast paths for numbers and strings, everything else goes through the `Value` impls
+// which stay the reference for the semantics of the language. they produce a `Value`, which the
+// VM moves back onto its heap
+
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+const SIGN: u64 = 0x8000_0000_0000_0000;
+const OBJ: u64 = SIGN | QNAN;
+
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+
+#[derive(Clone, Copy)]
+pub struct NanBox {
+    bits: u64,
+    marker: PhantomData<*const Object>, // neither `Send` nor `Sync`, the heap isn't shared between threads
+}
+
+impl NanBox {
+    pub const NIL: NanBox = NanBox {bits: QNAN | TAG_NIL, marker: PhantomData};
+
+    pub fn float(x: f64) -> Self {
+        // NaNs produced by arithmetic may carry any payload, only the canonical one can't be mistaken for a tag
+        let x = if x.is_nan() { f64::NAN } else { x };
+        NanBox {bits: x.to_bits(), marker: PhantomData}
+    }
+
+    pub fn bool(x: bool) -> Self {
+        NanBox {bits: QNAN | if x { TAG_TRUE } else { TAG_FALSE }, marker: PhantomData}
+    }
+
+    // word pointing to an object allocated by `gc::Heap`
+    pub(crate) fn object(ptr: *mut Object) -> Self {
+        let ptr = ptr as u64;
+        // user space addresses fit in the 48 bits below the tag
+        assert!(ptr & OBJ == 0, "heap address doesn't fit in a NaN payload");
+        NanBox {bits: OBJ | ptr, marker: PhantomData}
+    }
+
+    pub(crate) fn ptr(&self) -> Option<*mut Object> {
+        if self.bits & OBJ == OBJ {
+            Some((self.bits & !OBJ) as *mut Object)
+        } else {
+            None
+        }
+    }
+
+    fn obj(&self) -> Option<&Obj> {
+        // SAFETY: objects of words reachable by the VM haven't been collected
+        self.ptr().map(|ptr| unsafe { &(*ptr).obj })
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        if self.bits & QNAN != QNAN {
+            Some(f64::from_bits(self.bits))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.bits {
+            x if x == QNAN | TAG_TRUE => Some(true),
+            x if x == QNAN | TAG_FALSE => Some(false),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self.obj() {
+            Some(Obj::STRING(x)) => Some(x),
+            _ => None
+        }
+    }
+
+    pub fn as_native(&self) -> Option<&Native> {
+        match self.obj() {
+            Some(Obj::NATIVE(x)) => Some(x),
+            _ => None
+        }
+    }
+
+    pub fn as_userdata(&self) -> Option<&Userdata> {
+        match self.obj() {
+            Some(Obj::USERDATA(x)) => Some(x),
+            _ => None
+        }
+    }
+
+    // conditional jumps only treat `false` as false
+    pub fn is_false(&self) -> bool {
+        self.bits == QNAN | TAG_FALSE
+    }
+
+    pub fn to_value(&self) -> Value {
+        if let Some(x) = self.as_float() {
+            return Value::FLOAT(x);
+        }
+        if let Some(x) = self.as_bool() {
+            return Value::BOOL(x);
+        }
+        match self.obj() {
+            Some(Obj::STRING(x)) => Value::STRING(x.clone()),
+            Some(Obj::NATIVE(x)) => Value::NATIVE(x.clone()),
+            Some(Obj::USERDATA(x)) => Value::USERDATA(x.clone()),
+            None => Value::NIL
+        }
+    }
+
+    pub fn repr(&self) -> String {
+        self.to_value().repr()
+    }
+}
+
+impl PartialEq for NanBox {
+    fn eq(&self, other: &NanBox) -> bool {
+        if let (Some(a), Some(b)) = (self.as_float(), other.as_float()) {
+            return a == b;
+        }
+        if let (Some(a), Some(b)) = (self.as_str(), other.as_str()) {
+            return a == b;
+        }
+        if let (Some(a), Some(b)) = (self.as_native(), other.as_native()) {
+            return a == b;
+        }
+        if let (Some(a), Some(b)) = (self.as_userdata(), other.as_userdata()) {
+            return a == b;
+        }
+        self.bits == other.bits
+    }
+}
+
+impl PartialOrd for NanBox {
+    fn partial_cmp(&self, other: &NanBox) -> Option<Ordering> {
+        if let (Some(a), Some(b)) = (self.as_float(), other.as_float()) {
+            return a.partial_cmp(&b);
+        }
+        if let (Some(a), Some(b)) = (self.as_str(), other.as_str()) {
+            return a.partial_cmp(b);
+        }
+        self.to_value().partial_cmp(&other.to_value())
+    }
+}
+
+// `fast` computes the result for two numbers, None falls back to the `Value` impl (e.g. to report `1 / 0`)
+macro_rules! operator {
+    ($trait:ident, $method:ident, $fast:expr) => {
+        impl $trait for NanBox {
+            type Output = Result<Value, Error>;
+
+            fn $method(self, right: NanBox) -> Result<Value, Error> {
+                if let (Some(a), Some(b)) = (self.as_float(), right.as_float()) {
+                    let fast: fn(f64, f64) -> Option<f64> = $fast;
+                    if let Some(x) = fast(a, b) {
+                        return Ok(Value::FLOAT(x));
+                    }
+                }
+                $trait::$method(self.to_value(), right.to_value())
+            }
+        }
+    }
+}
+
+operator!(Sub, sub, |a, b| Some(a - b));
+operator!(Mul, mul, |a, b| Some(a * b));
+operator!(Div, div, |a, b| if b == 0.0 { None } else { Some(a / b) });
+operator!(Rem, rem, |a, b| if b == 0.0 { None } else { Some(a % b) });
+operator!(BitOr, bitor, |_, _| None);
+operator!(BitAnd, bitand, |_, _| None);
+
+impl Add for NanBox {
+    type Output = Result<Value, Error>;
+
+    fn add(self, right: NanBox) -> Result<Value, Error> {
+        if let (Some(a), Some(b)) = (self.as_float(), right.as_float()) {
+            return Ok(Value::FLOAT(a + b));
+        }
+        if let (Some(a), Some(b)) = (self.as_str(), right.as_str()) {
+            return Ok(Value::STRING(format!("{}{}", a, b)));
+        }
+        self.to_value() + right.to_value()
+    }
+}
+
+impl Neg for NanBox {
+    type Output = Result<Value, Error>;
+
+    fn neg(self) -> Result<Value, Error> {
+        match self.as_float() {
+            Some(x) => Ok(Value::FLOAT(-x)),
+            None => -self.to_value()
+        }
+    }
+}
+
+impl fmt::Display for NanBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.as_str() {
+            Some(x) => write!(f, "{}", x),
+            None => write!(f, "{}", self.to_value())
+        }
+    }
+}
+
+impl fmt::Debug for NanBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_value())
+    }
+}
@@ -0,0 +1,133 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::Error;
+use crate::convert::FromValue;
+use crate::sandbox::Capability;
+use crate::value::Value;
+use crate::vm::VM;
+
+// functions of the host program callable from scripts, registered with `VM::register_native`
+// arguments arrive as `Value`s, the result is moved onto the heap of the VM. a native reports
+// failures with `error` or the argument helpers below, the VM adds the line of the call
+
+pub type NativeFn = fn(&mut VM, &[Value]) -> Result<Value, Error>;
+
+// number of arguments a native takes
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    FIXED(usize),
+    VARIADIC(usize), // at least this many
+}
+
+impl From<usize> for Arity {
+    fn from(n: usize) -> Self {
+        Arity::FIXED(n)
+    }
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match self {
+            Arity::FIXED(x) => n == *x,
+            Arity::VARIADIC(x) => n >= *x,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::FIXED(1) => write!(f, "1 argument"),
+            Arity::FIXED(x) => write!(f, "{} arguments", x),
+            Arity::VARIADIC(1) => write!(f, "at least 1 argument"),
+            Arity::VARIADIC(x) => write!(f, "at least {} arguments", x),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Native {
+    pub name: String,
+    pub arity: Arity,
+    pub function: NativeFn,
+    pub capability: Option<Capability>, // of its side effect, checked by the VM before every call
+}
+
+impl PartialEq for Native {
+    fn eq(&self, other: &Native) -> bool {
+        self.name == other.name && std::ptr::fn_addr_eq(self.function, other.function)
+    }
+}
+
+// natives are only equal or unordered
+impl PartialOrd for Native {
+    fn partial_cmp(&self, other: &Native) -> Option<Ordering> {
+        if self == other { Some(Ordering::Equal) } else { None }
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+// failure of a native, reported at the line of the call
+pub fn error(message: impl Into<String>) -> Error {
+    Error::RUNTIME_ERROR(message.into(), 0)
+}
+
+fn mismatch(expected: &str, args: &[Value], i: usize) -> Error {
+    match args.get(i) {
+        Some(x) => error(format!("TypeError: expected {} as argument {}, got {}", expected, i + 1, x.type_name())),
+        None => error(format!("TypeError: missing argument {}, expected {}", i + 1, expected))
+    }
+}
+
+// argument `i` of a native, counted from 0
+pub fn number(args: &[Value], i: usize) -> Result<f64, Error> {
+    match args.get(i) {
+        Some(Value::FLOAT(x)) => Ok(*x),
+        _ => Err(mismatch("number", args, i))
+    }
+}
+
+pub fn string(args: &[Value], i: usize) -> Result<&str, Error> {
+    match args.get(i) {
+        Some(Value::STRING(x)) => Ok(x),
+        _ => Err(mismatch("string", args, i))
+    }
+}
+
+pub fn boolean(args: &[Value], i: usize) -> Result<bool, Error> {
+    match args.get(i) {
+        Some(Value::BOOL(x)) => Ok(*x),
+        _ => Err(mismatch("bool", args, i))
+    }
+}
+
+// argument `i` converted to a Rust type
+pub fn arg<T: FromValue>(args: &[Value], i: usize) -> Result<T, Error> {
+    match args.get(i) {
+        Some(x) => T::from_value(x.clone()),
+        None => Err(error(format!("TypeError: missing argument {}", i + 1)))
+    }
+}
+
+// error returned by native `name` as a runtime error of the call at `line`
+pub(crate) fn at_line(error: Error, name: &str, line: usize) -> Error {
+    let message = match error {
+        Error::RUNTIME_ERROR(message, _) | Error::COMPILE_ERROR(message, _) => message,
+        Error::DIVIDE_BY_ZERO => "DivideByZero Error".to_string(),
+        Error::SIGNAL => format!("TypeError: Unsupported operand types in `{}`", name),
+        Error::FILE_NOT_FOUND => format!("FileNotFound: file could not be found in `{}`", name),
+        Error::IO_ERROR => format!("IOError: `{}` failed", name),
+        // a limit reached inside the host is still a limit
+        Error::LIMIT_EXCEEDED(limit, _) => return Error::LIMIT_EXCEEDED(limit, line),
+        Error::INTERRUPTED(_) => return Error::INTERRUPTED(line),
+        Error::PERMISSION_DENIED(capability, resource, _) => return Error::PERMISSION_DENIED(capability, resource, line),
+    };
+    Error::RUNTIME_ERROR(message, line)
+}
@@ -0,0 +1,202 @@
+use crate::ast::*;
+use crate::value::Value;
+
+// constant folding and algebraic simplification on the syntax tree, enabled with `-O1`
+// folding evaluates operators with the `Value` impls the VM uses, operations which fail (`1/0`,
+// `-"a"`) are left alone so they still fail at runtime with line information
+
+// folded strings longer than this stay a runtime `MUL`, so `"a" * 1e9` doesn't bloat the chunk
+const MAX_FOLDED_STRING: usize = 4096;
+
+pub fn fold(statements: &mut [Stmt]) {
+    for stmt in statements.iter_mut() {
+        fold_stmt(stmt);
+    }
+}
+
+fn fold_block(block: &mut Block) {
+    fold(&mut block.statements);
+}
+
+fn fold_stmt(stmt: &mut Stmt) {
+    match &mut stmt.kind {
+        StmtKind::VAR(_, Some(expr)) | StmtKind::PRINT(expr) | StmtKind::EXPRESSION(expr) => fold_expr(expr),
+        StmtKind::BLOCK(block) => fold_block(block),
+        StmtKind::IF(condition, then, otherwise) => {
+            fold_expr(condition);
+            fold_block(then);
+            if let Some(otherwise) = otherwise {
+                fold_block(otherwise);
+            }
+        },
+        StmtKind::WHILE(condition, body) => {
+            fold_expr(condition);
+            fold_block(body);
+        },
+        StmtKind::VAR(_, None) | StmtKind::BREAK | StmtKind::CONTINUE => {}
+    }
+}
+
+fn constant(expr: &Expr) -> Option<Value> {
+    match &expr.kind {
+        ExprKind::NUMBER(x) => Some(Value::FLOAT(*x)),
+        ExprKind::STRING(x) => Some(Value::STRING(x.clone())),
+        ExprKind::BOOL(x) => Some(Value::BOOL(*x)),
+        ExprKind::NIL => Some(Value::NIL),
+        _ => None
+    }
+}
+
+fn literal(value: Value) -> Option<ExprKind> {
+    match value {
+        Value::FLOAT(x) => Some(ExprKind::NUMBER(x)),
+        Value::STRING(x) if x.len() > MAX_FOLDED_STRING => None,
+        Value::STRING(x) => Some(ExprKind::STRING(x)),
+        Value::BOOL(x) => Some(ExprKind::BOOL(x)),
+        Value::NIL => Some(ExprKind::NIL),
+        Value::NATIVE(_) | Value::USERDATA(_) => None,
+    }
+}
+
+// compares bits, so `0` and `-0` are different numbers
+fn is_number(expr: &Expr, x: f64) -> bool {
+    matches!(expr.kind, ExprKind::NUMBER(n) if n.to_bits() == x.to_bits())
+}
+
+// expressions which evaluate to a number or fail, e.g. `a - b` but not `a + b` which may concatenate strings
+fn numeric(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::NUMBER(_) | ExprKind::UNARY(UnaryOp::NEGATE, _) => true,
+        ExprKind::BINARY(op, _, _) => matches!(op, BinaryOp::SUB | BinaryOp::DIV | BinaryOp::REM),
+        _ => false
+    }
+}
+
+// expressions which evaluate to a bool or fail
+fn boolean(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::BOOL(_) | ExprKind::UNARY(UnaryOp::NOT, _) => true,
+        ExprKind::BINARY(op, _, _) => !matches!(op, BinaryOp::ADD | BinaryOp::SUB | BinaryOp::MUL | BinaryOp::DIV | BinaryOp::REM),
+        _ => false
+    }
+}
+
+// length of the string `a * b` repeats, 0 if it isn't a repetition
+fn repeated_len(a: &Value, b: &Value) -> usize {
+    match (a, b) {
+        (Value::STRING(x), Value::FLOAT(n)) | (Value::FLOAT(n), Value::STRING(x)) => x.len().saturating_mul(*n as usize),
+        _ => 0
+    }
+}
+
+fn binary(op: BinaryOp, a: Value, b: Value) -> Option<Value> {
+    match op {
+        BinaryOp::ADD => (a + b).ok(),
+        BinaryOp::SUB => (a - b).ok(),
+        // too long to fold anyway, checked before building a string which might not fit in memory
+        BinaryOp::MUL if repeated_len(&a, &b) > MAX_FOLDED_STRING => None,
+        BinaryOp::MUL => (a * b).ok(),
+        BinaryOp::DIV => (a / b).ok(),
+        BinaryOp::REM => (a % b).ok(),
+        BinaryOp::EQUAL => Some(Value::BOOL(a == b)),
+        BinaryOp::NOT_EQUAL => Some(Value::BOOL(a != b)),
+        BinaryOp::LESS => Some(Value::BOOL(a < b)),
+        BinaryOp::GREATER => Some(Value::BOOL(a > b)),
+        // compiled as negated `GREATER` and `LESS`, which differs from `<=` and `>=` for NaN
+        #[allow(clippy::neg_cmp_op_on_partial_ord)]
+        BinaryOp::LESS_EQUAL => Some(Value::BOOL(!(a > b))),
+        #[allow(clippy::neg_cmp_op_on_partial_ord)]
+        BinaryOp::GREATER_EQUAL => Some(Value::BOOL(!(a < b))),
+    }
+}
+
+// `left op right` where one operand is an identity element simplifies to the other one, Some(true)
+// for the lhs and Some(false) for the rhs. only operands known to be numbers are kept alone, `"a" + 0`
+// must still fail at runtime. the identity of addition is `-0`, `-0 + 0` is `0`
+fn simplify(op: BinaryOp, left: &Expr, right: &Expr) -> Option<bool> {
+    match op {
+        BinaryOp::ADD if is_number(right, -0.0) && numeric(left) => Some(true),
+        BinaryOp::ADD if is_number(left, -0.0) && numeric(right) => Some(false),
+        BinaryOp::SUB if is_number(right, 0.0) && numeric(left) => Some(true),
+        BinaryOp::MUL | BinaryOp::DIV if is_number(right, 1.0) && numeric(left) => Some(true),
+        BinaryOp::MUL if is_number(left, 1.0) && numeric(right) => Some(false),
+        _ => None
+    }
+}
+
+fn take(expr: &mut Expr) -> Expr {
+    let span = expr.span;
+    std::mem::replace(expr, Expr {kind: ExprKind::NIL, span})
+}
+
+fn fold_expr(expr: &mut Expr) {
+    let span = expr.span;
+    let at = |kind: ExprKind| Expr {kind, span};
+    let folded = match &mut expr.kind {
+        ExprKind::ASSIGN(_, value) => {
+            fold_expr(value);
+            None
+        },
+        ExprKind::UNARY(op, operand) => {
+            fold_expr(operand);
+            match (op, constant(operand)) {
+                (UnaryOp::NEGATE, Some(x)) => (-x).ok().and_then(literal).map(at),
+                (UnaryOp::NOT, Some(Value::BOOL(x))) => Some(at(ExprKind::BOOL(!x))),
+                // double negation
+                (UnaryOp::NEGATE, None) => match &mut operand.kind {
+                    ExprKind::UNARY(UnaryOp::NEGATE, inner) if numeric(inner) => Some(take(inner)),
+                    _ => None
+                },
+                (UnaryOp::NOT, None) => match &mut operand.kind {
+                    ExprKind::UNARY(UnaryOp::NOT, inner) if boolean(inner) => Some(take(inner)),
+                    _ => None
+                },
+                _ => None
+            }
+        },
+        ExprKind::BINARY(op, left, right) => {
+            fold_expr(left);
+            fold_expr(right);
+            match (constant(left), constant(right)) {
+                (Some(a), Some(b)) => binary(*op, a, b).and_then(literal).map(at),
+                _ => match simplify(*op, left, right) {
+                    Some(true) => Some(take(left)),
+                    Some(false) => Some(take(right)),
+                    None => None
+                }
+            }
+        },
+        ExprKind::LOGICAL(op, left, right) => {
+            fold_expr(left);
+            fold_expr(right);
+            // lhs decides the result if it is `false` for `and`, anything but `false` for `or`
+            match (op, constant(left), constant(right)) {
+                (LogicalOp::AND, Some(Value::BOOL(false)), _) => Some(take(left)),
+                (LogicalOp::OR, Some(a), _) if a != Value::BOOL(false) => Some(take(left)),
+                (LogicalOp::AND, Some(a), Some(b)) => (a & b).ok().and_then(literal).map(at),
+                (LogicalOp::OR, Some(a), Some(b)) => (a | b).ok().and_then(literal).map(at),
+                _ => None
+            }
+        },
+        ExprKind::GET(object, _) => {
+            fold_expr(object);
+            None
+        },
+        ExprKind::SET(object, _, value) => {
+            fold_expr(object);
+            fold_expr(value);
+            None
+        },
+        ExprKind::CALL(callee, args) | ExprKind::INVOKE(callee, _, args) => {
+            fold_expr(callee);
+            for arg in args.iter_mut() {
+                fold_expr(arg);
+            }
+            None
+        },
+        _ => None
+    };
+    if let Some(folded) = folded {
+        *expr = folded;
+    }
+}
@@ -0,0 +1,324 @@
+use crate::Error;
+use crate::ast::*;
+use crate::scanner::Scanner;
+use crate::token::{Token, TokenType};
+
+#[allow(non_camel_case_types)]
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy)]
+pub enum Precendence {
+    NONE,
+    ASSIGNMENT,  // =
+    OR,          // or
+    AND,         // and
+    EQUALITY,    // == !=
+    COMPARISON,  // < > <= >=
+    TERM,        // + -
+    FACTOR,      // * / %
+    UNARY,       // ! -
+    CALL,        // () .
+    PRIMARY
+}
+
+impl Precendence {
+    // binding power of token used as infix operator
+    pub fn of(t: &TokenType) -> Self {
+        match t {
+            TokenType::LEFT_PAREN => Precendence::CALL,
+            TokenType::DOT => Precendence::CALL,
+            TokenType::AND => Precendence::AND,
+            TokenType::OR => Precendence::OR,
+            TokenType::MINUS => Precendence::TERM,
+            TokenType::PLUS => Precendence::TERM,
+            TokenType::SLASH => Precendence::FACTOR,
+            TokenType::STAR => Precendence::FACTOR,
+            TokenType::PERCENT => Precendence::FACTOR,
+            TokenType::EQUAL_EQUAL => Precendence::EQUALITY,
+            TokenType::BANG_EQUAL => Precendence::EQUALITY,
+            TokenType::LESS => Precendence::COMPARISON,
+            TokenType::GREATER => Precendence::COMPARISON,
+            TokenType::LESS_EQUAL => Precendence::COMPARISON,
+            TokenType::GREATER_EQUAL => Precendence::COMPARISON,
+            TokenType::NUMBER => Precendence::NONE,
+            _ => Precendence::NONE
+        }
+    }
+}
+
+// Pratt parser turning source code into a syntax tree
+pub struct Parser {
+    scanner: Scanner,
+    current: Token,
+    previous: Token,
+    loop_depth: usize, // number of enclosing `while` loops, `break` and `continue` are only valid inside one
+}
+
+impl Parser {
+    pub fn new(source: String) -> Self {
+        Parser {scanner: Scanner::new(source), current: Token::default(), previous: Token::default(), loop_depth: 0}
+    }
+
+    // helper functions
+
+    fn check_type(&self, tt: &TokenType) -> bool {
+        self.current.t == *tt
+    }
+
+    fn advance(&mut self) -> Result<(), Error> {
+        self.previous = self.current.clone();
+        self.current = self.scanner.advance()?;
+        Ok(())
+    }
+
+    fn consume(&mut self, tt: TokenType, error_message: &str) -> Result<(), Error> {
+        if self.check_type(&tt) {
+            self.advance()
+        } else {
+            Err(Error::RUNTIME_ERROR(format!("{} got `{}`", error_message, self.current), self.current.line))
+        }
+    }
+
+    // parses whole program, statements parsed before an error are kept in `statements` so tools
+    // can still work with the valid part of the source
+    pub fn program(&mut self, statements: &mut Vec<Stmt>) -> Result<(), Error> {
+        self.advance()?; // consume default
+        while !self.check_type(&TokenType::EOF) {
+            statements.push(self.declaration()?);
+        }
+        Ok(())
+    }
+
+    // parses source consisting of a single expression
+    pub fn single_expression(&mut self) -> Result<Expr, Error> {
+        self.advance()?; // consume default
+        let expr = self.expression()?;
+        if !self.check_type(&TokenType::EOF) {
+            return Err(Error::COMPILE_ERROR(format!("Unexpected `{}` after expression", self.current), self.current.line));
+        }
+        Ok(expr)
+    }
+
+    // Statements
+    fn declaration(&mut self) -> Result<Stmt, Error> {
+        match self.current.t {
+            TokenType::VAR => self.variable_declr(),
+            _ => self.statement(),
+        }
+    }
+
+    fn variable_declr(&mut self) -> Result<Stmt, Error> {
+        self.advance()?; // consume `var`
+        let span = Span::of(&self.previous);
+        self.consume(TokenType::IDENTIFIER, "Expect identifier after `var`")?;
+        let identifier = self.previous.clone();
+
+        let initializer = match self.current.t {
+            TokenType::EQUAL => {
+                self.advance()?;
+                Some(self.expression()?)
+            },
+            _ => None
+        };
+        self.consume(TokenType::SEMICOLON, "Expect `;` after statement")?;
+        Ok(Stmt {kind: StmtKind::VAR(Variable::new(identifier), initializer), span})
+    }
+
+    fn statement(&mut self) -> Result<Stmt, Error> {
+        let span = Span::of(&self.current);
+        let kind = match self.current.t {
+            TokenType::PRINT => {
+                self.advance()?; // consume `print` token
+                let expr = self.expression()?; // expression to be printed
+                self.consume(TokenType::SEMICOLON, "Expect `;` after statement")?;
+                StmtKind::PRINT(expr)
+            },
+            TokenType::LEFT_BRACE => StmtKind::BLOCK(self.block()?),
+            TokenType::IF => self.if_stmt()?,
+            TokenType::WHILE => self.while_loop()?,
+            TokenType::BREAK | TokenType::CONTINUE => {
+                self.advance()?;
+                let keyword = self.previous.clone();
+                if self.loop_depth == 0 {
+                    return Err(Error::COMPILE_ERROR(format!("SyntaxError: `{}` outside of loop", keyword), keyword.line));
+                }
+                self.consume(TokenType::SEMICOLON, "Expect `;` after statement")?;
+                if keyword.t == TokenType::BREAK { StmtKind::BREAK } else { StmtKind::CONTINUE }
+            },
+            // expression statements
+            _ => {
+                let expr = self.expression()?;
+                self.consume(TokenType::SEMICOLON, "Expect `;` after statement")?;
+                StmtKind::EXPRESSION(expr)
+            }
+        };
+        Ok(Stmt {kind, span})
+    }
+
+    fn block(&mut self) -> Result<Block, Error> {
+        self.consume(TokenType::LEFT_BRACE, "Expect `{` at the start of block statement")?; // consume `{`
+        let mut statements = vec![];
+        while self.current.t != TokenType::RIGHT_BRACE && self.current.t != TokenType::EOF {
+            statements.push(self.declaration()?);
+        }
+        let end = Span::of(&self.current);
+        self.consume(TokenType::RIGHT_BRACE, "Expect `}` after block statement")?;
+        Ok(Block {statements, end})
+    }
+
+    fn if_stmt(&mut self) -> Result<StmtKind, Error> {
+        self.advance()?; // consume `if`
+        let condition = self.expression()?;
+        let then = self.block()?;
+        let otherwise = if self.current.t == TokenType::ELSE {
+            self.advance()?; // consume `else`
+            Some(self.block()?)
+        } else {
+            None
+        };
+        Ok(StmtKind::IF(condition, then, otherwise))
+    }
+
+    fn while_loop(&mut self) -> Result<StmtKind, Error> {
+        self.advance()?; // consume `while`
+        let condition = self.expression()?;
+        self.loop_depth += 1;
+        let body = self.block();
+        self.loop_depth -= 1;
+        Ok(StmtKind::WHILE(condition, body?))
+    }
+
+    // Expressions
+    fn expression(&mut self) -> Result<Expr, Error> {
+        self.parse_precendence(Precendence::ASSIGNMENT)
+    }
+
+    fn parse_precendence(&mut self, prec: Precendence) -> Result<Expr, Error> {
+        self.advance()?;
+        let token = self.previous.clone();
+        let span = Span::of(&token);
+        // prefix
+        let mut expr = match token.t {
+            TokenType::LEFT_PAREN => {
+                let expr = self.expression()?;
+                self.consume(TokenType::RIGHT_PAREN, "Expect `)` after expression")?;
+                expr
+            },
+            TokenType::MINUS | TokenType::BANG => {
+                // operand binds tighter than any binary operator
+                let operand = self.parse_precendence(Precendence::UNARY)?;
+                let op = if token.t == TokenType::MINUS { UnaryOp::NEGATE } else { UnaryOp::NOT };
+                Expr {kind: ExprKind::UNARY(op, Box::new(operand)), span}
+            },
+            TokenType::NUMBER => match token.lexeme.parse() {
+                Ok(x) => Expr {kind: ExprKind::NUMBER(x), span},
+                Err(_) => return Err(Error::COMPILE_ERROR(format!("TypeError: cannot convert {} to float", token), token.line))
+            },
+            TokenType::STRING => Expr {kind: ExprKind::STRING(token.lexeme.clone()), span},
+            TokenType::TRUE => Expr {kind: ExprKind::BOOL(true), span},
+            TokenType::FALSE => Expr {kind: ExprKind::BOOL(false), span},
+            TokenType::NIL => Expr {kind: ExprKind::NIL, span},
+            TokenType::IDENTIFIER => self.variable(prec <= Precendence::ASSIGNMENT)?,
+            _ => {
+                return Err(Error::RUNTIME_ERROR(format!("Expected expression got `{}`", token), token.line));
+            }
+        };
+        // infix
+        while prec < Precendence::of(&self.current.t) {
+            self.advance()?;
+            expr = match self.previous.t {
+                TokenType::LEFT_PAREN => self.call(expr)?,
+                TokenType::DOT => self.property(expr, prec <= Precendence::ASSIGNMENT)?,
+                _ => self.binary(expr)?
+            };
+        }
+        Ok(expr)
+    }
+
+    fn variable(&mut self, can_assign: bool) -> Result<Expr, Error> {
+        let token = self.previous.clone();
+        let span = Span::of(&token);
+        if self.current.t != TokenType::EQUAL {
+            return Ok(Expr {kind: ExprKind::VARIABLE(Variable::new(token)), span});
+        }
+        if !can_assign {
+            return Err(Error::COMPILE_ERROR("TypeError: Invalid target for variable assignment".into(), token.line));
+        }
+        self.advance()?; // consume `=`
+        let value = self.expression()?;
+        Ok(Expr {kind: ExprKind::ASSIGN(Variable::new(token), Box::new(value)), span})
+    }
+
+    // `(` is the previous token, `callee` the already parsed expression before it
+    fn call(&mut self, callee: Expr) -> Result<Expr, Error> {
+        let span = Span::of(&self.previous);
+        let args = self.arguments()?;
+        Ok(Expr {kind: ExprKind::CALL(Box::new(callee), args), span})
+    }
+
+    // `.` is the previous token, a name followed by `(` is a method call and by `=` an assignment
+    fn property(&mut self, object: Expr, can_assign: bool) -> Result<Expr, Error> {
+        self.consume(TokenType::IDENTIFIER, "Expect property name after `.`")?;
+        let name = self.previous.clone();
+        let span = Span::of(&name);
+        let object = Box::new(object);
+        let kind = match self.current.t {
+            TokenType::LEFT_PAREN => {
+                self.advance()?; // consume `(`
+                ExprKind::INVOKE(object, name, self.arguments()?)
+            },
+            TokenType::EQUAL if can_assign => {
+                self.advance()?; // consume `=`
+                ExprKind::SET(object, name, Box::new(self.expression()?))
+            },
+            _ => ExprKind::GET(object, name)
+        };
+        Ok(Expr {kind, span})
+    }
+
+    // arguments of a call after its `(`
+    fn arguments(&mut self) -> Result<Vec<Expr>, Error> {
+        let mut args = vec![];
+        if !self.check_type(&TokenType::RIGHT_PAREN) {
+            loop {
+                args.push(self.expression()?);
+                if !self.check_type(&TokenType::COMMA) {
+                    break;
+                }
+                self.advance()?; // consume `,`
+            }
+        }
+        self.consume(TokenType::RIGHT_PAREN, "Expect `)` after arguments")?;
+        Ok(args)
+    }
+
+    // operator is the previous token, `left` its already parsed lhs operand
+    fn binary(&mut self, left: Expr) -> Result<Expr, Error> {
+        let token = self.previous.clone();
+        let span = Span::of(&token);
+
+        // rhs operand only takes operators binding tighter than this one, so operators of the same
+        // precendence associate to the left
+        let prec = Precendence::of(&token.t);
+        let right = Box::new(self.parse_precendence(prec)?);
+        let left = Box::new(left);
+
+        let kind = match token.t {
+            TokenType::PLUS => ExprKind::BINARY(BinaryOp::ADD, left, right),
+            TokenType::MINUS => ExprKind::BINARY(BinaryOp::SUB, left, right),
+            TokenType::STAR => ExprKind::BINARY(BinaryOp::MUL, left, right),
+            TokenType::SLASH => ExprKind::BINARY(BinaryOp::DIV, left, right),
+            TokenType::PERCENT => ExprKind::BINARY(BinaryOp::REM, left, right),
+            TokenType::EQUAL_EQUAL => ExprKind::BINARY(BinaryOp::EQUAL, left, right),
+            TokenType::BANG_EQUAL => ExprKind::BINARY(BinaryOp::NOT_EQUAL, left, right),
+            TokenType::LESS => ExprKind::BINARY(BinaryOp::LESS, left, right),
+            TokenType::LESS_EQUAL => ExprKind::BINARY(BinaryOp::LESS_EQUAL, left, right),
+            TokenType::GREATER => ExprKind::BINARY(BinaryOp::GREATER, left, right),
+            TokenType::GREATER_EQUAL => ExprKind::BINARY(BinaryOp::GREATER_EQUAL, left, right),
+            TokenType::AND => ExprKind::LOGICAL(LogicalOp::AND, left, right),
+            TokenType::OR => ExprKind::LOGICAL(LogicalOp::OR, left, right),
+            _ => {
+                return Err(Error::RUNTIME_ERROR(format!("Invalid infix operator `{}`", token), token.line));
+            }
+        };
+        Ok(Expr {kind, span})
+    }
+}
@@ -0,0 +1,264 @@
+use crate::chunk::{Chunk, OpCode};
+
+// peephole optimizer on generated bytecode, enabled with `-O1`, superinstructions with `-O2`
+// rewrites are applied until none matches, the instructions are then compacted and every jump
+// target, line and debug info offset is moved to the new position of its instruction
+
+fn target(byte: &OpCode) -> Option<usize> {
+    match byte {
+        OpCode::IF(x) | OpCode::IFN(x) | OpCode::JMP(x) | OpCode::POP_JUMP_IF_FALSE(x) => Some(*x),
+        _ => None
+    }
+}
+
+fn retarget(byte: &OpCode, jaddr: usize) -> OpCode {
+    match byte {
+        OpCode::IF(_) => OpCode::IF(jaddr),
+        OpCode::IFN(_) => OpCode::IFN(jaddr),
+        OpCode::POP_JUMP_IF_FALSE(_) => OpCode::POP_JUMP_IF_FALSE(jaddr),
+        _ => OpCode::JMP(jaddr),
+    }
+}
+
+// offsets of the instructions which may run after instruction `i`
+fn successors(code: &[Option<OpCode>], i: usize) -> Vec<usize> {
+    match &code[i] {
+        Some(OpCode::RETURN) => vec![],
+        Some(OpCode::JMP(x)) => vec![*x],
+        Some(byte @ (OpCode::IF(_) | OpCode::IFN(_) | OpCode::POP_JUMP_IF_FALSE(_))) => vec![i + 1, target(byte).unwrap()],
+        _ => vec![i + 1]
+    }
+}
+
+struct Peephole {
+    code: Vec<Option<OpCode>>, // None => instruction was removed
+}
+
+impl Peephole {
+    // number of jumps to each offset
+    fn jumps_to(&self) -> Vec<usize> {
+        let mut count = vec![0; self.code.len() + 1];
+        for byte in self.code.iter().flatten() {
+            if let Some(x) = target(byte) {
+                count[self.live(x)] += 1;
+            }
+        }
+        count
+    }
+
+    // next instruction at or after `i` which was not removed
+    fn live(&self, mut i: usize) -> usize {
+        while i < self.code.len() && self.code[i].is_none() {
+            i += 1;
+        }
+        i
+    }
+
+    // `EQUAL; BANG` => `NOT_EQUAL` and likewise for `<=` and `>=`
+    fn fuse_comparisons(&mut self) -> bool {
+        let jumps = self.jumps_to();
+        let mut changed = false;
+        for i in 0..self.code.len() {
+            let next = self.live(i + 1);
+            if next >= self.code.len() || jumps[next] > 0 || !matches!(self.code[next], Some(OpCode::BANG)) {
+                continue;
+            }
+            let fused = match self.code[i] {
+                Some(OpCode::EQUAL) => OpCode::NOT_EQUAL,
+                Some(OpCode::GREATER) => OpCode::LESS_EQUAL,
+                Some(OpCode::LESS) => OpCode::GREATER_EQUAL,
+                _ => continue
+            };
+            self.code[i] = Some(fused);
+            self.code[next] = None;
+            changed = true;
+        }
+        changed
+    }
+
+    // `IF(t); POP` with a `POP` at `t` which is only reached by this jump => `POP_JUMP_IF_FALSE(t + 1)`
+    fn fuse_conditions(&mut self) -> bool {
+        let jumps = self.jumps_to();
+        let mut changed = false;
+        for i in 0..self.code.len() {
+            let t = match self.code[i] {
+                Some(OpCode::IF(t)) => self.live(t),
+                _ => continue
+            };
+            let pop = self.live(i + 1);
+            if t >= self.code.len() || t <= pop || jumps[pop] > 0 || jumps[t] > 1 {
+                continue;
+            }
+            if !matches!(self.code[pop], Some(OpCode::POP)) || !matches!(self.code[t], Some(OpCode::POP)) {
+                continue;
+            }
+            // the `POP` at `t` must not be reached by falling through from the instruction before it
+            let falls_through = match (0..t).rev().find(|x| self.code[*x].is_some()) {
+                Some(x) => !matches!(self.code[x], Some(OpCode::JMP(_)) | Some(OpCode::RETURN)),
+                None => true
+            };
+            if falls_through {
+                continue;
+            }
+            self.code[i] = Some(OpCode::POP_JUMP_IF_FALSE(t + 1));
+            self.code[pop] = None;
+            self.code[t] = None;
+            changed = true;
+        }
+        changed
+    }
+
+    // next `n` remaining instructions starting at `i`, None if any but the first is a jump target
+    fn window(&self, i: usize, n: usize, jumps: &[usize]) -> Option<Vec<usize>> {
+        let mut window = vec![i];
+        while window.len() < n {
+            let next = self.live(window[window.len() - 1] + 1);
+            if next >= self.code.len() || jumps[next] > 0 {
+                return None;
+            }
+            window.push(next);
+        }
+        Some(window)
+    }
+
+    // `GET_LOCAL(s); CONSTANT(k); ADD; SET_LOCAL(s); POP` => `INC_LOCAL(s, k)`
+    // `GET_LOCAL(a); GET_LOCAL(b); LESS` => `LESS_LOCAL_LOCAL(a, b)`
+    // `GET_LOCAL(a); CONSTANT(k); LESS` => `LESS_LOCAL_CONSTANT(a, k)`
+    fn fuse_superinstructions(&mut self) -> bool {
+        let jumps = self.jumps_to();
+        let mut changed = false;
+        for i in 0..self.code.len() {
+            if !matches!(self.code[i], Some(OpCode::GET_LOCAL(_))) {
+                continue;
+            }
+            if let Some(w) = self.window(i, 5, &jumps) {
+                if let [Some(OpCode::GET_LOCAL(s)), Some(OpCode::CONSTANT(k)), Some(OpCode::ADD), Some(OpCode::SET_LOCAL(t)), Some(OpCode::POP)] =
+                    [&self.code[w[0]], &self.code[w[1]], &self.code[w[2]], &self.code[w[3]], &self.code[w[4]]] {
+                    if s == t {
+                        self.code[i] = Some(OpCode::INC_LOCAL(*s, *k));
+                        for x in &w[1..] {
+                            self.code[*x] = None;
+                        }
+                        changed = true;
+                        continue;
+                    }
+                }
+            }
+            if let Some(w) = self.window(i, 3, &jumps) {
+                let fused = match [&self.code[w[0]], &self.code[w[1]], &self.code[w[2]]] {
+                    [Some(OpCode::GET_LOCAL(a)), Some(OpCode::GET_LOCAL(b)), Some(OpCode::LESS)] => OpCode::LESS_LOCAL_LOCAL(*a, *b),
+                    [Some(OpCode::GET_LOCAL(a)), Some(OpCode::CONSTANT(k)), Some(OpCode::LESS)] => OpCode::LESS_LOCAL_CONSTANT(*a, *k),
+                    _ => continue
+                };
+                self.code[i] = Some(fused);
+                for x in &w[1..] {
+                    self.code[*x] = None;
+                }
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // jumps to an unconditional jump go straight to its target, jumps to the next instruction are removed
+    fn thread_jumps(&mut self) -> bool {
+        let mut changed = false;
+        for i in 0..self.code.len() {
+            let byte = match &self.code[i] {
+                Some(byte) => byte.clone(),
+                None => continue
+            };
+            let mut jaddr = match target(&byte) {
+                Some(x) => self.live(x),
+                None => continue
+            };
+            // bounded, jump chains may form a cycle (`while true {}`)
+            for _ in 0..self.code.len() {
+                match self.code.get(jaddr) {
+                    Some(Some(OpCode::JMP(x))) if self.live(*x) != jaddr => jaddr = self.live(*x),
+                    _ => break
+                }
+            }
+            if let OpCode::JMP(_) = byte {
+                if jaddr == self.live(i + 1) {
+                    self.code[i] = None;
+                    changed = true;
+                    continue;
+                }
+            }
+            if Some(jaddr) != target(&byte) {
+                self.code[i] = Some(retarget(&byte, jaddr));
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // removes instructions which can't be reached from the start of the chunk
+    fn remove_dead_code(&mut self) -> bool {
+        let mut reachable = vec![false; self.code.len()];
+        let mut pending = vec![self.live(0)];
+        while let Some(i) = pending.pop() {
+            let i = self.live(i);
+            if i >= self.code.len() || reachable[i] {
+                continue;
+            }
+            reachable[i] = true;
+            pending.extend(successors(&self.code, i));
+        }
+        let mut changed = false;
+        for (i, reachable) in reachable.into_iter().enumerate() {
+            if !reachable && self.code[i].is_some() {
+                self.code[i] = None;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+// `superinstructions` enables fusing of the instruction sequences common in loops
+pub fn optimize(chunk: &mut Chunk, superinstructions: bool) {
+    let lines = chunk.instruction_lines();
+    let mut peephole = Peephole {code: chunk.code.drain(..).map(Some).collect()};
+    loop {
+        let mut changed = peephole.fuse_comparisons();
+        changed |= peephole.fuse_conditions();
+        changed |= peephole.thread_jumps();
+        changed |= peephole.remove_dead_code();
+        if superinstructions {
+            changed |= peephole.fuse_superinstructions();
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    // new offset of each instruction, removed instructions map to the next remaining one
+    let mut offsets = vec![0; peephole.code.len() + 1];
+    let mut n = 0;
+    for (i, byte) in peephole.code.iter().enumerate() {
+        offsets[i] = n;
+        if byte.is_some() {
+            n += 1;
+        }
+    }
+    offsets[peephole.code.len()] = n;
+    let offset = |x: usize| if x == usize::MAX { x } else { offsets[x.min(offsets.len() - 1)] };
+
+    let code = peephole.code.into_iter().zip(lines).filter_map(|(byte, line)| {
+        byte.map(|byte| match target(&byte) {
+            Some(x) => (retarget(&byte, offset(x)), line),
+            None => (byte, line)
+        })
+    }).collect();
+    chunk.set_code(code);
+    for local in chunk.locals.iter_mut() {
+        local.start = offset(local.start);
+        local.end = offset(local.end);
+    }
+    for scope in chunk.scopes.iter_mut() {
+        scope.start = offset(scope.start);
+        scope.end = offset(scope.end);
+    }
+}
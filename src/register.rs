@@ -0,0 +1,414 @@
+use crate::ast::*;
+use crate::intern::Interner;
+use crate::value::Value;
+
+// register based instruction set, an alternative backend to the stack machine in `chunk`/`vm`
+// locals live in fixed registers, temporaries are allocated above them like a stack and freed
+// after every statement. operands are either a register or a constant so literals need no load
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    REG(usize),
+    CONST(usize), // address in `RegisterChunk::values`
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone)]
+pub enum Instr {
+    MOVE(usize, Operand), // dst, src
+    DEFINE_GLOBAL(usize, Operand), // slot in `RegisterChunk::globals`, value
+    GET_GLOBAL(usize, usize), // dst, slot
+    SET_GLOBAL(usize, Operand), // slot, value
+    NEGATE(usize, Operand),
+    NOT(usize, Operand),
+    // dst, lhs, rhs
+    ADD(usize, Operand, Operand),
+    SUB(usize, Operand, Operand),
+    MUL(usize, Operand, Operand),
+    DIV(usize, Operand, Operand),
+    REM(usize, Operand, Operand),
+    EQUAL(usize, Operand, Operand),
+    NOT_EQUAL(usize, Operand, Operand),
+    LESS(usize, Operand, Operand),
+    LESS_EQUAL(usize, Operand, Operand),
+    GREATER(usize, Operand, Operand),
+    GREATER_EQUAL(usize, Operand, Operand),
+    AND(usize, Operand, Operand),
+    OR(usize, Operand, Operand),
+    CALL(usize, usize, usize), // dst, register of the callee followed by the arguments, number of arguments
+    GET_PROPERTY(usize, Operand, Operand), // dst, object, name
+    SET_PROPERTY(Operand, Operand, Operand), // object, name, value
+    INVOKE(usize, usize, Operand, usize), // dst, register of the object followed by the arguments, name, number of arguments
+    PRINT(Operand),
+    JUMP(usize),
+    JUMP_IF_FALSE(Operand, usize),
+    JUMP_UNLESS_FALSE(Operand, usize), // taken for any value but `false`, like `IFN`
+    RETURN(Operand),
+}
+
+#[derive(Default, Clone)]
+pub struct RegisterChunk {
+    pub code: Vec<Instr>,
+    pub values: Vec<Value>,
+    pub globals: Interner, // names of the global variables, numbered by slot
+    pub lines: Vec<usize>, // source line of each instruction
+    pub registers: usize, // size of the register file
+}
+
+impl RegisterChunk {
+    // displays contents of the chunk
+    pub fn dissassemble(&self) {
+        for (offset, instr) in self.code.iter().enumerate() {
+            println!("{:0>4}  {:>4} {:?}", offset, self.lines[offset], instr);
+        }
+    }
+}
+
+// checks if evaluating `expr` assigns a variable
+fn assigns(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::ASSIGN(..) => true,
+        ExprKind::UNARY(_, x) => assigns(x),
+        ExprKind::BINARY(_, a, b) | ExprKind::LOGICAL(_, a, b) => assigns(a) || assigns(b),
+        ExprKind::GET(x, _) => assigns(x),
+        ExprKind::SET(object, _, value) => assigns(object) || assigns(value),
+        ExprKind::CALL(callee, args) | ExprKind::INVOKE(callee, _, args) => assigns(callee) || args.iter().any(assigns),
+        _ => false
+    }
+}
+
+// jumps of `break` statements and start of the innermost loop
+struct Loop {
+    start: usize,
+    breaks: Vec<usize>,
+}
+
+// emits register code for a resolved syntax tree
+#[derive(Default)]
+pub struct Generator {
+    chunk: RegisterChunk,
+    line: usize,
+    next: usize, // first free register
+    locals: Vec<(String, usize)>, // visible locals and their registers, innermost last
+    loops: Vec<Loop>,
+}
+
+impl Generator {
+    pub fn new() -> Self {
+        Generator::default()
+    }
+
+    fn emit(&mut self, instr: Instr, line: usize) -> usize {
+        self.line = self.line.max(line);
+        self.chunk.code.push(instr);
+        self.chunk.lines.push(self.line);
+        self.chunk.code.len() - 1
+    }
+
+    fn constant(&mut self, value: Value) -> Operand {
+        // constant pool is deduplicated, floats by bits so `0` and `-0` stay apart
+        let same = |x: &Value| match (x, &value) {
+            (Value::FLOAT(a), Value::FLOAT(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b
+        };
+        match self.chunk.values.iter().position(same) {
+            Some(i) => Operand::CONST(i),
+            None => {
+                self.chunk.values.push(value);
+                Operand::CONST(self.chunk.values.len() - 1)
+            }
+        }
+    }
+
+    fn global(&mut self, name: &str) -> usize {
+        self.chunk.globals.intern(name).0
+    }
+
+    fn alloc(&mut self) -> usize {
+        self.next += 1;
+        self.chunk.registers = self.chunk.registers.max(self.next);
+        self.next - 1
+    }
+
+    // points jump at `index` to the next instruction
+    fn patch(&mut self, index: usize) {
+        let jaddr = self.chunk.code.len();
+        match &mut self.chunk.code[index] {
+            Instr::JUMP(x) | Instr::JUMP_IF_FALSE(_, x) | Instr::JUMP_UNLESS_FALSE(_, x) => *x = jaddr,
+            _ => {}
+        }
+    }
+
+    fn local(&self, name: &str) -> usize {
+        self.locals.iter().rev().find(|x| x.0 == name).map(|x| x.1).unwrap()
+    }
+
+    pub fn program(mut self, statements: &[Stmt]) -> RegisterChunk {
+        let mut result = self.constant(Value::NIL);
+        for (i, stmt) in statements.iter().enumerate() {
+            match &stmt.kind {
+                // value of the last top-level expression statement is the result of the program
+                StmtKind::EXPRESSION(expr) if i == statements.len() - 1 => result = self.expression(expr, None),
+                _ => self.statement(stmt)
+            }
+        }
+        self.emit(Instr::RETURN(result), 0);
+        self.chunk
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        let line = stmt.span.line;
+        let mark = self.next;
+        match &stmt.kind {
+            StmtKind::VAR(variable, initializer) => {
+                match variable.resolved {
+                    Resolved::LOCAL(_) => {
+                        let register = self.alloc();
+                        match initializer {
+                            Some(expr) => self.expression_into(expr, register),
+                            None => {
+                                let nil = self.constant(Value::NIL);
+                                self.emit(Instr::MOVE(register, nil), line);
+                            }
+                        }
+                        // the local keeps its register until the end of the block
+                        self.locals.push((variable.name.lexeme.clone(), register));
+                        self.next = register + 1;
+                        return;
+                    },
+                    Resolved::GLOBAL => {
+                        let value = match initializer {
+                            Some(expr) => self.expression(expr, None),
+                            None => self.constant(Value::NIL)
+                        };
+                        let slot = self.global(&variable.name.lexeme);
+                        self.emit(Instr::DEFINE_GLOBAL(slot, value), line);
+                    }
+                }
+            },
+            StmtKind::PRINT(expr) => {
+                let value = self.expression(expr, None);
+                self.emit(Instr::PRINT(value), line);
+            },
+            StmtKind::EXPRESSION(expr) => {
+                self.expression(expr, None);
+            },
+            StmtKind::BLOCK(block) => self.block(block),
+            StmtKind::IF(condition, then, otherwise) => {
+                let condition = self.expression(condition, None);
+                let index = self.emit(Instr::JUMP_IF_FALSE(condition, 0), line);
+                self.next = mark;
+                self.block(then);
+                match otherwise {
+                    Some(otherwise) => {
+                        let exit = self.emit(Instr::JUMP(0), then.end.line);
+                        self.patch(index);
+                        self.block(otherwise);
+                        self.patch(exit);
+                    },
+                    None => self.patch(index)
+                }
+            },
+            StmtKind::WHILE(condition, body) => {
+                let start = self.chunk.code.len();
+                let condition = self.expression(condition, None);
+                let index = self.emit(Instr::JUMP_IF_FALSE(condition, 0), line);
+                self.next = mark;
+                self.loops.push(Loop {start, breaks: vec![]});
+                self.block(body);
+                self.emit(Instr::JUMP(start), body.end.line);
+                self.patch(index);
+                for i in self.loops.pop().unwrap().breaks {
+                    self.patch(i);
+                }
+            },
+            StmtKind::BREAK => {
+                let index = self.emit(Instr::JUMP(0), line);
+                self.loops.last_mut().unwrap().breaks.push(index);
+            },
+            StmtKind::CONTINUE => {
+                let start = self.loops.last().unwrap().start;
+                self.emit(Instr::JUMP(start), line);
+            }
+        }
+        // temporaries of the statement are free again
+        self.next = mark;
+    }
+
+    fn block(&mut self, block: &Block) {
+        let (locals, next) = (self.locals.len(), self.next);
+        for stmt in block.statements.iter() {
+            self.statement(stmt);
+        }
+        self.locals.truncate(locals);
+        self.next = next;
+    }
+
+    // evaluates `expr` into register `dst`
+    fn expression_into(&mut self, expr: &Expr, dst: usize) {
+        let value = self.expression(expr, Some(dst));
+        if value != Operand::REG(dst) {
+            self.emit(Instr::MOVE(dst, value), expr.span.line);
+        }
+    }
+
+    // compiles `expr` and returns where its value is, `dst` is the preferred register for the result.
+    // instructions read all operands before writing their destination, so `dst` may be an operand
+    fn expression(&mut self, expr: &Expr, dst: Option<usize>) -> Operand {
+        let line = expr.span.line;
+        let target = |g: &mut Generator| dst.unwrap_or_else(|| g.alloc());
+        match &expr.kind {
+            ExprKind::NUMBER(x) => self.constant(Value::FLOAT(*x)),
+            ExprKind::STRING(x) => self.constant(Value::STRING(x.clone())),
+            ExprKind::BOOL(x) => self.constant(Value::BOOL(*x)),
+            ExprKind::NIL => self.constant(Value::NIL),
+            ExprKind::VARIABLE(variable) => match variable.resolved {
+                Resolved::LOCAL(_) => Operand::REG(self.local(&variable.name.lexeme)),
+                Resolved::GLOBAL => {
+                    let slot = self.global(&variable.name.lexeme);
+                    let dst = target(self);
+                    self.emit(Instr::GET_GLOBAL(dst, slot), line);
+                    Operand::REG(dst)
+                }
+            },
+            ExprKind::ASSIGN(variable, value) => match variable.resolved {
+                Resolved::LOCAL(_) => {
+                    let register = self.local(&variable.name.lexeme);
+                    self.expression_into(value, register);
+                    Operand::REG(register)
+                },
+                Resolved::GLOBAL => {
+                    let value = self.expression(value, dst);
+                    let slot = self.global(&variable.name.lexeme);
+                    self.emit(Instr::SET_GLOBAL(slot, value), line);
+                    value
+                }
+            },
+            ExprKind::UNARY(op, operand) => {
+                let mark = self.next;
+                let operand = self.expression(operand, None);
+                self.next = mark;
+                let dst = target(self);
+                match op {
+                    UnaryOp::NEGATE => self.emit(Instr::NEGATE(dst, operand), line),
+                    UnaryOp::NOT => self.emit(Instr::NOT(dst, operand), line),
+                };
+                Operand::REG(dst)
+            },
+            ExprKind::BINARY(op, left, right) => {
+                let mark = self.next;
+                let mut a = self.expression(left, None);
+                if let Operand::REG(register) = a {
+                    // lhs is read after the rhs ran, a local the rhs assigns must be copied first
+                    if register < mark && assigns(right) {
+                        let copy = self.alloc();
+                        self.emit(Instr::MOVE(copy, a), line);
+                        a = Operand::REG(copy);
+                    }
+                }
+                let b = self.expression(right, None);
+                self.next = mark;
+                let dst = target(self);
+                let instr = match op {
+                    BinaryOp::ADD => Instr::ADD(dst, a, b),
+                    BinaryOp::SUB => Instr::SUB(dst, a, b),
+                    BinaryOp::MUL => Instr::MUL(dst, a, b),
+                    BinaryOp::DIV => Instr::DIV(dst, a, b),
+                    BinaryOp::REM => Instr::REM(dst, a, b),
+                    BinaryOp::EQUAL => Instr::EQUAL(dst, a, b),
+                    BinaryOp::NOT_EQUAL => Instr::NOT_EQUAL(dst, a, b),
+                    BinaryOp::LESS => Instr::LESS(dst, a, b),
+                    BinaryOp::LESS_EQUAL => Instr::LESS_EQUAL(dst, a, b),
+                    BinaryOp::GREATER => Instr::GREATER(dst, a, b),
+                    BinaryOp::GREATER_EQUAL => Instr::GREATER_EQUAL(dst, a, b),
+                };
+                self.emit(instr, line);
+                Operand::REG(dst)
+            },
+            ExprKind::LOGICAL(op, left, right) => {
+                // result is built in a fresh register, `dst` may be a local the rhs still reads
+                let result = self.alloc();
+                self.expression_into(left, result);
+                let index = match op {
+                    LogicalOp::AND => self.emit(Instr::JUMP_IF_FALSE(Operand::REG(result), 0), line),
+                    LogicalOp::OR => self.emit(Instr::JUMP_UNLESS_FALSE(Operand::REG(result), 0), line),
+                };
+                let b = self.expression(right, None);
+                match op {
+                    LogicalOp::AND => self.emit(Instr::AND(result, Operand::REG(result), b), line),
+                    LogicalOp::OR => self.emit(Instr::OR(result, Operand::REG(result), b), line),
+                };
+                self.patch(index);
+                match dst {
+                    Some(dst) => {
+                        self.emit(Instr::MOVE(dst, Operand::REG(result)), line);
+                        Operand::REG(dst)
+                    },
+                    None => Operand::REG(result)
+                }
+            },
+            ExprKind::CALL(callee, args) => {
+                // callee and arguments in consecutive temporaries
+                let mark = self.next;
+                let base = self.alloc();
+                self.expression_into(callee, base);
+                for arg in args {
+                    let register = self.alloc();
+                    self.expression_into(arg, register);
+                }
+                self.next = mark;
+                let dst = target(self);
+                self.emit(Instr::CALL(dst, base, args.len()), line);
+                Operand::REG(dst)
+            },
+            ExprKind::GET(object, name) => {
+                let mark = self.next;
+                let object = self.expression(object, None);
+                self.next = mark;
+                let name = self.constant(Value::STRING(name.lexeme.clone()));
+                let dst = target(self);
+                self.emit(Instr::GET_PROPERTY(dst, object, name), line);
+                Operand::REG(dst)
+            },
+            ExprKind::SET(object, name, value) => {
+                let mark = self.next;
+                let mut a = self.expression(object, None);
+                if let Operand::REG(register) = a {
+                    // object is read after the value ran, like the lhs of a binary operator
+                    if register < mark && assigns(value) {
+                        let copy = self.alloc();
+                        self.emit(Instr::MOVE(copy, a), line);
+                        a = Operand::REG(copy);
+                    }
+                }
+                let value = self.expression(value, None);
+                let name = self.constant(Value::STRING(name.lexeme.clone()));
+                self.emit(Instr::SET_PROPERTY(a, name, value), line);
+                // assignment is an expression, its value is the result
+                match dst {
+                    Some(dst) => {
+                        self.next = mark;
+                        self.emit(Instr::MOVE(dst, value), line);
+                        Operand::REG(dst)
+                    },
+                    None => value
+                }
+            },
+            ExprKind::INVOKE(object, name, args) => {
+                let mark = self.next;
+                let base = self.alloc();
+                self.expression_into(object, base);
+                for arg in args {
+                    let register = self.alloc();
+                    self.expression_into(arg, register);
+                }
+                self.next = mark;
+                let name = self.constant(Value::STRING(name.lexeme.clone()));
+                let dst = target(self);
+                self.emit(Instr::INVOKE(dst, base, name, args.len()), line);
+                Operand::REG(dst)
+            },
+        }
+    }
+}
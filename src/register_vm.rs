@@ -0,0 +1,163 @@
+use crate::Error;
+use crate::gc::Heap;
+use crate::intern::{Interner, Symbol};
+use crate::nanbox::NanBox;
+use crate::register::{Instr, Operand, RegisterChunk};
+use crate::value::Value;
+
+use std::io::Write;
+
+// interpreter for `register::Instr`, selected with `--vm=register`
+// operators share the `NanBox` impls and error messages of the stack machine in `vm`
+pub struct RegisterVM {
+    registers: Vec<NanBox>,
+    constants: Vec<NanBox>, // values of the running chunk
+    links: Vec<usize>, // slot in `globals` of every global slot of the running chunk
+    interner: Interner, // names of the globals, the symbol of a name is its slot in `globals`
+    globals: Vec<Option<NanBox>>, // None => not defined yet
+    heap: Heap,
+    out: Box<dyn Write>, // destination of `print` statements
+}
+
+impl Default for RegisterVM {
+    fn default() -> Self {
+        RegisterVM {registers: vec![], constants: vec![], links: vec![], interner: Interner::default(), globals: vec![], heap: Heap::default(), out: Box::new(std::io::stdout())}
+    }
+}
+
+macro_rules! binary_op {
+    ($self:ident, $line:expr, $dst:expr, $a:expr, $b:expr, $op:tt, $symbol:expr) => {{
+        let value = *$self.get($a) $op *$self.get($b);
+        match value {
+            Ok(x) => $self.registers[*$dst] = $self.heap.manage(x),
+            Err(Error::DIVIDE_BY_ZERO) => return Err(Error::RUNTIME_ERROR("DivideByZero Error".into(), $line)),
+            Err(_) => return Err(Error::RUNTIME_ERROR(format!("TypeError: Unsupported operand types for `{}`", $symbol), $line))
+        }
+    }}
+}
+
+macro_rules! compare_op {
+    ($self:ident, $dst:expr, $a:expr, $b:expr, $op:tt) => {{
+        let value = $self.get($a) $op $self.get($b);
+        $self.registers[*$dst] = NanBox::bool(value);
+    }}
+}
+
+impl RegisterVM {
+    pub fn set_output(&mut self, out: Box<dyn Write>) {
+        self.out = out;
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    fn get(&self, operand: &Operand) -> &NanBox {
+        match operand {
+            Operand::REG(r) => &self.registers[*r],
+            Operand::CONST(addr) => &self.constants[*addr],
+        }
+    }
+
+    fn undefined(&self, slot: usize, line: usize) -> Error {
+        Error::RUNTIME_ERROR(format!("NameError: undefined variable `{}`", self.interner.name(Symbol(slot))), line)
+    }
+
+    // runs the chunk, returns value of the last expression statement (nil if there is none)
+    pub fn execute(&mut self, chunk: &RegisterChunk) -> Result<Value, Error> {
+        self.registers = vec![NanBox::NIL; chunk.registers];
+        self.constants = chunk.values.iter().map(|x| self.heap.manage(x.clone())).collect();
+        self.links = chunk.globals.names().map(|name| self.interner.intern(name).0).collect();
+        let n = self.links.iter().map(|x| x + 1).max().unwrap_or(0);
+        if self.globals.len() < n {
+            self.globals.resize(n, None);
+        }
+        let mut ip = 0;
+        loop {
+            if self.heap.should_collect() {
+                let roots = self.registers.iter().chain(self.globals.iter().flatten()).chain(&self.constants);
+                self.heap.collect(roots);
+            }
+            let instr = &chunk.code[ip];
+            let line = chunk.lines[ip];
+            ip += 1;
+            match instr {
+                Instr::MOVE(dst, src) => self.registers[*dst] = *self.get(src),
+                Instr::DEFINE_GLOBAL(slot, value) => self.globals[self.links[*slot]] = Some(*self.get(value)),
+                Instr::GET_GLOBAL(dst, slot) => match self.globals[self.links[*slot]] {
+                    Some(x) => self.registers[*dst] = x,
+                    None => return Err(self.undefined(self.links[*slot], line))
+                },
+                Instr::SET_GLOBAL(slot, value) => {
+                    let value = *self.get(value);
+                    match &mut self.globals[self.links[*slot]] {
+                        Some(x) => *x = value,
+                        None => return Err(self.undefined(self.links[*slot], line))
+                    }
+                },
+                Instr::NEGATE(dst, src) => match -*self.get(src) {
+                    Ok(x) => self.registers[*dst] = self.heap.manage(x),
+                    Err(_) => return Err(Error::RUNTIME_ERROR("TypeError: Unsupported operand types for `-`".into(), line))
+                },
+                Instr::NOT(dst, src) => match self.get(src).as_bool() {
+                    Some(x) => self.registers[*dst] = NanBox::bool(!x),
+                    _ => return Err(Error::RUNTIME_ERROR("TypeError: Unsupported operand types for `!`".into(), line))
+                },
+                Instr::ADD(dst, a, b) => binary_op!(self, line, dst, a, b, +, "+"),
+                Instr::SUB(dst, a, b) => binary_op!(self, line, dst, a, b, -, "-"),
+                Instr::MUL(dst, a, b) => binary_op!(self, line, dst, a, b, *, "*"),
+                Instr::DIV(dst, a, b) => binary_op!(self, line, dst, a, b, /, "/"),
+                Instr::REM(dst, a, b) => binary_op!(self, line, dst, a, b, %, "%"),
+                Instr::AND(dst, a, b) => binary_op!(self, line, dst, a, b, &, "and"),
+                Instr::OR(dst, a, b) => binary_op!(self, line, dst, a, b, |, "or"),
+                Instr::EQUAL(dst, a, b) => compare_op!(self, dst, a, b, ==),
+                Instr::NOT_EQUAL(dst, a, b) => compare_op!(self, dst, a, b, !=),
+                Instr::LESS(dst, a, b) => compare_op!(self, dst, a, b, <),
+                Instr::GREATER(dst, a, b) => compare_op!(self, dst, a, b, >),
+                // negated like the stack machine's `GREATER; BANG`, `NaN <= 1` is `!(NaN > 1)`
+                Instr::LESS_EQUAL(dst, a, b) => {
+                    #[allow(clippy::neg_cmp_op_on_partial_ord)]
+                    let value = !(self.get(a) > self.get(b));
+                    self.registers[*dst] = NanBox::bool(value);
+                },
+                Instr::GREATER_EQUAL(dst, a, b) => {
+                    #[allow(clippy::neg_cmp_op_on_partial_ord)]
+                    let value = !(self.get(a) < self.get(b));
+                    self.registers[*dst] = NanBox::bool(value);
+                },
+                // natives and userdata need the stack machine, no value is callable or has properties here
+                Instr::CALL(_, callee, _) => {
+                    return Err(Error::RUNTIME_ERROR(format!("TypeError: `{}` is not callable", self.registers[*callee].repr()), line));
+                },
+                Instr::GET_PROPERTY(_, object, name) | Instr::SET_PROPERTY(object, name, _) => {
+                    return Err(Error::RUNTIME_ERROR(format!("TypeError: `{}` has no property `{}`", self.get(object).repr(), self.get(name)), line));
+                },
+                Instr::INVOKE(_, object, name, _) => {
+                    return Err(Error::RUNTIME_ERROR(format!("TypeError: `{}` has no method `{}`", self.registers[*object].repr(), self.get(name)), line));
+                },
+                Instr::PRINT(value) => {
+                    let value = *self.get(value);
+                    if writeln!(self.out, "{}", value).is_err() {
+                        return Err(Error::IO_ERROR);
+                    }
+                },
+                Instr::JUMP(jaddr) => ip = *jaddr,
+                Instr::JUMP_IF_FALSE(value, jaddr) => {
+                    if self.get(value).is_false() {
+                        ip = *jaddr;
+                    }
+                },
+                Instr::JUMP_UNLESS_FALSE(value, jaddr) => {
+                    if !self.get(value).is_false() {
+                        ip = *jaddr;
+                    }
+                },
+                Instr::RETURN(value) => return Ok(self.get(value).to_value()),
+            }
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use crate::Error;
+use crate::ast::*;
+use crate::chunk::LocalInfo;
+use crate::token::Token;
+
+use std::collections::HashMap;
+use std::fmt;
+
+// locals in the order they are pushed on the runtime stack, the index of a local is its slot
+#[derive(Default)]
+pub struct LocalEnv {
+    locals: Vec<Local>,
+    scope_depth: usize // depth 0 => global scope
+}
+
+#[derive(Debug, Clone)]
+pub struct Local {
+    name: String,
+    depth: usize,
+    initialized: bool, // false while the initializer of the local is resolved
+    declaration: Option<usize>, // index in `Symbols::declarations`, None for locals of a paused program
+}
+
+impl LocalEnv {
+    // innermost local named `name` and its slot
+    fn find(&self, name: &str) -> Option<(usize, &Local)> {
+        self.locals.iter().enumerate().rev().find(|(_, local)| local.name == name)
+    }
+}
+
+// kind of value an expression evaluates to, as far as it can be told at compile time
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    NUMBER,
+    STRING,
+    BOOL,
+    NIL,
+    ANY
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::NUMBER => write!(f, "number"),
+            Kind::STRING => write!(f, "string"),
+            Kind::BOOL => write!(f, "bool"),
+            Kind::NIL => write!(f, "nil"),
+            Kind::ANY => write!(f, "any"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Declaration {
+    pub name: Token,
+    pub depth: usize, // depth 0 => global variable
+    pub kind: Kind, // ANY if variable is assigned values of different kinds
+    pub shadows: Option<usize>, // declaration of variable with the same name which was visible at this point
+}
+
+// variable declarations and uses, collected for editor tooling
+#[derive(Default)]
+pub struct Symbols {
+    pub declarations: Vec<Declaration>,
+    pub references: Vec<(Token, Option<usize>)>, // identifier and index of declaration it resolves to
+}
+
+// assigns stack slots to local variables and links every use of a variable to its declaration
+#[derive(Default)]
+pub struct Resolver {
+    env: LocalEnv,
+    globals: HashMap<String, usize>, // latest declaration of each global
+    pub symbols: Symbols,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver::default()
+    }
+
+    // resolver for an expression evaluated while a program is paused, `locals` are the variables alive at that point
+    pub fn with_locals(locals: &[&LocalInfo]) -> Self {
+        let mut resolver = Resolver::new();
+        let mut locals = locals.to_vec();
+        locals.sort_by_key(|x| x.slot);
+        for local in locals {
+            // the paused program pushed its locals without gaps, the stack slot is the position
+            resolver.env.locals.push(Local {name: local.name.clone(), depth: local.depth, initialized: true, declaration: None});
+            resolver.env.scope_depth = resolver.env.scope_depth.max(local.depth);
+        }
+        resolver
+    }
+
+    pub fn program(&mut self, statements: &mut [Stmt]) -> Result<(), Error> {
+        for stmt in statements.iter_mut() {
+            self.statement(stmt)?;
+        }
+        self.resolve_globals();
+        Ok(())
+    }
+
+    // globals used before their declaration resolve to the first declaration with that name
+    fn resolve_globals(&mut self) {
+        for (token, declaration) in self.symbols.references.iter_mut().filter(|x| x.1.is_none()) {
+            *declaration = self.symbols.declarations.iter().position(|x| x.depth == 0 && x.name.lexeme == token.lexeme);
+        }
+    }
+
+    // declaration which variable `name` currently resolves to
+    fn resolve_declaration(&self, name: &Token) -> Option<usize> {
+        match self.env.find(&name.lexeme) {
+            Some((_, local)) => local.declaration,
+            None => self.globals.get(&name.lexeme).copied()
+        }
+    }
+
+    fn statement(&mut self, stmt: &mut Stmt) -> Result<(), Error> {
+        match &mut stmt.kind {
+            StmtKind::VAR(variable, initializer) => {
+                let shadows = self.resolve_declaration(&variable.name);
+                let depth = self.env.scope_depth;
+                if depth > 0 {
+                    let name = &variable.name.lexeme;
+                    if self.env.locals.iter().rev().take_while(|x| x.depth == depth).any(|x| x.name == *name) {
+                        return Err(Error::COMPILE_ERROR(format!("NameError: `{}` is already declared in this scope", name), variable.name.line));
+                    }
+                    // the local is visible to its initializer only to report reading it
+                    self.env.locals.push(Local {name: name.clone(), depth, initialized: false, declaration: None});
+                    variable.resolved = Resolved::LOCAL(self.env.locals.len() - 1);
+                }
+                // a global initializer is resolved before the variable is declared, `var a = a;` refers to an older `a`
+                let kind = match initializer {
+                    Some(expr) => self.expression(expr)?,
+                    None => Kind::NIL
+                };
+                self.symbols.declarations.push(Declaration {name: variable.name.clone(), depth, kind, shadows});
+                let index = self.symbols.declarations.len() - 1;
+                if depth > 0 {
+                    let local = self.env.locals.last_mut().unwrap();
+                    local.initialized = true;
+                    local.declaration = Some(index);
+                } else {
+                    self.globals.insert(variable.name.lexeme.clone(), index);
+                    variable.resolved = Resolved::GLOBAL;
+                }
+            },
+            StmtKind::PRINT(expr) | StmtKind::EXPRESSION(expr) => {
+                self.expression(expr)?;
+            },
+            StmtKind::BLOCK(block) => self.block(block)?,
+            StmtKind::IF(condition, then, otherwise) => {
+                self.expression(condition)?;
+                self.block(then)?;
+                if let Some(otherwise) = otherwise {
+                    self.block(otherwise)?;
+                }
+            },
+            StmtKind::WHILE(condition, body) => {
+                self.expression(condition)?;
+                self.block(body)?;
+            },
+            StmtKind::BREAK | StmtKind::CONTINUE => {}
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: &mut Block) -> Result<(), Error> {
+        self.env.scope_depth += 1;
+        let mut result = Ok(());
+        for stmt in block.statements.iter_mut() {
+            result = self.statement(stmt);
+            if result.is_err() {
+                break;
+            }
+        }
+        // forget locals of the block, they are on top of the stack
+        let scope_depth = self.env.scope_depth;
+        while self.env.locals.last().is_some_and(|x| x.depth == scope_depth) {
+            self.env.locals.pop();
+        }
+        self.env.scope_depth -= 1;
+        result
+    }
+
+    // resolves variables in `expr`, returns kind of the value it evaluates to
+    pub fn expression(&mut self, expr: &mut Expr) -> Result<Kind, Error> {
+        let kind = match &mut expr.kind {
+            ExprKind::NUMBER(_) => Kind::NUMBER,
+            ExprKind::STRING(_) => Kind::STRING,
+            ExprKind::BOOL(_) => Kind::BOOL,
+            ExprKind::NIL => Kind::NIL,
+            ExprKind::VARIABLE(variable) => {
+                let declaration = self.variable(variable)?;
+                declaration.map(|i| self.symbols.declarations[i].kind).unwrap_or(Kind::ANY)
+            },
+            ExprKind::ASSIGN(variable, value) => {
+                let declaration = self.variable(variable)?;
+                let kind = self.expression(value)?;
+                if let Some(i) = declaration {
+                    if self.symbols.declarations[i].kind != kind {
+                        self.symbols.declarations[i].kind = Kind::ANY;
+                    }
+                }
+                kind
+            },
+            ExprKind::UNARY(op, operand) => {
+                self.expression(operand)?;
+                match op {
+                    UnaryOp::NEGATE => Kind::NUMBER,
+                    UnaryOp::NOT => Kind::BOOL,
+                }
+            },
+            ExprKind::BINARY(op, left, right) => {
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+                match (op, left, right) {
+                    (BinaryOp::ADD, Kind::NUMBER, Kind::NUMBER) | (BinaryOp::MUL, Kind::NUMBER, Kind::NUMBER) => Kind::NUMBER,
+                    (BinaryOp::ADD, Kind::STRING, Kind::STRING) => Kind::STRING,
+                    (BinaryOp::MUL, Kind::STRING, Kind::NUMBER) | (BinaryOp::MUL, Kind::NUMBER, Kind::STRING) => Kind::STRING,
+                    (BinaryOp::ADD, _, _) | (BinaryOp::MUL, _, _) => Kind::ANY,
+                    (BinaryOp::SUB, _, _) | (BinaryOp::DIV, _, _) | (BinaryOp::REM, _, _) => Kind::NUMBER,
+                    _ => Kind::BOOL
+                }
+            },
+            ExprKind::LOGICAL(_, left, right) => {
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+                // short-circuit yields the lhs operand when it decides the result
+                if left == Kind::BOOL && right == Kind::BOOL { Kind::BOOL } else { Kind::ANY }
+            },
+            ExprKind::CALL(callee, args) | ExprKind::INVOKE(callee, _, args) => {
+                self.expression(callee)?;
+                for arg in args.iter_mut() {
+                    self.expression(arg)?;
+                }
+                Kind::ANY
+            },
+            ExprKind::GET(object, _) => {
+                self.expression(object)?;
+                Kind::ANY
+            },
+            ExprKind::SET(object, _, value) => {
+                self.expression(object)?;
+                self.expression(value)?
+            },
+        };
+        Ok(kind)
+    }
+
+    // resolves storage of a variable use and records the reference, returns its declaration
+    fn variable(&mut self, variable: &mut Variable) -> Result<Option<usize>, Error> {
+        let declaration = match self.env.find(&variable.name.lexeme) {
+            Some((slot, local)) => {
+                if !local.initialized {
+                    return Err(Error::COMPILE_ERROR(format!("NameError: can't read local `{}` in its own initializer", local.name), variable.name.line));
+                }
+                variable.resolved = Resolved::LOCAL(slot);
+                local.declaration
+            },
+            None => {
+                variable.resolved = Resolved::GLOBAL;
+                self.globals.get(&variable.name.lexeme).copied()
+            }
+        };
+        self.symbols.references.push((variable.name.clone(), declaration));
+        Ok(declaration)
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path};
+
+use crate::Error;
+
+// capabilities of a sandboxed VM, for running scripts the host doesn't trust. every side effect
+// needs a capability: the VM checks `print` statements, and natives and methods of the host before
+// calling them, with the capability they were registered with. a native with effects on further
+// resources checks those with `VM::require`. a denied effect fails with `Error::PERMISSION_DENIED`.
+// a new sandbox denies everything, the host or an allowlist config grants capabilities one by one
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    PRINT,
+    READ, // files, the resource is a path
+    WRITE,
+    ENV, // environment variables, the resource is a name
+    CLOCK,
+    IMPORT, // loading other scripts, the resource is a path
+}
+
+const CAPABILITIES: [Capability; 6] = [Capability::PRINT, Capability::READ, Capability::WRITE, Capability::ENV, Capability::CLOCK, Capability::IMPORT];
+
+impl Capability {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::PRINT => "print",
+            Capability::READ => "read",
+            Capability::WRITE => "write",
+            Capability::ENV => "env",
+            Capability::CLOCK => "clock",
+            Capability::IMPORT => "import",
+        }
+    }
+
+    // capabilities granted per resource, a native registered with one gets the resource as its
+    // first argument
+    pub fn has_resources(&self) -> bool {
+        !matches!(self, Capability::PRINT | Capability::CLOCK)
+    }
+
+    // resources of file capabilities are paths, a granted directory covers everything below it.
+    // paths going up with `..` could leave the directory, they are never covered
+    fn covers(&self, granted: &str, resource: &str) -> bool {
+        match self {
+            Capability::READ | Capability::WRITE | Capability::IMPORT => {
+                let path = Path::new(resource);
+                path.starts_with(granted) && !path.components().any(|x| x == Component::ParentDir)
+            },
+            _ => granted == resource
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    granted: HashMap<Capability, Option<Vec<String>>>, // None => every resource
+}
+
+impl Sandbox {
+    // the default profile, nothing is allowed
+    pub fn deny_all() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(&mut self, capability: Capability) {
+        self.granted.insert(capability, None);
+    }
+
+    // allows `capability` only for `resource`, e.g. reading below one directory
+    pub fn allow_resource(&mut self, capability: Capability, resource: &str) {
+        // None => already allowed for everything
+        if let Some(resources) = self.granted.entry(capability).or_insert_with(|| Some(vec![])) {
+            resources.push(resource.to_string());
+        }
+    }
+
+    // allowlist with one capability per line, optionally limited to resources:
+    // `print`, `env = HOME, LANG` or `read = /srv/data`. `#` starts a comment
+    pub fn parse(text: &str) -> Result<Sandbox, Error> {
+        let mut sandbox = Sandbox::deny_all();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, resources) = match line.split_once('=') {
+                Some((name, resources)) => (name.trim(), Some(resources)),
+                None => (line, None)
+            };
+            let capability = match CAPABILITIES.iter().find(|x| x.name() == name) {
+                Some(x) => *x,
+                None => return Err(Error::COMPILE_ERROR(format!("ConfigError: unknown capability `{}`", name), i + 1))
+            };
+            match resources {
+                Some(resources) => {
+                    for resource in resources.split(',').map(|x| x.trim()) {
+                        if resource.is_empty() {
+                            return Err(Error::COMPILE_ERROR(format!("ConfigError: expected resources of `{}` got `{}`", name, line), i + 1));
+                        }
+                        sandbox.allow_resource(capability, resource);
+                    }
+                },
+                None => sandbox.allow(capability)
+            }
+        }
+        Ok(sandbox)
+    }
+
+    pub fn allows(&self, capability: Capability, resource: Option<&str>) -> bool {
+        match (self.granted.get(&capability), resource) {
+            (None, _) => false,
+            (Some(None), _) => true,
+            // allowed for some resources only, so an effect without a resource isn't covered
+            (Some(Some(_)), None) => false,
+            (Some(Some(granted)), Some(resource)) => granted.iter().any(|x| capability.covers(x, resource)),
+        }
+    }
+
+    // text of `Error::PERMISSION_DENIED` for reporting it
+    pub fn message(capability: Capability, resource: Option<&str>) -> String {
+        match resource {
+            Some(resource) => format!("PermissionError: `{}` of `{}` is not allowed", capability, resource),
+            None => format!("PermissionError: `{}` is not allowed", capability)
+        }
+    }
+
+    // error for effects which aren't allowed, the VM adds the line
+    pub fn check(&self, capability: Capability, resource: Option<&str>) -> Result<(), Error> {
+        if self.allows(capability, resource) {
+            return Ok(());
+        }
+        Err(Error::PERMISSION_DENIED(capability, resource.map(|x| x.to_string()), 0))
+    }
+}
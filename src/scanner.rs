@@ -7,12 +7,21 @@ pub struct Scanner {
     current: usize, // current index of token
     source: Vec<char>,
     multi_line_comment: usize, // keeps track of multiline comments
+    line_start: usize, // index of the first character of current line
+    keep_comments: bool, // emit COMMENT tokens instead of skipping comments
 }
 
 impl Scanner {
     pub fn new(source: String) -> Scanner {
         let chars: Vec<char> = source.chars().collect();
-        Scanner {source: chars, line: 1, start: 0, current: 0, multi_line_comment: 0}
+        Scanner {source: chars, line: 1, start: 0, current: 0, multi_line_comment: 0, line_start: 0, keep_comments: false}
+    }
+
+    // scanner which returns comments as tokens, used by tools which must not lose them (formatter, linter)
+    pub fn with_comments(source: String) -> Scanner {
+        let mut scanner = Scanner::new(source);
+        scanner.keep_comments = true;
+        scanner
     }
 
     pub fn cell(&self) -> char {
@@ -26,7 +35,7 @@ impl Scanner {
 
     // checks if passed char is next char (char at index "current + 1")
     pub fn next(&self, next_char: char) -> bool {
-        if self.is_eof() {
+        if self.current + 1 >= self.source.len() {
             false
         } else {
             self.source[self.current + 1] == next_char
@@ -99,6 +108,7 @@ impl Scanner {
         while !self.next('"') && !self.is_eof() {
             if self.cell() == '\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
             }
             self.current += 1;
         }
@@ -117,12 +127,9 @@ impl Scanner {
         Ok(string)
     }
 
-    // scans for an individual token at start location (at function call start == current)
-    // throws SyntaxError if finds an unexpected character
-    pub fn scan_token(&mut self) -> Result<Token, Error> {
-
-        // handles multi-line comments
-        while self.multi_line_comment > 0 {
+    // skips over nested multi-line comment, at function call "current" points after the opening `/*`
+    fn skip_comment(&mut self) {
+        while self.multi_line_comment > 0 && !self.is_eof() {
             if self.cell() == '*' && self.next('/') {
                 self.multi_line_comment -= 1;
                 self.current += 1;
@@ -131,9 +138,21 @@ impl Scanner {
                 self.current += 1;
             } else if self.cell() == '\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
             }
             self.current += 1;
         }
+    }
+
+    // scans for an individual token at start location (at function call start == current)
+    // throws SyntaxError if finds an unexpected character
+    pub fn scan_token(&mut self) -> Result<Token, Error> {
+
+        // handles multi-line comments
+        if self.multi_line_comment > 0 {
+            self.skip_comment();
+            self.start = self.current;
+        }
 
         if self.is_eof() { return Ok(Token::new("EOF".to_string(), TokenType::EOF, self.line)); }
         
@@ -190,14 +209,27 @@ impl Scanner {
             // special case: '/' stands for division, while // stands for comment
             '/' => {
                 if self.next('/') {
+                    // newline itself is consumed by the next call
                     while !(self.is_eof() || self.cell() == '\n') { 
                         self.current += 1;
                     }
-                    self.line += 1;
+                    if self.keep_comments {
+                        let comment: String = self.source[self.start..self.current].iter().collect();
+                        self.current -= 1; // `advance` steps over the last character of the token
+                        return Ok(Token::new(comment, TokenType::COMMENT, self.line));
+                    }
                     return self.scan_token();
                 } else if self.next('*') {
                     self.multi_line_comment += 1;
-                    self.current += 1;
+                    self.current += 2; // skip `/*`
+                    if self.keep_comments {
+                        // comment token is placed on the line where comment starts
+                        let line = self.line;
+                        self.skip_comment();
+                        let comment: String = self.source[self.start..self.current].iter().collect();
+                        self.current -= 1;
+                        return Ok(Token::new(comment, TokenType::COMMENT, line));
+                    }
                     return self.scan_token();
                 } else {
                     return Ok(Token::new(c.to_string(), TokenType::SLASH, self.line));
@@ -213,6 +245,7 @@ impl Scanner {
             '\n' => {
                 self.line += 1;
                 self.current += 1;
+                self.line_start = self.current;
                 self.start = self.current;
                 return self.scan_token();
             },
@@ -239,8 +272,12 @@ impl Scanner {
 
     pub fn advance(&mut self) -> Result<Token, Error> {
         self.start = self.current;
-        let token = self.scan_token();
+        let mut token = self.scan_token();
         self.current += 1;
+        if let Ok(token) = &mut token {
+            token.col = self.start.saturating_sub(self.line_start);
+            token.len = self.current.min(self.source.len()).saturating_sub(self.start);
+        }
         return token;
     }
 }
@@ -0,0 +1,76 @@
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{self, Serialize, Serializer};
+
+use crate::value::Value;
+
+// serde support for `Value`, enabled with the `serde` feature. nil maps to unit (`null` in JSON),
+// numbers to f64. natives and userdata belong to the host and fail to serialize, the language has
+// no collections yet so sequences and maps fail to deserialize
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::FLOAT(x) => serializer.serialize_f64(*x),
+            Value::BOOL(x) => serializer.serialize_bool(*x),
+            Value::STRING(x) => serializer.serialize_str(x),
+            Value::NIL => serializer.serialize_unit(),
+            Value::NATIVE(x) => Err(ser::Error::custom(format!("can't serialize native function `{}`", x.name))),
+            Value::USERDATA(x) => Err(ser::Error::custom(format!("can't serialize `{}` object", x.class().name()))),
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("nil, a bool, a number or a string")
+    }
+
+    fn visit_bool<E: de::Error>(self, x: bool) -> Result<Value, E> {
+        Ok(Value::BOOL(x))
+    }
+
+    // integers beyond 2^53 are rounded like in the language
+    fn visit_i64<E: de::Error>(self, x: i64) -> Result<Value, E> {
+        Ok(Value::FLOAT(x as f64))
+    }
+
+    fn visit_u64<E: de::Error>(self, x: u64) -> Result<Value, E> {
+        Ok(Value::FLOAT(x as f64))
+    }
+
+    fn visit_f64<E: de::Error>(self, x: f64) -> Result<Value, E> {
+        Ok(Value::FLOAT(x))
+    }
+
+    fn visit_str<E: de::Error>(self, x: &str) -> Result<Value, E> {
+        Ok(Value::STRING(x.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, x: String) -> Result<Value, E> {
+        Ok(Value::STRING(x))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::NIL)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::NIL)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
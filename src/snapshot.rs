@@ -0,0 +1,443 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+
+use crate::Error;
+use crate::chunk::{Chunk, LocalInfo, OpCode, ScopeInfo};
+use crate::gc::{Heap, Obj, Object};
+use crate::json::Json;
+use crate::nanbox::NanBox;
+use crate::native::Native;
+use crate::value::Value;
+
+// state of a paused program, taken with `VM::snapshot` and continued with `VM::restore` followed by
+// `VM::execute`, e.g. by a new process after a restart. the snapshot holds the chunk, the
+// instruction pointer, the stack, the globals and the heap objects they point to, an object
+// reachable from several places is stored once and restored shared.
+// a program is paused between instructions, so snapshots are taken from a `vm::Hook`, which may
+// then stop the program with an error. natives are stored by name and looked up among the natives
+// the host registered before restoring, userdata belongs to the host and can't be stored.
+// the file is JSON, floats are stored as their bits so NaN and -0 survive
+
+// format of snapshot files, bumped whenever the layout changes
+pub const VERSION: usize = 1;
+
+// value of a stack slot, global or constant
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Word {
+    FLOAT(f64),
+    BOOL(bool),
+    NIL,
+    OBJECT(usize), // index in `Snapshot::objects`
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Stored {
+    STRING(String),
+    NATIVE(String), // name of the native
+}
+
+#[derive(Clone)]
+pub struct Snapshot {
+    pub(crate) chunk: Chunk,
+    pub(crate) ip: usize,
+    pub(crate) objects: Vec<Stored>,
+    pub(crate) constants: Vec<Word>, // of `chunk` on the heap
+    pub(crate) stack: Vec<Word>,
+    pub(crate) globals: Vec<(String, Option<Word>)>, // in order of their slots
+}
+
+fn error(message: impl Into<String>) -> Error {
+    Error::RUNTIME_ERROR(format!("SnapshotError: {}", message.into()), 0)
+}
+
+fn malformed(what: &str) -> Error {
+    error(format!("malformed snapshot, bad {}", what))
+}
+
+// numbers objects while a snapshot is taken, so an object shared by several words is stored once
+#[derive(Default)]
+pub(crate) struct Objects {
+    ids: HashMap<*mut Object, usize>,
+    pub(crate) objects: Vec<Stored>,
+}
+
+impl Objects {
+    pub(crate) fn word(&mut self, x: NanBox) -> Result<Word, Error> {
+        let ptr = match x.ptr() {
+            Some(ptr) => ptr,
+            None => return Ok(match x.to_value() {
+                Value::FLOAT(x) => Word::FLOAT(x),
+                Value::BOOL(x) => Word::BOOL(x),
+                _ => Word::NIL
+            })
+        };
+        if let Some(id) = self.ids.get(&ptr) {
+            return Ok(Word::OBJECT(*id));
+        }
+        // SAFETY: `x` is a live word of the VM, its object is owned by the heap of the VM
+        let stored = match unsafe { &(*ptr).obj } {
+            Obj::STRING(x) => Stored::STRING(x.clone()),
+            Obj::NATIVE(x) => Stored::NATIVE(x.name.clone()),
+            Obj::USERDATA(x) => return Err(error(format!("can't store `{}` object", x.class().name())))
+        };
+        self.objects.push(stored);
+        self.ids.insert(ptr, self.objects.len() - 1);
+        Ok(Word::OBJECT(self.objects.len() - 1))
+    }
+}
+
+impl Snapshot {
+    // stored objects moved onto `heap`, indexed like `objects`. `native` finds the native the host
+    // registered under a name
+    pub(crate) fn objects(&self, heap: &mut Heap, native: impl Fn(&str) -> Option<Native>) -> Result<Vec<NanBox>, Error> {
+        let mut objects = vec![];
+        for stored in &self.objects {
+            let value = match stored {
+                Stored::STRING(x) => Value::STRING(x.clone()),
+                Stored::NATIVE(name) => match native(name) {
+                    Some(x) => Value::NATIVE(x),
+                    None => return Err(error(format!("native `{}` is not registered", name)))
+                }
+            };
+            objects.push(heap.manage(value));
+        }
+        Ok(objects)
+    }
+
+    pub fn write(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let json = Json::object(vec![
+            ("version", VERSION.into()),
+            ("chunk", chunk_json(&self.chunk)?),
+            ("ip", self.ip.into()),
+            ("objects", self.objects.iter().map(stored_json).collect::<Vec<Json>>().into()),
+            ("constants", self.constants.iter().map(word_json).collect::<Vec<Json>>().into()),
+            ("stack", self.stack.iter().map(word_json).collect::<Vec<Json>>().into()),
+            ("globals", self.globals.iter().map(|(name, x)| vec![name.as_str().into(), x.as_ref().map(word_json).unwrap_or(Json::NULL)].into()).collect::<Vec<Json>>().into()),
+        ]);
+        match writeln!(out, "{}", json) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::IO_ERROR)
+        }
+    }
+
+    pub fn read(input: &mut dyn Read) -> Result<Snapshot, Error> {
+        let mut text = String::new();
+        if input.read_to_string(&mut text).is_err() {
+            return Err(Error::IO_ERROR);
+        }
+        let json = match Json::parse(&text) {
+            Ok(x) => x,
+            Err(e) => return Err(error(format!("malformed snapshot, {}", e)))
+        };
+        match json.get("version").as_usize() {
+            Some(VERSION) => {},
+            Some(x) => return Err(error(format!("unsupported version {}, expected {}", x, VERSION))),
+            None => return Err(malformed("version"))
+        }
+        let words = |key: &str| json.get(key).as_array().iter().map(word).collect::<Result<Vec<Word>, Error>>();
+        let mut globals = vec![];
+        for global in json.get("globals").as_array() {
+            let (name, value) = match global.as_array() {
+                [Json::STRING(name), Json::NULL] => (name.clone(), None),
+                [Json::STRING(name), value] => (name.clone(), Some(word(value)?)),
+                _ => return Err(malformed("global"))
+            };
+            globals.push((name, value));
+        }
+        let snapshot = Snapshot {
+            chunk: chunk(json.get("chunk"))?,
+            ip: json.get("ip").as_usize().ok_or_else(|| malformed("ip"))?,
+            objects: json.get("objects").as_array().iter().map(stored).collect::<Result<Vec<Stored>, Error>>()?,
+            constants: words("constants")?,
+            stack: words("stack")?,
+            globals,
+        };
+        snapshot.check()?;
+        Ok(snapshot)
+    }
+
+    // catches truncated, mismatched and edited files, so restoring never makes the VM panic
+    fn check(&self) -> Result<(), Error> {
+        let mut words = self.constants.iter().chain(&self.stack).chain(self.globals.iter().filter_map(|(_, x)| x.as_ref()));
+        if words.any(|x| matches!(x, Word::OBJECT(id) if *id >= self.objects.len())) {
+            return Err(malformed("object reference"));
+        }
+        if self.ip >= self.chunk.code.len() || self.constants.len() != self.chunk.values().len() {
+            return Err(malformed("chunk"));
+        }
+        let mut names = HashSet::new();
+        if !self.globals.iter().all(|(name, _)| names.insert(name)) {
+            return Err(malformed("global"));
+        }
+        self.check_code()
+    }
+
+    // follows every path the program can still take from `ip` with the depth of the stack, instructions
+    // must find the values they pop, the stack slots, constants and globals they refer to, and jump
+    // inside the code. paths meeting at an instruction agree on the depth, as in compiled code
+    fn check_code(&self) -> Result<(), Error> {
+        let code = &self.chunk.code;
+        let globals = self.chunk.globals.names().count();
+        let constant = |addr: usize| addr < self.constants.len();
+        // properties and methods are looked up by a string constant
+        let name = |addr: usize| matches!(self.constants.get(addr), Some(Word::OBJECT(id)) if matches!(self.objects[*id], Stored::STRING(_)));
+        let mut depths: Vec<Option<usize>> = vec![None; code.len()];
+        let mut pending = vec![(self.ip, self.stack.len())];
+        while let Some((offset, depth)) = pending.pop() {
+            match depths.get(offset) {
+                Some(None) => depths[offset] = Some(depth),
+                Some(Some(x)) if *x == depth => continue,
+                Some(Some(_)) => return Err(malformed("stack")),
+                None => return Err(malformed("jump"))
+            }
+            let local = |slot: usize| slot < depth;
+            // operands are valid, values popped, values pushed, jump target
+            let (valid, pops, pushes, target) = match code[offset] {
+                OpCode::RETURN => continue,
+                OpCode::CONSTANT(addr) => (constant(addr), 0, 1, None),
+                OpCode::POP | OpCode::PRINT => (true, 1, 0, None),
+                OpCode::POPN(n) => (true, n, 0, None),
+                OpCode::DEFINE_GLOBAL(slot) => (slot < globals, 1, 0, None),
+                OpCode::GET_GLOBAL(slot) => (slot < globals, 0, 1, None),
+                OpCode::SET_GLOBAL(slot) => (slot < globals, 1, 1, None),
+                OpCode::GET_LOCAL(slot) => (local(slot), 0, 1, None),
+                OpCode::SET_LOCAL(slot) => (local(slot), 1, 1, None),
+                OpCode::NEGATE | OpCode::BANG => (true, 1, 1, None),
+                OpCode::ADD | OpCode::SUB | OpCode::MUL | OpCode::DIV | OpCode::REM | OpCode::OR | OpCode::AND | OpCode::EQUAL |
+                OpCode::GREATER | OpCode::LESS | OpCode::NOT_EQUAL | OpCode::LESS_EQUAL | OpCode::GREATER_EQUAL => (true, 2, 1, None),
+                OpCode::POP_JUMP_IF_FALSE(target) => (true, 1, 0, Some(target)),
+                OpCode::INC_LOCAL(slot, addr) => (local(slot) && constant(addr), 0, 0, None),
+                OpCode::LESS_LOCAL_LOCAL(a, b) => (local(a) && local(b), 0, 1, None),
+                OpCode::LESS_LOCAL_CONSTANT(slot, addr) => (local(slot) && constant(addr), 0, 1, None),
+                OpCode::CALL(argc) => (true, argc.saturating_add(1), 1, None),
+                OpCode::GET_PROPERTY(addr) => (name(addr), 1, 1, None),
+                OpCode::SET_PROPERTY(addr) => (name(addr), 2, 1, None),
+                OpCode::INVOKE(addr, argc) => (name(addr), argc.saturating_add(1), 1, None),
+                OpCode::IF(target) | OpCode::IFN(target) => (true, 1, 1, Some(target)),
+                OpCode::JMP(target) => (true, 0, 0, Some(target)),
+            };
+            if !valid || pops > depth {
+                return Err(malformed("instruction"));
+            }
+            let depth = depth - pops + pushes;
+            if let Some(target) = target {
+                pending.push((target, depth));
+            }
+            if !matches!(code[offset], OpCode::JMP(_)) {
+                pending.push((offset + 1, depth));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn float_json(x: f64) -> Json {
+    format!("{:016x}", x.to_bits()).into()
+}
+
+fn float(json: &Json) -> Result<f64, Error> {
+    match json.as_str().and_then(|x| u64::from_str_radix(x, 16).ok()) {
+        Some(bits) => Ok(f64::from_bits(bits)),
+        None => Err(malformed("number"))
+    }
+}
+
+fn word_json(x: &Word) -> Json {
+    match x {
+        Word::FLOAT(x) => vec!["float".into(), float_json(*x)].into(),
+        Word::BOOL(x) => vec!["bool".into(), (*x).into()].into(),
+        Word::NIL => vec!["nil".into()].into(),
+        Word::OBJECT(id) => vec!["object".into(), (*id).into()].into(),
+    }
+}
+
+fn word(json: &Json) -> Result<Word, Error> {
+    match json.as_array() {
+        [Json::STRING(tag), x] if tag == "float" => Ok(Word::FLOAT(float(x)?)),
+        [Json::STRING(tag), Json::BOOL(x)] if tag == "bool" => Ok(Word::BOOL(*x)),
+        [Json::STRING(tag)] if tag == "nil" => Ok(Word::NIL),
+        [Json::STRING(tag), x] if tag == "object" => x.as_usize().map(Word::OBJECT).ok_or_else(|| malformed("value")),
+        _ => Err(malformed("value"))
+    }
+}
+
+fn stored_json(x: &Stored) -> Json {
+    match x {
+        Stored::STRING(x) => vec!["string".into(), x.as_str().into()].into(),
+        Stored::NATIVE(name) => vec!["native".into(), name.as_str().into()].into(),
+    }
+}
+
+fn stored(json: &Json) -> Result<Stored, Error> {
+    match json.as_array() {
+        [Json::STRING(tag), Json::STRING(x)] if tag == "string" => Ok(Stored::STRING(x.clone())),
+        [Json::STRING(tag), Json::STRING(x)] if tag == "native" => Ok(Stored::NATIVE(x.clone())),
+        _ => Err(malformed("object"))
+    }
+}
+
+// constants of a chunk are literals, the compiler never puts natives or userdata there
+fn value_json(x: &Value) -> Result<Json, Error> {
+    Ok(match x {
+        Value::FLOAT(x) => word_json(&Word::FLOAT(*x)),
+        Value::BOOL(x) => word_json(&Word::BOOL(*x)),
+        Value::NIL => word_json(&Word::NIL),
+        Value::STRING(x) => stored_json(&Stored::STRING(x.clone())),
+        x => return Err(error(format!("can't store constant `{}`", x.repr())))
+    })
+}
+
+fn value(json: &Json) -> Result<Value, Error> {
+    match json.as_array() {
+        [Json::STRING(tag), Json::STRING(x)] if tag == "string" => Ok(Value::STRING(x.clone())),
+        _ => match word(json)? {
+            Word::FLOAT(x) => Ok(Value::FLOAT(x)),
+            Word::BOOL(x) => Ok(Value::BOOL(x)),
+            Word::NIL => Ok(Value::NIL),
+            Word::OBJECT(_) => Err(malformed("constant"))
+        }
+    }
+}
+
+fn chunk_json(chunk: &Chunk) -> Result<Json, Error> {
+    let lines = chunk.instruction_lines();
+    Ok(Json::object(vec![
+        ("code", chunk.code.iter().zip(&lines).map(|(x, line)| vec![instruction_json(x).into(), (*line).into()].into()).collect::<Vec<Json>>().into()),
+        ("values", chunk.values().iter().map(value_json).collect::<Result<Vec<Json>, Error>>()?.into()),
+        ("globals", chunk.globals.names().map(Json::from).collect::<Vec<Json>>().into()),
+        ("locals", chunk.locals.iter().map(|x| vec![x.name.as_str().into(), x.line.into(), x.slot.into(), x.depth.into(), x.start.into(), x.end.into()].into()).collect::<Vec<Json>>().into()),
+        ("scopes", chunk.scopes.iter().map(|x| vec![x.depth.into(), x.start.into(), x.end.into()].into()).collect::<Vec<Json>>().into()),
+    ]))
+}
+
+fn chunk(json: &Json) -> Result<Chunk, Error> {
+    let mut chunk = Chunk::new();
+    // values were added without duplicates, adding them again in order keeps their addresses
+    for x in json.get("values").as_array() {
+        chunk.write_value(value(x)?);
+    }
+    for name in json.get("globals").as_array() {
+        chunk.globals.intern(name.as_str().ok_or_else(|| malformed("global name"))?);
+    }
+    let mut code = vec![];
+    for x in json.get("code").as_array() {
+        match x.as_array() {
+            [Json::STRING(op), line] => code.push((opcode(op).ok_or_else(|| malformed("instruction"))?, line.as_usize().ok_or_else(|| malformed("line"))?)),
+            _ => return Err(malformed("instruction"))
+        }
+    }
+    chunk.set_code(code);
+    for x in json.get("locals").as_array() {
+        let n: Vec<usize> = x.as_array()[1..].iter().filter_map(|x| x.as_usize()).collect();
+        match (x.as_array().first().and_then(|x| x.as_str()), n.as_slice()) {
+            (Some(name), [line, slot, depth, start, end]) => chunk.locals.push(LocalInfo {name: name.to_string(), line: *line, slot: *slot, depth: *depth, start: *start, end: *end}),
+            _ => return Err(malformed("local"))
+        }
+    }
+    for x in json.get("scopes").as_array() {
+        match x.as_array().iter().filter_map(|x| x.as_usize()).collect::<Vec<usize>>().as_slice() {
+            [depth, start, end] => chunk.scopes.push(ScopeInfo {depth: *depth, start: *start, end: *end}),
+            _ => return Err(malformed("scope"))
+        }
+    }
+    Ok(chunk)
+}
+
+// name and operands of an instruction, stored as e.g. `INVOKE(1, 2)`
+fn operands(x: &OpCode) -> (&'static str, Vec<usize>) {
+    match *x {
+        OpCode::RETURN => ("RETURN", vec![]),
+        OpCode::CONSTANT(a) => ("CONSTANT", vec![a]),
+        OpCode::POP => ("POP", vec![]),
+        OpCode::POPN(n) => ("POPN", vec![n]),
+        OpCode::DEFINE_GLOBAL(a) => ("DEFINE_GLOBAL", vec![a]),
+        OpCode::GET_GLOBAL(a) => ("GET_GLOBAL", vec![a]),
+        OpCode::SET_GLOBAL(a) => ("SET_GLOBAL", vec![a]),
+        OpCode::GET_LOCAL(a) => ("GET_LOCAL", vec![a]),
+        OpCode::SET_LOCAL(a) => ("SET_LOCAL", vec![a]),
+        OpCode::NEGATE => ("NEGATE", vec![]),
+        OpCode::BANG => ("BANG", vec![]),
+        OpCode::ADD => ("ADD", vec![]),
+        OpCode::SUB => ("SUB", vec![]),
+        OpCode::MUL => ("MUL", vec![]),
+        OpCode::DIV => ("DIV", vec![]),
+        OpCode::REM => ("REM", vec![]),
+        OpCode::OR => ("OR", vec![]),
+        OpCode::AND => ("AND", vec![]),
+        OpCode::EQUAL => ("EQUAL", vec![]),
+        OpCode::GREATER => ("GREATER", vec![]),
+        OpCode::LESS => ("LESS", vec![]),
+        OpCode::NOT_EQUAL => ("NOT_EQUAL", vec![]),
+        OpCode::LESS_EQUAL => ("LESS_EQUAL", vec![]),
+        OpCode::GREATER_EQUAL => ("GREATER_EQUAL", vec![]),
+        OpCode::POP_JUMP_IF_FALSE(a) => ("POP_JUMP_IF_FALSE", vec![a]),
+        OpCode::INC_LOCAL(a, b) => ("INC_LOCAL", vec![a, b]),
+        OpCode::LESS_LOCAL_LOCAL(a, b) => ("LESS_LOCAL_LOCAL", vec![a, b]),
+        OpCode::LESS_LOCAL_CONSTANT(a, b) => ("LESS_LOCAL_CONSTANT", vec![a, b]),
+        OpCode::CALL(n) => ("CALL", vec![n]),
+        OpCode::GET_PROPERTY(a) => ("GET_PROPERTY", vec![a]),
+        OpCode::SET_PROPERTY(a) => ("SET_PROPERTY", vec![a]),
+        OpCode::INVOKE(a, n) => ("INVOKE", vec![a, n]),
+        OpCode::PRINT => ("PRINT", vec![]),
+        OpCode::IF(a) => ("IF", vec![a]),
+        OpCode::IFN(a) => ("IFN", vec![a]),
+        OpCode::JMP(a) => ("JMP", vec![a]),
+    }
+}
+
+fn instruction_json(x: &OpCode) -> String {
+    match operands(x) {
+        (name, n) if n.is_empty() => name.to_string(),
+        (name, n) => format!("{}({})", name, n.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", "))
+    }
+}
+
+// inverse of `instruction_json`
+fn opcode(text: &str) -> Option<OpCode> {
+    let (name, operands) = match text.split_once('(') {
+        Some((name, rest)) => (name, rest.strip_suffix(')')?),
+        None => (text, "")
+    };
+    let n: Vec<usize> = if operands.is_empty() { vec![] } else { operands.split(", ").map(|x| x.parse().ok()).collect::<Option<Vec<usize>>>()? };
+    Some(match (name, n.as_slice()) {
+        ("RETURN", []) => OpCode::RETURN,
+        ("CONSTANT", [a]) => OpCode::CONSTANT(*a),
+        ("POP", []) => OpCode::POP,
+        ("POPN", [n]) => OpCode::POPN(*n),
+        ("DEFINE_GLOBAL", [a]) => OpCode::DEFINE_GLOBAL(*a),
+        ("GET_GLOBAL", [a]) => OpCode::GET_GLOBAL(*a),
+        ("SET_GLOBAL", [a]) => OpCode::SET_GLOBAL(*a),
+        ("GET_LOCAL", [a]) => OpCode::GET_LOCAL(*a),
+        ("SET_LOCAL", [a]) => OpCode::SET_LOCAL(*a),
+        ("NEGATE", []) => OpCode::NEGATE,
+        ("BANG", []) => OpCode::BANG,
+        ("ADD", []) => OpCode::ADD,
+        ("SUB", []) => OpCode::SUB,
+        ("MUL", []) => OpCode::MUL,
+        ("DIV", []) => OpCode::DIV,
+        ("REM", []) => OpCode::REM,
+        ("OR", []) => OpCode::OR,
+        ("AND", []) => OpCode::AND,
+        ("EQUAL", []) => OpCode::EQUAL,
+        ("GREATER", []) => OpCode::GREATER,
+        ("LESS", []) => OpCode::LESS,
+        ("NOT_EQUAL", []) => OpCode::NOT_EQUAL,
+        ("LESS_EQUAL", []) => OpCode::LESS_EQUAL,
+        ("GREATER_EQUAL", []) => OpCode::GREATER_EQUAL,
+        ("POP_JUMP_IF_FALSE", [a]) => OpCode::POP_JUMP_IF_FALSE(*a),
+        ("INC_LOCAL", [a, b]) => OpCode::INC_LOCAL(*a, *b),
+        ("LESS_LOCAL_LOCAL", [a, b]) => OpCode::LESS_LOCAL_LOCAL(*a, *b),
+        ("LESS_LOCAL_CONSTANT", [a, b]) => OpCode::LESS_LOCAL_CONSTANT(*a, *b),
+        ("CALL", [n]) => OpCode::CALL(*n),
+        ("GET_PROPERTY", [a]) => OpCode::GET_PROPERTY(*a),
+        ("SET_PROPERTY", [a]) => OpCode::SET_PROPERTY(*a),
+        ("INVOKE", [a, n]) => OpCode::INVOKE(*a, *n),
+        ("PRINT", []) => OpCode::PRINT,
+        ("IF", [a]) => OpCode::IF(*a),
+        ("IFN", [a]) => OpCode::IFN(*a),
+        ("JMP", [a]) => OpCode::JMP(*a),
+        _ => return None
+    })
+}
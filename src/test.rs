@@ -5,9 +5,909 @@ mod tests {
 
     #[test]
     fn variable_tests() -> Result<(), Error> {
-        assert_eq!(Value::NIL, crate::interpret("var a; a;".to_string())?); // variable initializes to nil
-        assert_eq!(Value::FLOAT(5.0), crate::interpret("var a = 5; a;".to_string())?);
-        assert_eq!(Value::FLOAT(5.0), crate::interpret("var a; a = 5; a;".to_string())?);
+        assert_eq!(Value::NIL, Interpreter::new().eval("var a; a;")?); // variable initializes to nil
+        assert_eq!(Value::FLOAT(5.0), Interpreter::new().eval("var a = 5; a;")?);
+        assert_eq!(Value::FLOAT(5.0), Interpreter::new().eval("var a; a = 5; a;")?);
+        // slots follow the stack after sibling blocks, block exit pops all locals at once
+        let source = "var r; { var a = 1; { var b = 2; } { var c = 3; var d = 4; r = c * d; } var e = 5; r = r + a * e; } r;";
+        assert_eq!(Value::FLOAT(17.0), Interpreter::new().eval(source)?);
+        let mut compiler = crate::compiler::Compiler::new(source.to_string());
+        compiler.compile()?;
+        assert!(compiler.chunk.code.iter().any(|x| matches!(x, crate::chunk::OpCode::POPN(2))));
+        // globals may be redeclared and read in their initializer, locals may not
+        assert_eq!(Value::FLOAT(2.0), Interpreter::new().eval("var a = 1; var a = a + 1; a;")?);
+        let error = |source: &str| match Interpreter::new().eval(source) {
+            Err(Error::COMPILE_ERROR(message, _)) => message,
+            _ => String::new()
+        };
+        assert_eq!("NameError: `a` is already declared in this scope", error("{ var a = 1;\n var a = 2; }"));
+        assert_eq!("NameError: can't read local `a` in its own initializer", error("{ var a = 1; { var a = a; } }"));
+        assert_eq!("", error("{ var a = 1; { var b = a; var a = b; } }"));
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn expression_tests() -> Result<(), Error> {
+        let run = |code: &str| Interpreter::new().eval(code);
+        assert_eq!(Value::FLOAT(-5.0), run("1 - 2 * 3;")?); // factor binds tighter than term
+        assert_eq!(Value::FLOAT(3.0), run("10 - 4 - 3;")?); // operators associate to the left
+        assert_eq!(Value::BOOL(true), run("var t = true; (false or t) and (t or false);")?);
+        // locals keep their slots across statements, loops leave the stack balanced
+        assert_eq!(Value::FLOAT(7.0), run("var i = 0; while i < 10 { var k = i; i = i + 1; if k == 6 { break; } } i;")?);
+        assert_eq!(Value::FLOAT(6.0), run("var r; { 1; var a = 2; a = a + 1; r = a * 2; } r;")?);
+        Ok(())
+    }
+
+    #[test]
+    fn folding_tests() -> Result<(), Error> {
+        let compile = |code: &str, level: usize| -> Result<usize, Error> {
+            let mut compiler = crate::compiler::Compiler::new(code.to_string());
+            compiler.set_opt_level(level);
+            compiler.compile()?;
+            Ok(compiler.chunk.code.len())
+        };
+        assert_eq!(2, compile("60 * 60 * 24;", 1)?); // CONSTANT, RETURN
+        assert_eq!(6, compile("60 * 60 * 24;", 0)?);
+        assert_eq!(2, compile("!(\"a\" + \"b\" != \"ab\") and true;", 1)?);
+        // failing operations are kept for the runtime error
+        assert_eq!(4, compile("1 / 0;", 1)?);
+        assert!(Interpreter::new().eval("print 1 / 0;").is_err());
+        // `-0 + 0` is `0`, only adding `-0` and subtracting `0` leave a number unchanged
+        for level in 0..3 {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_opt_level(level);
+            let bits = |interpreter: &mut Interpreter, code: &str| match interpreter.eval(code) {
+                Ok(Value::FLOAT(x)) => x.to_bits(),
+                _ => panic!("expected number")
+            };
+            interpreter.eval("var z = 0;")?;
+            assert_eq!(0.0f64.to_bits(), bits(&mut interpreter, "-z + 0;"));
+            assert_eq!(0.0f64.to_bits(), bits(&mut interpreter, "0 + -z;"));
+            assert_eq!(0.0f64.to_bits(), bits(&mut interpreter, "-z - -0;"));
+            assert_eq!((-0.0f64).to_bits(), bits(&mut interpreter, "-z + -0;"));
+            assert_eq!((-0.0f64).to_bits(), bits(&mut interpreter, "-z - 0;"));
+        }
+        assert_eq!(compile("var z = 0; -z;", 1)?, compile("var z = 0; -z + -0;", 1)?);
+        Ok(())
+    }
+
+    #[test]
+    fn peephole_tests() -> Result<(), Error> {
+        use crate::chunk::OpCode;
+        let mut compiler = crate::compiler::Compiler::new("var a = 1; while a <= 3 { if a != 2 { print a; } a = a + 1; }".to_string());
+        compiler.set_opt_level(1);
+        compiler.compile()?;
+        let code = &compiler.chunk.code;
+        assert!(!code.iter().any(|x| matches!(x, OpCode::BANG | OpCode::IF(_))));
+        assert_eq!(2, code.iter().filter(|x| matches!(x, OpCode::POP_JUMP_IF_FALSE(_))).count());
+        assert!(code.iter().any(|x| matches!(x, OpCode::LESS_EQUAL)) && code.iter().any(|x| matches!(x, OpCode::NOT_EQUAL)));
+        // jump targets still point at the start of the loop and past its end
+        assert!(matches!(code[code.len() - 2], OpCode::JMP(2)));
+        assert!(matches!(code[5], OpCode::POP_JUMP_IF_FALSE(x) if x == code.len() - 1));
+
+        // superinstructions at -O2 compute the same result
+        let source = "var r; { var i = 0; var n = 5; var s = 0; while i < n { s = s + i; i = i + 1; } r = s; } r;";
+        let mut compiler = crate::compiler::Compiler::new(source.to_string());
+        compiler.set_opt_level(2);
+        compiler.compile()?;
+        assert!(compiler.chunk.code.iter().any(|x| matches!(x, OpCode::INC_LOCAL(0, _))));
+        assert!(compiler.chunk.code.iter().any(|x| matches!(x, OpCode::LESS_LOCAL_LOCAL(0, 1))));
+        assert_eq!(Value::FLOAT(10.0), Interpreter::new().eval(source)?);
+        Ok(())
+    }
+
+    // writer whose contents can be inspected after it has been moved into the code under test
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn nanbox_tests() -> Result<(), Error> {
+        use crate::gc::Heap;
+        use crate::nanbox::NanBox;
+        assert_eq!(8, std::mem::size_of::<NanBox>());
+        let mut heap = Heap::default();
+        let values = [Value::FLOAT(-1.5), Value::FLOAT(f64::INFINITY), Value::BOOL(true), Value::BOOL(false), Value::NIL, Value::STRING("oxa".into())];
+        for value in values {
+            assert_eq!(value, heap.manage(value.clone()).to_value());
+        }
+        // NaN is a float, not one of the tagged values
+        assert!(NanBox::float(f64::NAN).as_float().unwrap().is_nan());
+        assert!(NanBox::float(f64::NAN) != NanBox::float(f64::NAN));
+        // the operators agree with `Value`
+        let a = heap.string("ab".into());
+        assert_eq!(Value::STRING("abab".into()), (a + a)?);
+        assert_eq!(Value::STRING("ababab".into()), (a * NanBox::float(3.0))?);
+        assert!(matches!(NanBox::float(1.0) / NanBox::float(0.0), Err(Error::DIVIDE_BY_ZERO)));
+        assert_eq!(Value::FLOAT(1.0) < Value::BOOL(false), NanBox::float(1.0) < NanBox::bool(false));
+        Ok(())
+    }
+
+    #[test]
+    fn gc_tests() -> Result<(), Error> {
+        use crate::gc::Heap;
+        // only objects reachable from the roots survive a collection
+        let mut heap = Heap::default();
+        let kept = heap.string("kept".into());
+        heap.string("garbage".into());
+        heap.collect([kept].iter());
+        assert_eq!((2, 1, 1), (heap.stats().allocations, heap.stats().collections, heap.stats().freed));
+        assert_eq!(Some("kept"), kept.as_str());
+
+        // collecting between every instruction keeps strings held by locals, globals and constants alive
+        let source = "var g = \"g\"; { var s = \"\"; var i = 0; while i < 50 { s = s + g; i = i + 1; } g = s + \"!\"; } g;";
+        let mut compiler = crate::compiler::Compiler::new(source.to_string());
+        compiler.compile()?;
+        let mut vm = crate::vm::VM::default();
+        vm.heap_mut().set_stress(true);
+        vm.set_chunk(compiler.chunk);
+        assert_eq!(Value::STRING("g".repeat(50) + "!"), vm.execute(false)?);
+        assert!(vm.heap().stats().collections >= 50 && vm.heap().stats().freed >= 49);
+        // values read from the VM are copies, they outlive its heap
+        let (globals, stack): (Vec<(String, Value)>, Vec<Value>) = (vm.globals().into_iter().map(|(name, value)| (name.to_string(), value)).collect(), vm.stack());
+        drop(vm);
+        assert_eq!(vec![("g".to_string(), Value::STRING("g".repeat(50) + "!"))], globals);
+        assert!(stack.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn intern_tests() -> Result<(), Error> {
+        use crate::intern::Interner;
+        let mut interner = Interner::default();
+        let a = interner.intern("a");
+        assert_eq!(a, interner.intern("a"));
+        assert!(a != interner.intern("b"));
+        assert_eq!((Some(a), None, "a"), (interner.get("a"), interner.get("c"), interner.name(a)));
+
+        // every repeated constant is stored once, not only consecutive ones, globals get slots
+        let mut compiler = crate::compiler::Compiler::new("var a = \"x\"; var b = 1; a = \"x\" + a; b = b + 1; print a; print b;".to_string());
+        compiler.compile()?;
+        let values = compiler.chunk.values();
+        assert_eq!(1, values.iter().filter(|x| **x == Value::STRING("x".into())).count());
+        assert_eq!(1, values.iter().filter(|x| **x == Value::FLOAT(1.0)).count());
+        assert_eq!(vec!["a", "b"], compiler.chunk.globals.names().collect::<Vec<_>>());
+        // chunks compiled separately share the globals of the VM even if their slots differ
+        let mut vm = crate::vm::VM::default();
+        let mut compiler = crate::compiler::Compiler::new("var a = 1; var b = 2;".to_string());
+        compiler.compile()?;
+        vm.set_chunk(compiler.chunk);
+        vm.execute(false)?;
+        let mut compiler = crate::compiler::Compiler::new("b = b * 10 + a;".to_string());
+        compiler.compile()?;
+        assert_eq!(Value::FLOAT(21.0), vm.evaluate(compiler.chunk)?);
+        assert_eq!(Some(Value::FLOAT(21.0)), vm.global("b"));
+        match Interpreter::new().eval("var a = 1; a = b;") {
+            Err(Error::RUNTIME_ERROR(message, 1)) => assert_eq!("NameError: undefined variable `b`", message),
+            _ => panic!("expected name error")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn interpreter_tests() -> Result<(), Error> {
+        // globals outlive a call, also one which failed halfway
+        let out = SharedBuffer::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Box::new(out.clone()));
+        interpreter.set_global("greeting", Value::STRING("hello".to_string()));
+        assert_eq!(Value::NIL, interpreter.eval("var count = 1; print greeting + \" world\";")?);
+        assert!(interpreter.eval("{ var a = 1; count = count + 1; print a / 0; }").is_err());
+        assert_eq!(Value::FLOAT(3.0), interpreter.eval("count = count + 1; count;")?);
+        assert_eq!(Some(Value::FLOAT(3.0)), interpreter.global("count"));
+        interpreter.set_global("count", Value::FLOAT(10.0));
+        assert_eq!(Value::FLOAT(20.0), interpreter.eval("count * 2;")?);
+        assert_eq!(None, interpreter.global("missing"));
+        assert_eq!("hello world\n", String::from_utf8(out.0.borrow().clone()).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn native_tests() -> Result<(), Error> {
+        use crate::native::{self, Arity};
+        let mut interpreter = Interpreter::new();
+        interpreter.register_native("hypot", 2, |_, args| {
+            let (a, b) = (native::number(args, 0)?, native::number(args, 1)?);
+            Ok(Value::FLOAT((a * a + b * b).sqrt()))
+        });
+        interpreter.register_native("concat", Arity::VARIADIC(0), |_, args| {
+            let parts: Result<Vec<&str>, Error> = (0..args.len()).map(|i| native::string(args, i)).collect();
+            Ok(Value::STRING(parts?.concat()))
+        });
+        interpreter.register_native("fail", 1, |_, args| Err(native::error(format!("ValueError: {}", native::string(args, 0)?))));
+        // natives may use the VM, e.g. to define globals
+        interpreter.register_native("define", 2, |vm, args| {
+            vm.define_global(native::string(args, 0)?, args[1].clone());
+            Ok(Value::NIL)
+        });
+        assert_eq!(Value::FLOAT(5.0), interpreter.eval("hypot(3, 2 + 2);")?);
+        assert_eq!(Value::FLOAT(7.0), interpreter.eval("{ var a = 3; -hypot(a, 4) + hypot(0, 12) * 1; } hypot(3, 4) + 2;")?);
+        assert_eq!(Value::STRING("ab1".to_string()), interpreter.eval("concat(\"a\", concat(), concat(\"b\", \"1\"));")?);
+        assert_eq!(Value::FLOAT(42.0), interpreter.eval("define(\"answer\", 42); answer;")?);
+        let error = |interpreter: &mut Interpreter, source: &str| match interpreter.eval(source) {
+            Err(Error::RUNTIME_ERROR(message, line)) => (message, line),
+            _ => (String::new(), 0)
+        };
+        let cases = [
+            ("var a = 1;\nprint fail(\"bad\");", "ValueError: bad", 2),
+            ("\n\nhypot(1);", "TypeError: `hypot` takes 2 arguments but 1 were given", 3),
+            ("hypot(1, \"2\");", "TypeError: expected number as argument 2, got string", 1),
+            ("var x = 1;\nx(2);", "TypeError: `1` is not callable", 2),
+        ];
+        for (source, message, line) in cases {
+            assert_eq!((message.to_string(), line), error(&mut interpreter, source));
+        }
+        // a native is a value which can be stored and compared
+        assert_eq!(Value::BOOL(true), interpreter.eval("var h = hypot; h == hypot and h != concat;")?);
+        assert_eq!("<native fn hypot>", format!("{}", interpreter.global("h").unwrap()));
+        Ok(())
+    }
+
+    #[test]
+    fn userdata_tests() -> Result<(), Error> {
+        use crate::native;
+        use crate::userdata::{Class, Userdata};
+        struct Request {
+            path: String,
+            headers: Vec<(String, String)>,
+        }
+        let mut class = Class::new("Request");
+        class.add_method("header", 1, |_, this, args| {
+            let name = native::string(args, 0)?;
+            let request = this.borrow::<Request>()?;
+            Ok(request.headers.iter().find(|x| x.0 == name).map(|x| Value::STRING(x.1.clone())).unwrap_or(Value::NIL))
+        });
+        class.add_getter("path", |_, this| Ok(Value::STRING(this.borrow::<Request>()?.path.clone())));
+        class.add_setter("path", |_, this, value| {
+            this.borrow_mut::<Request>()?.path = native::string(&[value], 0)?.to_string();
+            Ok(())
+        });
+        class.add_getter("size", |_, this| Ok(Value::FLOAT(this.borrow::<Request>()?.headers.len() as f64)));
+        class.set_display(|this| format!("Request({})", this.borrow::<Request>().unwrap().path));
+        class.set_equal(|a, b| a.borrow::<Request>().unwrap().path == b.borrow::<Request>().unwrap().path);
+
+        let out = SharedBuffer::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Box::new(out.clone()));
+        let class = interpreter.register_class(class);
+        // the host keeps a handle to the object and sees the changes of the script
+        let request = std::rc::Rc::new(std::cell::RefCell::new(Request {path: "/a".into(), headers: vec![("x".into(), "1".into())]}));
+        interpreter.set_global("req", Value::USERDATA(Userdata::shared(class, request.clone())));
+        interpreter.set_global("other", interpreter.userdata("Request", Request {path: "/b".into(), headers: vec![]})?);
+        assert_eq!(Value::STRING("1".into()), interpreter.eval("req.header(\"x\");")?);
+        assert_eq!(Value::NIL, interpreter.eval("req.header(\"y\");")?);
+        assert_eq!(Value::BOOL(true), interpreter.eval("req != other and req == req; var p = req.path = \"/b\"; p == req.path and req == other;")?);
+        interpreter.eval("print req; print req.size + other.size;")?;
+        assert_eq!("/b", request.borrow().path);
+        assert_eq!("Request(/b)\n1\n", String::from_utf8(out.0.borrow().clone()).unwrap());
+
+        let error = |interpreter: &mut Interpreter, source: &str| match interpreter.eval(source) {
+            Err(Error::RUNTIME_ERROR(message, line)) => (message, line),
+            _ => (String::new(), 0)
+        };
+        let cases = [
+            ("req.nope;", "AttributeError: `Request` object has no property `nope`"),
+            ("req.header;", "TypeError: method `header` of `Request` must be called"),
+            ("req.size = 2;", "AttributeError: property `size` of `Request` is read-only"),
+            ("req.path = 1;", "TypeError: expected string as argument 1, got number"),
+            ("req.nope();", "AttributeError: `Request` object has no method `nope`"),
+            ("req.header(1, 2);", "TypeError: `header` takes 1 argument but 2 were given"),
+            ("var n = 1; n.x;", "TypeError: `1` has no property `x`"),
+        ];
+        for (source, message) in cases {
+            assert_eq!((message.to_string(), 2), error(&mut interpreter, &format!("\n{}", source)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn convert_tests() -> Result<(), Error> {
+        use crate::native::{self, Arity};
+        let message = |result: Result<(), Error>| match result {
+            Err(Error::RUNTIME_ERROR(message, _)) => message,
+            _ => String::new()
+        };
+        assert_eq!(Value::FLOAT(3.0), 3u8.into_value());
+        assert_eq!(Value::STRING("a".into()), "a".into_value());
+        assert_eq!(Value::NIL, None::<bool>.into_value());
+        assert_eq!(-7i64, i64::from_value(Value::FLOAT(-7.0))?);
+        assert_eq!(Some("x".to_string()), Option::<String>::from_value(Value::STRING("x".into()))?);
+        assert_eq!(None, Option::<f64>::from_value(Value::NIL)?);
+        assert_eq!("ValueError: 256 is out of range for u8", message(u8::from_value(Value::FLOAT(256.0)).map(|_| ())));
+        assert_eq!("ValueError: -1 is out of range for usize", message(usize::from_value(Value::FLOAT(-1.0)).map(|_| ())));
+        assert_eq!("ValueError: expected integer, got 1.5", message(i32::from_value(Value::FLOAT(1.5)).map(|_| ())));
+        assert_eq!("TypeError: expected bool, got nil", message(bool::from_value(Value::NIL).map(|_| ())));
+
+        // natives convert their arguments, the host calls them with Rust values
+        let mut interpreter = Interpreter::new();
+        interpreter.register_native("repeat", 2, |_, args| {
+            let (text, n): (String, u8) = (native::arg(args, 0)?, native::arg(args, 1)?);
+            Ok(text.repeat(n as usize).into_value())
+        });
+        interpreter.register_native("count", Arity::VARIADIC(0), |_, args| Ok(args.len().into_value()));
+        interpreter.set_global("greeting", "hi");
+        assert_eq!("hihihi", interpreter.call::<String>("repeat", ("hi", 3))?);
+        assert_eq!(0, interpreter.call::<usize>("count", ())?);
+        assert_eq!(Value::STRING("hihi".into()), interpreter.eval("repeat(greeting, count(1, nil));")?);
+        assert_eq!("ValueError: expected integer, got 0.5", message(interpreter.call("repeat", ("a", 0.5))));
+        assert_eq!("TypeError: expected string, got number", message(interpreter.call::<String>("count", (1, true, "x")).map(|_| ())));
+        assert_eq!("TypeError: `\"hi\"` is not callable", message(interpreter.call("greeting", ())));
+        assert_eq!("NameError: undefined variable `nope`", message(interpreter.call("nope", ())));
+        match interpreter.eval("\n\nrepeat(\"a\", -1);") {
+            Err(Error::RUNTIME_ERROR(message, 3)) => assert_eq!("ValueError: -1 is out of range for u8", message),
+            _ => panic!("expected conversion error")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn limit_tests() -> Result<(), Error> {
+        use crate::limits::{Limit, Limits};
+        use std::time::Duration;
+        let run = |limits: Limits, source: &str| {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_limits(limits);
+            interpreter.eval(source)
+        };
+        let limit = |result: Result<Value, Error>| match result {
+            Err(Error::LIMIT_EXCEEDED(limit, line)) => Some((limit, line)),
+            _ => None
+        };
+        let forever = "var i = 0;\nwhile true { i = i + 1; }";
+        let limits = Limits {instructions: Some(1000), ..Limits::default()};
+        assert_eq!(Some((Limit::INSTRUCTIONS(1000), 2)), limit(run(limits.clone(), forever)));
+        // the budget is per run, not per interpreter
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(limits);
+        for _ in 0..3 {
+            assert_eq!(Value::FLOAT(45.0), interpreter.eval("var s = 0; var i = 0; while i < 10 { s = s + i; i = i + 1; } s;")?);
+        }
+
+        let limits = Limits {timeout: Some(Duration::from_millis(20)), ..Limits::default()};
+        assert_eq!(Some((Limit::TIMEOUT(Duration::from_millis(20)), 2)), limit(run(limits, forever)));
+
+        let limits = Limits {stack: Some(5), ..Limits::default()};
+        assert_eq!(Value::FLOAT(6.0), run(limits.clone(), "var r; { var a = 1; var b = 2; var c = 3; r = a + b + c; } r;")?);
+        assert_eq!(Some((Limit::STACK(5), 1)), limit(run(limits, "{ var a = 1; var b = 2; var c = 3; var d = 4; var e = 5; var f = 6; }")));
+
+        // repeating is refused before the string is built, garbage doesn't count
+        let limits = Limits {memory: Some(64 * 1024), ..Limits::default()};
+        assert_eq!(Some((Limit::MEMORY(64 * 1024), 1)), limit(run(limits.clone(), "var s = \"x\" * 1000000000000000;")));
+        assert_eq!(Some((Limit::MEMORY(64 * 1024), 1)), limit(run(limits.clone(), "var s = \"ab\"; while true { s = s + s; }")));
+        assert_eq!(Value::FLOAT(1000.0), run(limits, "var i = 0; while i < 1000 { var s = \"x\" * 1000; i = i + 1; } i;")?);
+        Ok(())
+    }
+
+    #[test]
+    fn sandbox_tests() -> Result<(), Error> {
+        use crate::native;
+        use crate::sandbox::{Capability, Sandbox};
+        use crate::userdata::Class;
+        let denied = |result: Result<Value, Error>| match result {
+            Err(Error::PERMISSION_DENIED(capability, resource, line)) => Some((capability, resource, line)),
+            _ => None
+        };
+        // nothing is printed without the capability
+        let out = SharedBuffer::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Box::new(out.clone()));
+        interpreter.set_sandbox(Sandbox::deny_all());
+        assert_eq!(Some((Capability::PRINT, None, 2)), denied(interpreter.eval("var a = 1;\nprint a;")));
+        assert!(out.0.borrow().is_empty());
+
+        // the VM checks the capability natives and methods are registered with before calling them,
+        // the first argument is the resource
+        let sandbox = Sandbox::parse("# untrusted scripts\nprint\nenv = HOME, LANG\nread = /srv/data\n")?;
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Box::new(out.clone()));
+        interpreter.set_sandbox(sandbox);
+        interpreter.register_native_requiring("env", 1, Capability::ENV, |_, args| Ok(Value::STRING(format!("${}", native::string(args, 0)?))));
+        interpreter.register_native_requiring("read", 1, Capability::READ, |_, _| Ok(Value::NIL));
+        interpreter.register_native_requiring("clock", 0, Capability::CLOCK, |_, _| panic!("called without the capability"));
+        // further resources are checked by the native
+        interpreter.register_native_requiring("copy", 2, Capability::READ, |vm, args| {
+            vm.require(Capability::WRITE, Some(native::string(args, 1)?))?;
+            Ok(Value::NIL)
+        });
+        let mut class = Class::new("Dir");
+        class.add_method_requiring("list", 1, Capability::READ, |_, _, _| Ok(Value::FLOAT(2.0)));
+        class.add_method_requiring("remove", 1, Capability::WRITE, |_, _, _| panic!("called without the capability"));
+        interpreter.register_class(class);
+        let dir = interpreter.vm().userdata("Dir", ())?;
+        interpreter.set_global("dir", dir);
+        interpreter.eval("print env(\"HOME\"); read(\"/srv/data/a.txt\"); print dir.list(\"/srv/data\");")?;
+        assert_eq!("$HOME\n2\n", String::from_utf8(out.0.borrow().clone()).unwrap());
+        let resource = |capability: Capability, resource: &str, line: usize| Some((capability, Some(resource.to_string()), line));
+        assert_eq!(resource(Capability::ENV, "PATH", 1), denied(interpreter.eval("env(\"PATH\");")));
+        assert_eq!("PermissionError: `env` of `PATH` is not allowed", Sandbox::message(Capability::ENV, Some("PATH")));
+        assert_eq!(resource(Capability::READ, "/srv/data/../secret", 2), denied(interpreter.eval("\nread(\"/srv/data/../secret\");")));
+        assert_eq!(resource(Capability::READ, "/srv/database", 1), denied(interpreter.eval("read(\"/srv/database\");")));
+        assert_eq!(Some((Capability::READ, None, 1)), denied(interpreter.eval("read(1);")));
+        assert_eq!(Some((Capability::CLOCK, None, 1)), denied(interpreter.eval("clock();")));
+        assert_eq!(resource(Capability::WRITE, "/srv/data/b", 1), denied(interpreter.eval("copy(\"/srv/data/a\", \"/srv/data/b\");")));
+        assert_eq!(resource(Capability::WRITE, "/srv/data", 1), denied(interpreter.eval("dir.remove(\"/srv/data\");")));
+
+        match Sandbox::parse("print\nnetwork\n") {
+            Err(Error::COMPILE_ERROR(message, 2)) => assert_eq!("ConfigError: unknown capability `network`", message),
+            _ => panic!("expected config error")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn interrupt_tests() -> Result<(), Error> {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_native("nop", 0, |_, _| Ok(Value::NIL));
+        let handle = interpreter.interrupt_handle();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            handle.interrupt();
+        });
+        match interpreter.eval("var i = 0;\nwhile true { i = i + 1; }") {
+            Err(Error::INTERRUPTED(2)) => {},
+            _ => panic!("expected interrupt")
+        }
+        thread.join().unwrap();
+        // the interrupt stopped one program, globals survive it
+        assert!(interpreter.vm().stack().is_empty());
+        assert_eq!(Value::BOOL(true), interpreter.eval("i > 0;")?);
+        // calls check for interrupts too, an interrupt before the run isn't lost
+        interpreter.interrupt_handle().interrupt();
+        assert!(matches!(interpreter.eval("1;\nnop();"), Err(Error::INTERRUPTED(2))));
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_tests() -> Result<(), Error> {
+        use crate::chunk::OpCode;
+        use crate::native;
+        use crate::snapshot::{Snapshot, Stored, Word};
+        use crate::vm::{Hook, VM};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // stops the program after `steps` instructions and stores its state
+        struct Pause {
+            steps: usize,
+            file: Rc<RefCell<Vec<u8>>>,
+        }
+        impl Hook for Pause {
+            fn before_instruction(&mut self, vm: &mut VM) -> Result<(), Error> {
+                self.steps -= 1;
+                if self.steps > 0 {
+                    return Ok(());
+                }
+                vm.snapshot()?.write(&mut *self.file.borrow_mut())?;
+                Err(Error::INTERRUPTED(vm.chunk().get_line(vm.ip())))
+            }
+        }
+        let text = |out: &SharedBuffer| String::from_utf8(out.0.borrow().clone()).unwrap();
+        let interpreter = |out: &SharedBuffer| {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_output(Box::new(out.clone()));
+            interpreter.register_native("double", 1, |_, args| Ok(Value::FLOAT(2.0 * native::number(args, 0)?)));
+            interpreter
+        };
+        let source = "var s = \"ab\" * 2; var t = s; var f = double; var total = 0; var i = 0;
+            { var k = 10; while i < 8 { total = total + f(i); print total; k = k + 1; i = i + 1; } print k; }
+            print t; total;";
+
+        let out = SharedBuffer::default();
+        let expected = interpreter(&out).eval(source)?;
+        let expected_text = text(&out);
+
+        let (before, after, file) = (SharedBuffer::default(), SharedBuffer::default(), Rc::new(RefCell::new(vec![])));
+        let mut first = interpreter(&before);
+        first.vm_mut().set_hook(Box::new(Pause {steps: 60, file: file.clone()}));
+        assert!(matches!(first.eval(source), Err(Error::INTERRUPTED(2))));
+        assert!(!text(&before).is_empty() && text(&before).len() < expected_text.len());
+
+        let snapshot = Snapshot::read(&mut file.borrow().as_slice())?;
+        // `s` and `t` share one string
+        assert_eq!(1, snapshot.objects.iter().filter(|x| matches!(x, Stored::STRING(s) if s == "abab")).count());
+        let mut second = interpreter(&after);
+        second.restore(snapshot)?;
+        assert_eq!(expected, second.resume()?);
+        assert_eq!(expected_text, text(&before) + &text(&after));
+
+        // natives are looked up by name, the version guards against old files
+        assert!(matches!(Interpreter::new().restore(Snapshot::read(&mut file.borrow().as_slice())?), Err(Error::RUNTIME_ERROR(m, _)) if m == "SnapshotError: native `double` is not registered"));
+        let old = String::from_utf8(file.borrow().clone()).unwrap().replacen("\"version\":1", "\"version\":0", 1);
+        assert!(matches!(Snapshot::read(&mut old.as_bytes()), Err(Error::RUNTIME_ERROR(m, _)) if m == "SnapshotError: unsupported version 0, expected 1"));
+
+        let nested = "[".repeat(1_000_000);
+        assert!(matches!(Snapshot::read(&mut nested.as_bytes()), Err(Error::RUNTIME_ERROR(m, _)) if m == "SnapshotError: malformed snapshot, too deeply nested"));
+
+        // operands are checked against the constants, globals, stack and code they refer to
+        let snapshot = Snapshot::read(&mut file.borrow().as_slice())?;
+        let depth = snapshot.stack.len();
+        let number = snapshot.constants.iter().position(|x| matches!(x, Word::FLOAT(_))).unwrap();
+        for bad in [OpCode::GET_LOCAL(99), OpCode::INC_LOCAL(depth, 0), OpCode::CONSTANT(99), OpCode::GET_GLOBAL(99), OpCode::JMP(999), OpCode::POPN(depth + 1), OpCode::GET_PROPERTY(number)] {
+            let mut edited = snapshot.clone();
+            edited.chunk.code[edited.ip] = bad;
+            let mut bytes = vec![];
+            edited.write(&mut bytes)?;
+            assert!(matches!(Snapshot::read(&mut bytes.as_slice()), Err(Error::RUNTIME_ERROR(m, _)) if m.starts_with("SnapshotError: malformed snapshot")));
+        }
+
+        // compiled code can be stored before any of its instructions
+        struct Store;
+        impl Hook for Store {
+            fn before_instruction(&mut self, vm: &mut VM) -> Result<(), Error> {
+                let mut bytes = vec![];
+                vm.snapshot()?.write(&mut bytes)?;
+                Snapshot::read(&mut bytes.as_slice()).map(|_| ())
+            }
+        }
+        let source = "var n = 0; { var a = 1; while true { var b = a; { var c = b; a = a + 1; if c > 3 { break; } if c == 2 { continue; } n = n + c; } }
+            var i = 0; while i < 3 or false and n > 0 { var d = i; i = i + 1; } }";
+        for level in 0..3 {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_opt_level(level);
+            interpreter.vm_mut().set_hook(Box::new(Store));
+            interpreter.eval(source)?;
+        }
+
+        // natives run halfway through `CALL` and expressions of a debugger on top of the paused program
+        let stored = |vm: &mut VM, _: &[Value]| vm.snapshot().map(|_| Value::FLOAT(42.0));
+        fn outside(result: Result<Value, Error>) -> bool {
+            matches!(result, Err(Error::RUNTIME_ERROR(m, _)) if m == "SnapshotError: a program can only be stored between two instructions")
+        }
+        let mut interpreter = Interpreter::new();
+        interpreter.register_native("snap", 0, stored);
+        assert!(outside(interpreter.eval("var x = snap();")));
+        struct Evaluate(Rc<RefCell<usize>>);
+        impl Hook for Evaluate {
+            fn before_instruction(&mut self, vm: &mut VM) -> Result<(), Error> {
+                let native = vm.global("snap").unwrap();
+                assert!(outside(crate::debugger::evaluate(vm, "snap()")) && outside(vm.call(&native, &[])));
+                *self.0.borrow_mut() += 1;
+                vm.snapshot().map(|_| ())
+            }
+        }
+        let checked = Rc::new(RefCell::new(0));
+        interpreter.vm_mut().set_hook(Box::new(Evaluate(checked.clone())));
+        interpreter.eval("1 + 2;")?;
+        assert_eq!(2, *checked.borrow()); // CONSTANT, RETURN
+        Ok(())
+    }
+
+    #[test]
+    fn register_vm_tests() -> Result<(), Error> {
+        use crate::compiler::Compiler;
+        // both machines print the same lines and return the same value, also when collecting all the time
+        let run = |source: &str| -> Result<(String, Value, String, Value), Error> {
+            let mut compiler = Compiler::new(source.to_string());
+            compiler.set_opt_level(1);
+            compiler.compile()?;
+            let stack_out = SharedBuffer::default();
+            let mut vm = crate::vm::VM::default();
+            vm.heap_mut().set_stress(true);
+            vm.set_output(Box::new(stack_out.clone()));
+            vm.set_chunk(compiler.chunk.clone());
+            let stack_value = vm.execute(false)?;
+
+            let chunk = crate::register::Generator::new().program(&compiler.statements);
+            let register_out = SharedBuffer::default();
+            let mut vm = crate::register_vm::RegisterVM::default();
+            vm.heap_mut().set_stress(true);
+            vm.set_output(Box::new(register_out.clone()));
+            let register_value = vm.execute(&chunk)?;
+            let text = |x: SharedBuffer| String::from_utf8(x.0.borrow().clone()).unwrap();
+            Ok((text(stack_out), stack_value, text(register_out), register_value))
+        };
+        let programs = [
+            "1 + 2 * 3 - 4 / 2;",
+            "var a = \"x\"; var b = a * 3; print b; b == \"xxx\";",
+            "var i = 0; var s = 0; while i < 10 { i = i + 1; if i % 2 == 0 { continue; } if i > 7 { break; } s = s + i; } print s; s;",
+            "{ var a = 1; var b = a = 2; print a + b; { var a = 10; print a; } a >= b; }",
+            "var t = true; print (false or t) and !(t and false); var n = nil; n or 5;",
+            "var x; { var y = 3; x = y = y + 1; } print x; x <= 4;",
+        ];
+        for source in programs {
+            let (stack_out, stack_value, register_out, register_value) = run(source)?;
+            assert_eq!(stack_out, register_out, "{}", source);
+            assert_eq!(stack_value, register_value, "{}", source);
+        }
+        // runtime errors carry the same message and line
+        let chunk = {
+            let mut compiler = Compiler::new("var a = 1;\nprint a + \"b\";".to_string());
+            compiler.analyze()?;
+            crate::register::Generator::new().program(&compiler.statements)
+        };
+        match crate::register_vm::RegisterVM::default().execute(&chunk) {
+            Err(Error::RUNTIME_ERROR(message, 2)) => assert!(message.starts_with("TypeError")),
+            _ => panic!("expected type error on line 2")
+        }
+        // like the stack machine, a machine keeps its globals for the next chunk, e.g. the next line of the REPL
+        let program = |source: &str| -> Result<crate::register::RegisterChunk, Error> {
+            let mut compiler = Compiler::new(source.to_string());
+            compiler.analyze()?;
+            Ok(crate::register::Generator::new().program(&compiler.statements))
+        };
+        let mut vm = crate::register_vm::RegisterVM::default();
+        vm.execute(&program("var a = 20;")?)?;
+        assert_eq!(Value::FLOAT(42.0), vm.execute(&program("var b = 1; a * 2 + b + b;")?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn debugger_session() -> Result<(), Error> {
+        use crate::debugger::Console;
+        let source = "var a = 1;\nvar b = 2;\n{\n    var c = a + b;\n    print c;\n}\nprint a;\nb = b * 10;\nprint b;\n";
+        let run = |commands: &str| -> (Result<Value, Error>, String, String) {
+            let (console_out, program_out) = (SharedBuffer::default(), SharedBuffer::default());
+            let mut vm = crate::vm::VM::default();
+            vm.set_output(Box::new(program_out.clone()));
+            vm.set_hook(Box::new(Console::new(source, std::io::Cursor::new(commands.to_string()), console_out.clone())));
+            let mut compiler = crate::compiler::Compiler::new(source.to_string());
+            compiler.compile().unwrap();
+            vm.set_chunk(compiler.chunk);
+            let result = vm.execute(false);
+            let text = |out: &SharedBuffer| String::from_utf8(out.0.borrow().clone()).unwrap();
+            (result, text(&console_out), text(&program_out))
+        };
+
+        // stops on entry, breakpoints on lines without code move to the next line with code
+        let commands = "break 3\nbreak 5\ncontinue\ncontinue\nlocals\nprint c * 2\nset a = c + 10\nfinish\nstep\nglobals\nquit\n";
+        let (result, console, program) = run(commands);
+        assert!(matches!(result, Err(Error::INTERRUPTED(8))));
+        let transcript: Vec<&str> = console.split("(oxa) ").flat_map(|x| x.lines()).collect();
+        assert_eq!(vec![
+            "   1 | var a = 1;",
+            "breakpoint set at line 4",
+            "breakpoint set at line 5",
+            "breakpoint at line 4",
+            "   4 |     var c = a + b;",
+            "breakpoint at line 5",
+            "   5 |     print c;",
+            "c = 3",
+            "6",
+            "a = 13",
+            "   7 | print a;", // finish leaves the block
+            "   8 | b = b * 10;",
+            "a = 13",
+            "b = 2",
+        ], transcript);
+        // quitting stopped the program before it printed `b`
+        assert_eq!("3\n13\n", program);
+
+        // stepping shows every line, end of input stops the program
+        let (result, console, program) = run("step\nstep\n");
+        assert!(matches!(result, Err(Error::INTERRUPTED(4))));
+        assert!(console.contains("   2 | var b = 2;") && console.contains("   4 |     var c = a + b;"));
+        assert_eq!("", program);
+        Ok(())
+    }
+
+    #[test]
+    fn trace_tests() -> Result<(), Error> {
+        use crate::trace::{parse_line_range, Tracer};
+        let trace = |lines: Option<(usize, usize)>| -> Result<String, Error> {
+            let out = SharedBuffer::default();
+            let mut interpreter = Interpreter::new();
+            interpreter.set_opt_level(0);
+            interpreter.set_output(Box::new(SharedBuffer::default()));
+            interpreter.vm_mut().set_tracer(Tracer::new(Box::new(out.clone()), lines));
+            interpreter.eval("var a = 1;\nprint a + 2;\n")?;
+            let bytes = out.0.borrow().clone();
+            Ok(String::from_utf8(bytes).unwrap())
+        };
+        // offset, line, instruction and the stack before the instruction runs
+        let expected = "\
+0000     1  CONSTANT 0 (1)           []
+0001     1  DEFINE_GLOBAL a          [1]
+0002     2  GET_GLOBAL a             []
+0003     2  CONSTANT 1 (2)           [1]
+0004     2  ADD                      [1, 2]
+0005     2  PRINT                    [3]
+0006     2  RETURN                   []
+";
+        assert_eq!(expected, trace(None)?);
+        assert_eq!(expected.lines().skip(2).map(|x| format!("{}\n", x)).collect::<String>(), trace(Some((2, 2)))?);
+        assert_eq!("", trace(Some((3, 5)))?);
+
+        assert_eq!(Some((2, 4)), parse_line_range("2-4"));
+        assert_eq!(Some((2, usize::MAX)), parse_line_range("2-"));
+        assert_eq!(Some((0, 4)), parse_line_range("-4"));
+        for invalid in ["4", "4-2", "a-b", "1-2-3", ""] {
+            assert_eq!(None, parse_line_range(invalid));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dap_session() -> Result<(), Error> {
+        use crate::json::{self, Json};
+
+        let path = std::env::temp_dir().join(format!("oxa_dap_{}.oxa", std::process::id()));
+        std::fs::write(&path, "var a = 1;\n{\n    var b = a + 1;\n    print b;\n}\n").unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        // scripted client, requests are answered in order so no responses need to be awaited
+        let requests = vec![
+            ("initialize", Json::object(vec![("adapterID", "oxa".into())])),
+            ("launch", Json::object(vec![("program", path.as_str().into())])),
+            ("setBreakpoints", Json::object(vec![("breakpoints", vec![Json::object(vec![("line", 4.into())])].into())])),
+            ("configurationDone", Json::NULL),
+            ("stackTrace", Json::object(vec![("threadId", 1.into())])),
+            ("variables", Json::object(vec![("variablesReference", 1.into())])),
+            ("evaluate", Json::object(vec![("expression", "b * 10".into())])),
+            ("continue", Json::object(vec![("threadId", 1.into())])),
+            ("disconnect", Json::NULL),
+        ];
+        let mut input = vec![];
+        for (i, (command, arguments)) in requests.into_iter().enumerate() {
+            let request = Json::object(vec![("seq", (i + 1).into()), ("type", "request".into()), ("command", command.into()), ("arguments", arguments)]);
+            json::write_message(&mut input, &request)?;
+            if i == 0 {
+                // answered, the session goes on
+                input.extend_from_slice(b"Content-Length: 9\r\n\r\n{\"seq\": }");
+            }
+        }
+        let output = SharedBuffer::default();
+        crate::dap::serve(Box::new(std::io::Cursor::new(input)), Box::new(output.clone()))?;
+        std::fs::remove_file(&path).unwrap();
+
+        let mut messages = vec![];
+        let bytes = output.0.borrow().clone();
+        let mut reader = std::io::Cursor::new(bytes);
+        while let Some(message) = json::read_message(&mut reader)? {
+            messages.push(message.unwrap());
+        }
+        let response = |command: &str| messages.iter().find(|x| x.get("command").as_str() == Some(command)).unwrap().clone();
+        let event = |event: &str| messages.iter().find(|x| x.get("event").as_str() == Some(event)).cloned();
+
+        let (responses, failed): (Vec<&Json>, Vec<&Json>) = messages.iter().filter(|x| x.get("type").as_str() == Some("response")).partition(|x| x.get("success") == &Json::BOOL(true));
+        assert_eq!(9, responses.len());
+        assert!(matches!(failed.as_slice(), [x] if x.get("message").as_str().unwrap().starts_with("ProtocolError: ")));
+        assert_eq!(Some(4), response("setBreakpoints").get("body").get("breakpoints").as_array()[0].get("line").as_usize());
+        assert_eq!(Some("breakpoint"), event("stopped").unwrap().get("body").get("reason").as_str());
+        assert_eq!(Some(4), response("stackTrace").get("body").get("stackFrames").as_array()[0].get("line").as_usize());
+        let variables = response("variables");
+        let b = &variables.get("body").get("variables").as_array()[0];
+        assert_eq!((Some("b"), Some("2")), (b.get("name").as_str(), b.get("value").as_str()));
+        assert_eq!(Some("20"), response("evaluate").get("body").get("result").as_str());
+        assert_eq!(Some("2\n"), event("output").unwrap().get("body").get("output").as_str());
+        assert!(event("terminated").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn lsp_session() -> Result<(), Error> {
+        use crate::json::{self, Json};
+
+        let uri = "file:///session.oxa";
+        // the emoji take two UTF-16 code units each, positions after them are shifted by two
+        let text = "var s = \"😀😀\"; var t = s;\n{\n    var u = t;\n    print u;\n}\n";
+        let document = |text: &str| Json::object(vec![("uri", uri.into()), ("text", text.into())]);
+        let at = |line: usize, character: usize| Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", uri.into())])),
+            ("position", Json::object(vec![("line", line.into()), ("character", character.into())])),
+            ("context", Json::object(vec![("includeDeclaration", true.into())])),
+        ]);
+        let messages = vec![
+            (Some(1), "initialize", Json::object(vec![])),
+            (None, "textDocument/didOpen", Json::object(vec![("textDocument", document(text))])),
+            (Some(2), "textDocument/semanticTokens/full", Json::object(vec![("textDocument", document(text))])),
+            (Some(3), "textDocument/definition", at(0, 24)),
+            (Some(4), "textDocument/references", at(3, 10)),
+            (Some(5), "textDocument/hover", at(0, 24)),
+            (None, "textDocument/didChange", Json::object(vec![
+                ("textDocument", document("")),
+                ("contentChanges", vec![Json::object(vec![("text", "var = \"😀\";\n".into())])].into()),
+            ])),
+            (None, "textDocument/didSave", Json::object(vec![("textDocument", document(""))])),
+            (Some(6), "shutdown", Json::NULL),
+            (None, "exit", Json::NULL),
+        ];
+        let mut input = vec![];
+        for (id, method, params) in messages {
+            let mut message = vec![("jsonrpc", "2.0".into()), ("method", method.into()), ("params", params)];
+            if let Some(id) = id {
+                message.push(("id", Json::from(id)));
+            }
+            json::write_message(&mut input, &Json::object(message))?;
+            if id == Some(1) {
+                // bodies which aren't JSON or are too long are answered and skipped
+                input.extend_from_slice(b"Content-Length: 7\r\n\r\n{\"id\":}");
+                let length = 16 * 1024 * 1024 + 1;
+                input.extend_from_slice(format!("Content-Length: {}\r\n\r\n", length).as_bytes());
+                input.resize(input.len() + length, b' ');
+            }
+        }
+        let output = SharedBuffer::default();
+        crate::lsp::serve(Box::new(std::io::Cursor::new(input)), Box::new(output.clone()))?;
+
+        let mut messages = vec![];
+        let mut reader = std::io::Cursor::new(output.0.borrow().clone());
+        while let Some(message) = json::read_message(&mut reader)? {
+            messages.push(message.unwrap());
+        }
+        let result = |id: usize| messages.iter().find(|x| x.get("id").as_usize() == Some(id)).unwrap().get("result").clone();
+        let diagnostics: Vec<&Json> = messages.iter().filter(|x| x.get("method").as_str() == Some("textDocument/publishDiagnostics")).collect();
+        let position = |x: &Json| (x.get("line").as_usize(), x.get("character").as_usize());
+
+        assert_eq!(Some("utf-16"), result(1).get("capabilities").get("positionEncoding").as_str());
+        let errors: Vec<&Json> = messages.iter().filter(|x| x.get("id").is_null() && x.get("method").is_null()).map(|x| x.get("error").get("code")).collect();
+        assert_eq!(vec![&Json::NUMBER(-32700.0); 2], errors);
+        // [delta line, delta start, length, type, modifiers] of `var s = "😀😀"; var`
+        let tokens: Vec<Option<usize>> = result(2).get("data").as_array().iter().take(25).map(|x| x.as_usize()).collect();
+        let expected = [0, 0, 3, 0, 0, 0, 4, 1, 1, 0, 0, 2, 1, 4, 0, 0, 2, 6, 2, 0, 0, 8, 3, 0, 0];
+        assert_eq!(expected.iter().map(|x| Some(*x)).collect::<Vec<_>>(), tokens);
+        let definition = result(3).get("range").clone();
+        assert_eq!((position(definition.get("start")), position(definition.get("end"))), ((Some(0), Some(4)), (Some(0), Some(5))));
+        let references: Vec<_> = result(4).as_array().iter().map(|x| position(x.get("range").get("start"))).collect();
+        assert_eq!(vec![(Some(2), Some(8)), (Some(3), Some(10))], references);
+        assert!(result(5).get("contents").get("value").as_str().unwrap().contains("global variable"));
+
+        // the document opened cleanly, the saved change doesn't compile
+        assert_eq!(2, diagnostics.len());
+        assert!(diagnostics[0].get("params").get("diagnostics").as_array().is_empty());
+        let error = &diagnostics[1].get("params").get("diagnostics").as_array()[0];
+        assert_eq!((Some(0), Some(11)), position(error.get("range").get("end")));
+        Ok(())
+    }
+
+    #[test]
+    fn format_tests() -> Result<(), Error> {
+        let source = "// counter\nvar  i=0;  // start\n\n\nwhile i<10 {i = i+1;\n  if i==5 {break;} else { print i*2+1; }}\n";
+        let expected = "// counter\nvar i = 0; // start\n\nwhile i < 10 {\n    i = i + 1;\n    if i == 5 {\n        break;\n    } else {\n        print i*2 + 1;\n    }\n}\n";
+        let formatted = crate::fmt::format(source, crate::fmt::DEFAULT_WIDTH)?;
+        assert_eq!(expected, formatted);
+        // formatting is idempotent, also when lines have to be wrapped
+        assert_eq!(formatted, crate::fmt::format(&formatted, crate::fmt::DEFAULT_WIDTH)?);
+        let wrapped = crate::fmt::format("var total = 1111 + 2222 * 3333 - 4444 + (5555 - 6666);", 30)?;
+        assert_eq!("var total = 1111\n    + 2222*3333\n    - 4444\n    + (5555 - 6666);\n", wrapped);
+        assert_eq!(wrapped, crate::fmt::format(&wrapped, 30)?);
+        assert_eq!("print -f(a, g()) * 2;\n", crate::fmt::format("print -f( a ,g( ) )*2;", 30)?);
+        assert_eq!("req.path = a.b(1).c + 1;\n", crate::fmt::format("req . path=a.b( 1 ).c+1;", 30)?);
+        Ok(())
+    }
+
+    #[test]
+    fn lint_tests() -> Result<(), Error> {
+        use crate::lint::{self, Config};
+        let source = "var g = 1;\n{\n    var unused = 2;\n    var g = 3; // oxa-allow(unused-variable)\n}\nprint h;\nif (g = 2) { print g; }\n";
+        let rules = |config: &Config| -> Result<Vec<(usize, &str)>, Error> {
+            Ok(lint::lint(source, config)?.iter().map(|x| (x.line, x.rule)).collect())
+        };
+        let expected = vec![(3, "unused-variable"), (4, "shadowed-variable"), (6, "undefined-global"), (7, "assignment-in-condition")];
+        assert_eq!(expected, rules(&Config::default())?);
+        let config = Config::parse("# project settings\nshadowed-variable = off\n")?;
+        assert_eq!(vec![(3, "unused-variable"), (6, "undefined-global"), (7, "assignment-in-condition")], rules(&config)?);
+
+        // `while true` is the allowed infinite loop, `break` and `continue` may end a block
+        let source = "var i = 0;\nwhile true {\n    i = i + 1;\n    if i > 3 {\n        break;\n    }\n    if 1 < 2 {\n        continue;\n        print i;\n    }\n}\nwhile (true) { break; }\nif i < 2 { print i; }\nwhile false {\n    break;\n    i = 0;\n}\n";
+        let warnings: Vec<(usize, &str)> = lint::lint(source, &Config::default())?.iter().map(|x| (x.line, x.rule)).collect();
+        assert_eq!(vec![(7, "constant-condition"), (9, "unreachable-code"), (14, "constant-condition"), (16, "unreachable-code")], warnings);
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_tests() {
+        use serde::Deserialize;
+        use serde::de::IntoDeserializer;
+        use serde::de::value::{Error, SeqDeserializer};
+        fn value<'a>(x: impl IntoDeserializer<'a, Error>) -> Value {
+            Value::deserialize(x.into_deserializer()).unwrap()
+        }
+        assert_eq!(Value::FLOAT(1.5), value(1.5f64));
+        assert_eq!(Value::FLOAT(3.0), value(3u64));
+        assert_eq!(Value::STRING("hi".to_string()), value("hi"));
+        assert_eq!(Value::NIL, value(()));
+        // no collection types in the language yet
+        assert!(Value::deserialize(SeqDeserializer::<_, Error>::new(vec![1u64].into_iter())).is_err());
+    }
+}
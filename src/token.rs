@@ -6,11 +6,13 @@ pub struct Token {
     pub lexeme: String,
     pub t: TokenType,
     pub line: usize,
+    pub col: usize, // column of the first character of the token, starting at 0
+    pub len: usize, // number of source characters spanned by the token
 }
 
 impl Token {
     pub fn new(lexeme: String, t: TokenType, line: usize) -> Self {
-        Token {lexeme, t, line}
+        Token {lexeme, t, line, col: 0, len: 0}
     }
 }
 
@@ -93,6 +95,9 @@ pub enum TokenType {
     AS,
     FROM,
 
+    // only emitted by `Scanner::with_comments`
+    COMMENT,
+
     EOF
 }
 
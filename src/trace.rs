@@ -0,0 +1,51 @@
+use std::io::Write;
+
+use crate::Error;
+use crate::chunk::Chunk;
+use crate::nanbox::NanBox;
+
+// prints every instruction dispatched by the VM together with the state of the value stack
+pub struct Tracer {
+    out: Box<dyn Write>,
+    lines: Option<(usize, usize)>, // inclusive range of source lines to trace, all lines if None
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, lines: Option<(usize, usize)>) -> Self {
+        Tracer {out, lines}
+    }
+
+    // writes trace line for instruction at `offset`, called before instruction is executed
+    pub fn trace(&mut self, chunk: &Chunk, offset: usize, stack: &[NanBox]) -> Result<(), Error> {
+        let line = chunk.get_line(offset);
+        if let Some((from, to)) = self.lines {
+            if line < from || line > to {
+                return Ok(());
+            }
+        }
+        let instruction = chunk.code[offset].describe(chunk);
+        let stack: Vec<String> = stack.iter().map(|x| x.repr()).collect();
+        match writeln!(self.out, "{:0>4}  {:>4}  {:<24} [{}]", offset, line, instruction, stack.join(", ")) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::IO_ERROR)
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        match self.out.flush() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::IO_ERROR)
+        }
+    }
+}
+
+// parses line range of the form `from-to`, `from-` or `-to`
+pub fn parse_line_range(s: &str) -> Option<(usize, usize)> {
+    let (from, to) = s.split_once('-')?;
+    let from = if from.is_empty() { 0 } else { from.parse().ok()? };
+    let to = if to.is_empty() { usize::MAX } else { to.parse().ok()? };
+    if from > to {
+        return None;
+    }
+    Some((from, to))
+}
@@ -0,0 +1,151 @@
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::Error;
+use crate::native::{self, Arity};
+use crate::sandbox::Capability;
+use crate::value::Value;
+use crate::vm::VM;
+
+// objects of the host program handed to scripts, e.g. `req.header("x")` on a request context.
+// the object is shared with the host through `Rc<RefCell<..>>`, scripts only reach it through the
+// methods and properties of its `Class`. like natives these are plain functions which report
+// failures with `native::error`, the VM adds the line
+
+pub type Method = fn(&mut VM, &Userdata, &[Value]) -> Result<Value, Error>;
+pub type Getter = fn(&mut VM, &Userdata) -> Result<Value, Error>;
+pub type Setter = fn(&mut VM, &Userdata, Value) -> Result<(), Error>;
+
+// type of userdata, registered with `VM::register_class`
+pub struct Class {
+    name: String,
+    pub(crate) methods: HashMap<String, (Arity, Option<Capability>, Method)>,
+    pub(crate) getters: HashMap<String, Getter>,
+    pub(crate) setters: HashMap<String, Setter>,
+    display: Option<fn(&Userdata) -> String>,
+    equal: Option<fn(&Userdata, &Userdata) -> bool>, // None => objects are only equal to themselves
+}
+
+impl Class {
+    pub fn new(name: &str) -> Self {
+        Class {name: name.to_string(), methods: HashMap::new(), getters: HashMap::new(), setters: HashMap::new(), display: None, equal: None}
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn add_method(&mut self, name: &str, arity: impl Into<Arity>, method: Method) {
+        self.methods.insert(name.to_string(), (arity.into(), None, method));
+    }
+
+    // method with a side effect, see `VM::register_native_requiring`
+    pub fn add_method_requiring(&mut self, name: &str, arity: impl Into<Arity>, capability: Capability, method: Method) {
+        self.methods.insert(name.to_string(), (arity.into(), Some(capability), method));
+    }
+
+    pub fn add_getter(&mut self, name: &str, getter: Getter) {
+        self.getters.insert(name.to_string(), getter);
+    }
+
+    pub fn add_setter(&mut self, name: &str, setter: Setter) {
+        self.setters.insert(name.to_string(), setter);
+    }
+
+    // text printed by `print`, `<Name object>` by default
+    pub fn set_display(&mut self, display: fn(&Userdata) -> String) {
+        self.display = Some(display);
+    }
+
+    // equality of two objects of this class used by `==`
+    pub fn set_equal(&mut self, equal: fn(&Userdata, &Userdata) -> bool) {
+        self.equal = Some(equal);
+    }
+}
+
+#[derive(Clone)]
+pub struct Userdata {
+    class: Rc<Class>,
+    object: Rc<RefCell<dyn Any>>,
+}
+
+impl Userdata {
+    pub fn new<T: Any>(class: Rc<Class>, object: T) -> Self {
+        Userdata {class, object: Rc::new(RefCell::new(object))}
+    }
+
+    // userdata for an object the host keeps a handle to
+    pub fn shared(class: Rc<Class>, object: Rc<RefCell<dyn Any>>) -> Self {
+        Userdata {class, object}
+    }
+
+    pub fn class(&self) -> &Class {
+        &self.class
+    }
+
+    pub fn object(&self) -> &Rc<RefCell<dyn Any>> {
+        &self.object
+    }
+
+    pub fn borrow<T: Any>(&self) -> Result<Ref<'_, T>, Error> {
+        let object = match self.object.try_borrow() {
+            Ok(x) => x,
+            Err(_) => return Err(self.in_use())
+        };
+        Ref::filter_map(object, |x| x.downcast_ref::<T>()).map_err(|_| self.mismatch::<T>())
+    }
+
+    pub fn borrow_mut<T: Any>(&self) -> Result<RefMut<'_, T>, Error> {
+        let object = match self.object.try_borrow_mut() {
+            Ok(x) => x,
+            Err(_) => return Err(self.in_use())
+        };
+        RefMut::filter_map(object, |x| x.downcast_mut::<T>()).map_err(|_| self.mismatch::<T>())
+    }
+
+    fn in_use(&self) -> Error {
+        native::error(format!("BorrowError: `{}` object is already in use", self.class.name))
+    }
+
+    fn mismatch<T>(&self) -> Error {
+        native::error(format!("TypeError: `{}` object is not a `{}`", self.class.name, std::any::type_name::<T>()))
+    }
+}
+
+impl PartialEq for Userdata {
+    fn eq(&self, other: &Userdata) -> bool {
+        if !Rc::ptr_eq(&self.class, &other.class) {
+            return false;
+        }
+        match self.class.equal {
+            Some(equal) => equal(self, other),
+            None => Rc::ptr_eq(&self.object, &other.object)
+        }
+    }
+}
+
+// userdata is only equal or unordered
+impl PartialOrd for Userdata {
+    fn partial_cmp(&self, other: &Userdata) -> Option<Ordering> {
+        if self == other { Some(Ordering::Equal) } else { None }
+    }
+}
+
+impl fmt::Display for Userdata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.class.display {
+            Some(display) => write!(f, "{}", display(self)),
+            None => write!(f, "<{} object>", self.class.name)
+        }
+    }
+}
+
+impl fmt::Debug for Userdata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} object>", self.class.name)
+    }
+}
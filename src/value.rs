@@ -2,12 +2,16 @@ use std::ops::{Add, Sub, Mul, Div, Rem, Neg, BitOr, BitAnd};
 use std::fmt;
 
 use crate::Error;
+use crate::native::Native;
+use crate::userdata::Userdata;
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Value {
     FLOAT(f64),
     BOOL(bool),
     STRING(String),
+    NATIVE(Native),
+    USERDATA(Userdata),
     NIL
 }
 
@@ -120,12 +124,37 @@ impl Rem for Value {
     }
 }
 
+impl Value {
+    // name of the type of the value in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::FLOAT(_) => "number",
+            Value::BOOL(_) => "bool",
+            Value::STRING(_) => "string",
+            Value::NATIVE(_) => "native function",
+            Value::USERDATA(_) => "userdata",
+            Value::NIL => "nil",
+        }
+    }
+
+    // representation used by debugging tools, unlike `Display` strings are quoted and nil is visible
+    pub fn repr(&self) -> String {
+        match self {
+            Value::STRING(x) => format!("{:?}", x),
+            Value::NIL => "nil".to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::FLOAT(x) => write!(f, "{}", x),
             Value::BOOL(x) => write!(f, "{}", x),
             Value::STRING(x) => write!(f, "{}", x),
+            Value::NATIVE(x) => write!(f, "{:?}", x),
+            Value::USERDATA(x) => write!(f, "{}", x),
             Value::NIL => write!(f, ""),
         }
     }
@@ -1,8 +1,23 @@
 use crate::chunk::{Chunk, OpCode};
 use crate::Error;
+use crate::gc::Heap;
+use crate::intern::{Interner, Symbol};
+use crate::limits::{Limit, Limits};
+use crate::nanbox::NanBox;
+use crate::native::{self, Arity, Native, NativeFn};
+use crate::sandbox::{Capability, Sandbox};
+use crate::snapshot::{Objects, Snapshot, Word};
+use crate::userdata::{Class, Userdata};
 use crate::value::Value;
+use crate::trace::Tracer;
 
+use std::any::Any;
 use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 macro_rules! binary_op {
     ($self:ident, $op:tt) => {{ 
@@ -12,77 +27,490 @@ macro_rules! binary_op {
     }}
 }
 
+// instructions between two checks of the timeout, reading the clock is slow compared to an instruction
+const CLOCK_INTERVAL: u64 = 1024;
+
+// called by the VM before every instruction it dispatches, used by debuggers
+pub trait Hook {
+    fn before_instruction(&mut self, vm: &mut VM) -> Result<(), Error>;
+}
+
+// stops a running program from another thread, e.g. when a request timed out. the VM checks the flag
+// at backward jumps and calls, so every loop notices it, and fails with `Error::INTERRUPTED`
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // withdraws an interrupt the VM hasn't noticed yet, the VM clears the flag when it stops
+    pub fn clear(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
 pub struct VM {
     chunk: Chunk,
+    constants: Vec<NanBox>, // values of `chunk`, converted once so reading them doesn't allocate
+    paused: Vec<NanBox>, // constants of the program paused by `evaluate`, still roots of the heap
+    heap: Heap,
     ip: usize, // instruction pointer
-    stack: Vec<Value>,
-    symbol_table: HashMap<String, Value>
+    stack: Vec<NanBox>,
+    links: Vec<usize>, // slot in `globals` of every global slot of `chunk`
+    interner: Interner, // names of the globals, the symbol of a name is its slot in `globals`
+    globals: Vec<Option<NanBox>>, // None => declared by some chunk but not defined yet
+    classes: HashMap<String, Rc<Class>>, // types of userdata by name
+    tracer: Option<Tracer>,
+    hook: Option<Box<dyn Hook>>,
+    between: bool, // the hook runs between two instructions, the only time the program can be stored
+    out: Box<dyn Write>, // destination of `print` statements
+    limits: Limits,
+    limited: bool, // some limit is set, saves checking them one by one on every instruction
+    steps: u64, // instructions executed by the current run
+    started: Instant, // start of the current run
+    sandbox: Option<Sandbox>, // None => every side effect is allowed
+    interrupt: InterruptHandle,
 }
 
 impl Default for VM {
     fn default() -> Self {
-        VM {chunk: Chunk::default(), ip: 0, stack: vec![], symbol_table: HashMap::new()}
+        VM {chunk: Chunk::default(), constants: vec![], paused: vec![], heap: Heap::default(), ip: 0, stack: vec![], links: vec![], interner: Interner::default(), globals: vec![], classes: HashMap::new(), tracer: None, hook: None, between: false, out: Box::new(std::io::stdout()), limits: Limits::default(), limited: false, steps: 0, started: Instant::now(), sandbox: None, interrupt: InterruptHandle::default()}
     }
 }
 
 impl VM {
 
+    // loads chunk to run from its start, globals of earlier chunks are kept
     pub fn set_chunk(&mut self, chunk: Chunk) {
+        self.constants = self.constants(&chunk);
+        self.links = self.link(&chunk);
+        self.chunk = chunk;
+        self.ip = 0;
+        // left over by a chunk which stopped at an error
+        self.stack.clear();
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn set_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hook = Some(hook);
+    }
+
+    pub fn set_output(&mut self, out: Box<dyn Write>) {
+        self.out = out;
+    }
+
+    // resources a run may use, checked from the next instruction on
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limited = !limits.is_empty();
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    // runs scripts with only the capabilities granted by `sandbox`
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) {
+        self.sandbox = Some(sandbox);
+    }
+
+    pub fn sandbox(&self) -> Option<&Sandbox> {
+        self.sandbox.as_ref()
+    }
+
+    // checked by natives with effects beyond the capability they were registered with, e.g.
+    // `vm.require(Capability::WRITE, Some(target))?`
+    pub fn require(&self, capability: Capability, resource: Option<&str>) -> Result<(), Error> {
+        match &self.sandbox {
+            Some(sandbox) => sandbox.check(capability, resource),
+            None => Ok(())
+        }
+    }
+
+    // checked before calling a native or method of the host registered with `capability`
+    fn require_effect(&self, capability: Option<Capability>, args: &[NanBox]) -> Result<(), Error> {
+        match capability {
+            Some(capability) if capability.has_resources() => self.require(capability, args.first().and_then(|x| x.as_str())),
+            Some(capability) => self.require(capability, None),
+            None => Ok(())
+        }
+    }
+
+    // handle for interrupting the program from another thread
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    pub fn chunk(&self) -> &Chunk {
+        &self.chunk
+    }
+
+    // offset of the instruction which will be executed next
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    // values are copied out of the heap, words pointing into it must not outlive a collection
+    pub fn stack(&self) -> Vec<Value> {
+        self.stack.iter().map(|x| x.to_value()).collect()
+    }
+
+    // defined global variables, in no particular order
+    pub fn globals(&self) -> Vec<(&str, Value)> {
+        self.interner.names().zip(&self.globals).filter_map(|(name, value)| value.map(|x| (name, x.to_value()))).collect()
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.interner.get(name).and_then(|symbol| self.globals[symbol.0]).map(|x| x.to_value())
+    }
+
+    // overwrites value of existing global variable, returns false if variable is not defined
+    pub fn set_global(&mut self, name: &str, value: Value) -> bool {
+        let symbol = match self.interner.get(name) {
+            Some(symbol) => symbol,
+            None => return false
+        };
+        match &mut self.globals[symbol.0] {
+            Some(x) => {
+                *x = self.heap.manage(value);
+                true
+            },
+            None => false
+        }
+    }
+
+    // defines global variable, or overwrites its value if it is already defined
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        let slot = self.interner.intern(name).0;
+        if self.globals.len() <= slot {
+            self.globals.resize(slot + 1, None);
+        }
+        self.globals[slot] = Some(self.heap.manage(value));
+    }
+
+    // makes `function` callable from scripts as global `name`
+    pub fn register_native(&mut self, name: &str, arity: impl Into<Arity>, function: NativeFn) {
+        self.define_global(name, Value::NATIVE(Native {name: name.to_string(), arity: arity.into(), function, capability: None}));
+    }
+
+    // makes `function` with a side effect callable as global `name`, a sandboxed VM only calls it with
+    // `capability` granted. the resource of capabilities granted per resource is the first argument
+    pub fn register_native_requiring(&mut self, name: &str, arity: impl Into<Arity>, capability: Capability, function: NativeFn) {
+        self.define_global(name, Value::NATIVE(Native {name: name.to_string(), arity: arity.into(), function, capability: Some(capability)}));
+    }
+
+    // calls native `callee` from the host, errors have no line
+    pub fn call(&mut self, callee: &Value, args: &[Value]) -> Result<Value, Error> {
+        let native = match callee {
+            Value::NATIVE(x) => x,
+            x => return Err(Error::RUNTIME_ERROR(format!("TypeError: `{}` is not callable", x.repr()), 0))
+        };
+        if !native.arity.accepts(args.len()) {
+            return Err(Error::RUNTIME_ERROR(format!("TypeError: `{}` takes {} but {} were given", native.name, native.arity, args.len()), 0));
+        }
+        // e.g. called by a hook, the native runs in the middle of the paused program
+        let between = std::mem::replace(&mut self.between, false);
+        let result = (native.function)(self, args).map_err(|e| native::at_line(e, &native.name, 0));
+        self.between = between;
+        result
+    }
+
+    // makes `class` available for creating userdata with `userdata`
+    pub fn register_class(&mut self, class: Class) -> Rc<Class> {
+        let class = Rc::new(class);
+        self.classes.insert(class.name().to_string(), class.clone());
+        class
+    }
+
+    pub fn class(&self, name: &str) -> Option<Rc<Class>> {
+        self.classes.get(name).cloned()
+    }
+
+    // wraps `object` of the host as userdata of registered class `class`
+    pub fn userdata<T: Any>(&self, class: &str, object: T) -> Result<Value, Error> {
+        match self.class(class) {
+            Some(class) => Ok(Value::USERDATA(Userdata::new(class, object))),
+            None => Err(native::error(format!("NameError: undefined class `{}`", class)))
+        }
+    }
+
+    // evaluates chunk produced by `Compiler::compile_expression` on top of the current program state
+    // and returns value of the expression, the paused program is resumed afterwards
+    pub fn evaluate(&mut self, chunk: Chunk) -> Result<Value, Error> {
+        let constants = self.constants(&chunk);
+        self.paused = std::mem::replace(&mut self.constants, constants);
+        let links = self.link(&chunk);
+        let links = std::mem::replace(&mut self.links, links);
+        let chunk = std::mem::replace(&mut self.chunk, chunk);
+        let ip = std::mem::replace(&mut self.ip, 0);
+        let hook = self.hook.take();
+        let tracer = self.tracer.take();
+        let between = std::mem::replace(&mut self.between, false);
+        let depth = self.stack.len();
+
+        let result = self.run();
+        let value = if self.stack.len() > depth { self.stack.pop().unwrap().to_value() } else { Value::NIL };
+        self.stack.truncate(depth);
+
         self.chunk = chunk;
+        self.constants = std::mem::take(&mut self.paused);
+        self.links = links;
+        self.ip = ip;
+        self.hook = hook;
+        self.tracer = tracer;
+        self.between = between;
+        result.map(|_| value)
+    }
+
+    // state of the program paused by a `Hook` between two instructions, see `snapshot`
+    pub fn snapshot(&self) -> Result<Snapshot, Error> {
+        // natives, getters, setters and methods run halfway through an instruction, their callee and
+        // arguments are still on the stack
+        if !self.between {
+            return Err(Error::RUNTIME_ERROR("SnapshotError: a program can only be stored between two instructions".into(), 0));
+        }
+        let mut objects = Objects::default();
+        let constants = self.constants.iter().map(|x| objects.word(*x)).collect::<Result<Vec<Word>, Error>>()?;
+        let stack = self.stack.iter().map(|x| objects.word(*x)).collect::<Result<Vec<Word>, Error>>()?;
+        let mut globals = vec![];
+        for (name, value) in self.interner.names().zip(&self.globals) {
+            globals.push((name.to_string(), value.map(|x| objects.word(x)).transpose()?));
+        }
+        Ok(Snapshot {chunk: self.chunk.clone(), ip: self.ip, objects: objects.objects, constants, stack, globals})
     }
 
-    pub fn execute(&mut self, debug: bool) -> Result<(), Error> {
+    // replaces the program and globals with those of `snapshot`, `execute` continues where it was taken.
+    // natives it refers to must be registered first, other settings of the VM are kept
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), Error> {
+        let natives: HashMap<String, Native> = self.globals.iter().flatten().filter_map(|x| x.as_native()).map(|x| (x.name.clone(), x.clone())).collect();
+        let objects = snapshot.objects(&mut self.heap, |name| natives.get(name).cloned())?;
+        let word = |x: &Word| match x {
+            Word::FLOAT(x) => NanBox::float(*x),
+            Word::BOOL(x) => NanBox::bool(*x),
+            Word::NIL => NanBox::NIL,
+            Word::OBJECT(id) => objects[*id],
+        };
+        self.constants = snapshot.constants.iter().map(word).collect();
+        self.stack = snapshot.stack.iter().map(word).collect();
+        self.interner = Interner::default();
+        self.globals = vec![];
+        for (name, value) in &snapshot.globals {
+            self.interner.intern(name);
+            self.globals.push(value.as_ref().map(word));
+        }
+        self.links = self.link(&snapshot.chunk);
+        self.chunk = snapshot.chunk;
+        self.ip = snapshot.ip;
+        Ok(())
+    }
+
+    // runs the chunk, returns value of the last expression statement (nil if there is none)
+    pub fn execute(&mut self, debug: bool) -> Result<Value, Error> {
         if debug {
             println!("------------------------------");
             self.chunk.dissassemble_chunk();
             println!("------------------------------");
         }
         if self.chunk.code.len() == 0 {
-            return Ok(());
+            return Ok(Value::NIL);
+        }
+        self.steps = 0;
+        self.started = Instant::now();
+        if let Err(e) = self.run() {
+            // values of the stopped program are garbage now
+            self.stack.clear();
+            return Err(e);
         }
+        //println!("{:?}", self.stack);
+        Ok(self.stack.pop().map(|x| x.to_value()).unwrap_or(Value::NIL))
+    }
+
+    // values of `chunk` moved onto the heap
+    fn constants(&mut self, chunk: &Chunk) -> Vec<NanBox> {
+        chunk.values().iter().map(|x| self.heap.manage(x.clone())).collect()
+    }
+
+    // slots of the globals of `chunk` in the table of the VM, globals new to the VM get a slot
+    fn link(&mut self, chunk: &Chunk) -> Vec<usize> {
+        let links: Vec<usize> = chunk.globals.names().map(|name| self.interner.intern(name).0).collect();
+        let n = links.iter().map(|x| x + 1).max().unwrap_or(0);
+        if self.globals.len() < n {
+            self.globals.resize(n, None);
+        }
+        links
+    }
+
+    // userdata the property or method `name` is looked up on
+    fn receiver<'a>(object: &'a NanBox, name: &str, what: &str, line: usize) -> Result<&'a Userdata, Error> {
+        match object.as_userdata() {
+            Some(x) => Ok(x),
+            None => Err(Error::RUNTIME_ERROR(format!("TypeError: `{}` has no {} `{}`", object.repr(), what, name), line))
+        }
+    }
+
+    // frees heap objects which the program can no longer reach
+    fn collect_garbage(&mut self) {
+        let roots = self.stack.iter().chain(self.globals.iter().flatten()).chain(&self.constants).chain(&self.paused);
+        self.heap.collect(roots);
+    }
+
+    fn check_interrupt(&self) -> Result<(), Error> {
+        // swapped, so the interrupt stops one program and not also the next one
+        if self.interrupt.0.swap(false, Ordering::Relaxed) {
+            return Err(Error::INTERRUPTED(self.chunk.get_line(self.ip)));
+        }
+        Ok(())
+    }
+
+    // moves to `target`, jumping backward may loop forever so it checks for an interrupt
+    fn jump(&mut self, target: usize) -> Result<(), Error> {
+        if target < self.ip {
+            self.check_interrupt()?;
+        }
+        self.ip = target;
+        Ok(())
+    }
+
+    fn limit_exceeded(&self, limit: Limit) -> Error {
+        Error::LIMIT_EXCEEDED(limit, self.chunk.get_line(self.ip))
+    }
+
+    // stops the program once the last instruction took it over one of its limits
+    fn check_limits(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if let Some(max) = self.limits.instructions {
+            if self.steps > max {
+                return Err(self.limit_exceeded(Limit::INSTRUCTIONS(max)));
+            }
+        }
+        if let Some(max) = self.limits.stack {
+            if self.stack.len() > max {
+                return Err(self.limit_exceeded(Limit::STACK(max)));
+            }
+        }
+        if let Some(max) = self.limits.memory {
+            // only live objects count, garbage is collected before giving up
+            if self.heap.stats().bytes > max {
+                self.collect_garbage();
+                if self.heap.stats().bytes > max {
+                    return Err(self.limit_exceeded(Limit::MEMORY(max)));
+                }
+            }
+        }
+        if let Some(timeout) = self.limits.timeout {
+            if self.steps.is_multiple_of(CLOCK_INTERVAL) && self.started.elapsed() > timeout {
+                return Err(self.limit_exceeded(Limit::TIMEOUT(timeout)));
+            }
+        }
+        Ok(())
+    }
+
+    // `"x" * n` is checked against the memory limit before the string is built, it could exhaust the
+    // memory of the host on its own
+    fn check_repeat(&self) -> Result<(), Error> {
+        let max = match self.limits.memory {
+            Some(x) => x,
+            None => return Ok(())
+        };
+        let n = self.stack.len();
+        let (a, b) = (self.stack[n - 2], self.stack[n - 1]);
+        let (string, count) = match (a.as_str(), b.as_float(), a.as_float(), b.as_str()) {
+            (Some(string), Some(count), _, _) | (_, _, Some(count), Some(string)) => (string, count),
+            _ => return Ok(())
+        };
+        // the float to integer cast saturates, so huge counts stay huge
+        let bytes = string.len().saturating_mul(count as usize);
+        if self.heap.stats().bytes.saturating_add(bytes) > max {
+            return Err(self.limit_exceeded(Limit::MEMORY(max)));
+        }
+        Ok(())
+    }
+
+    // runs instructions of the chunk until `RETURN` is reached
+    fn run(&mut self) -> Result<(), Error> {
         loop {
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
+            if self.limited {
+                self.check_limits()?;
+            }
+            if let Some(tracer) = &mut self.tracer {
+                tracer.trace(&self.chunk, self.ip, &self.stack)?;
+            }
+            if let Some(mut hook) = self.hook.take() {
+                self.between = true;
+                let result = hook.before_instruction(self);
+                self.between = false;
+                self.hook = Some(hook);
+                result?;
+            }
             let instruction = self.chunk.read_instruction(&mut self.ip);
             match instruction {
                 OpCode::RETURN => {
-                    //println!("{:?}", self.stack);
-                    assert_eq!(0, self.stack.len());
+                    if let Some(tracer) = &mut self.tracer {
+                        tracer.flush()?;
+                    }
                     return Ok(());
                 },
                 OpCode::POP => { self.stack.pop(); },
-                OpCode::PRINT => println!("{}", self.stack.pop().unwrap()),
-                OpCode::CONSTANT(addr) => {
-                    let value = self.chunk.read_value(*addr);
-                    self.stack.push(value);
+                OpCode::POPN(n) => {
+                    let n = self.stack.len() - *n;
+                    self.stack.truncate(n);
                 },
-                OpCode::DEFINE_GLOBAL(addr) => {
-                    if let Value::STRING(s) = self.chunk.read_value(*addr) {
-                        self.symbol_table.insert(s, self.stack.pop().unwrap());
-                    } else {
-                        return Err(Error::RUNTIME_ERROR("NameError: Invalid identifier".into(), self.chunk.get_line(self.ip)));
+                OpCode::PRINT => {
+                    if let Err(e) = self.require(Capability::PRINT, None) {
+                        return Err(native::at_line(e, "print", self.chunk.get_line(self.ip)));
+                    }
+                    let value = self.stack.pop().unwrap();
+                    if writeln!(self.out, "{}", value).is_err() {
+                        return Err(Error::IO_ERROR);
                     }
                 },
-                OpCode::GET_GLOBAL(addr) => {
-                    if let Value::STRING(s) = self.chunk.read_value(*addr) {
-                        if !self.symbol_table.contains_key(&s) {
-                            return Err(Error::RUNTIME_ERROR(format!("NameError: undefined variable `{}`", s), self.chunk.get_line(self.ip)));
-                        }
-                        self.stack.push(self.symbol_table.get(&s).unwrap().clone());
+                OpCode::CONSTANT(addr) => {
+                    self.stack.push(self.constants[*addr]);
+                },
+                OpCode::DEFINE_GLOBAL(slot) => {
+                    self.globals[self.links[*slot]] = self.stack.pop();
+                },
+                OpCode::GET_GLOBAL(slot) => {
+                    let slot = self.links[*slot];
+                    match self.globals[slot] {
+                        Some(x) => self.stack.push(x),
+                        None => return Err(Error::RUNTIME_ERROR(format!("NameError: undefined variable `{}`", self.interner.name(Symbol(slot))), self.chunk.get_line(self.ip)))
                     }
                 },
-                OpCode::SET_GLOBAL(addr) => {
-                    if let Value::STRING(s) = self.chunk.read_value(*addr) {
-                        if !self.symbol_table.contains_key(&s) {
-                            return Err(Error::RUNTIME_ERROR(format!("NameError: undefined variable `{}`", s), self.chunk.get_line(self.ip)));
-                        }
-                        self.symbol_table.insert(s, self.stack.pop().unwrap());
+                OpCode::SET_GLOBAL(slot) => {
+                    let slot = self.links[*slot];
+                    // assignment is an expression, its value stays on the stack
+                    match &mut self.globals[slot] {
+                        Some(x) => *x = *self.stack.last().unwrap(),
+                        None => return Err(Error::RUNTIME_ERROR(format!("NameError: undefined variable `{}`", self.interner.name(Symbol(slot))), self.chunk.get_line(self.ip)))
                     }
                 },
                 OpCode::GET_LOCAL(addr) => {
-                    let val = self.stack[*addr].clone();
+                    let val = self.stack[*addr];
                     self.stack.push(val);
                 },
                 OpCode::SET_LOCAL(addr) => {
-                    let val = self.stack.last().unwrap().clone();
+                    let val = *self.stack.last().unwrap();
                     self.stack[*addr] = val;
                 },
                 OpCode::NEGATE => {
@@ -90,9 +518,9 @@ impl VM {
                     if n < 1 {
                         return Err(Error::RUNTIME_ERROR("IndexError: Stack index out of range".into(), self.chunk.get_line(self.ip)));
                     }
-                    let value = -self.stack[n - 1].clone();
+                    let value = -self.stack[n - 1];
                     if let Ok(x) = value {
-                        self.stack[n - 1] = x.clone();
+                        self.stack[n - 1] = self.heap.manage(x);
                     } else {
                         return Err(Error::RUNTIME_ERROR("TypeError: Unsupported operand types for `-`".into(), self.chunk.get_line(self.ip)));
                     }   
@@ -102,9 +530,8 @@ impl VM {
                     if n < 1 {
                         return Err(Error::RUNTIME_ERROR("IndexError: Stack index out of range".into(), self.chunk.get_line(self.ip)));
                     }
-                    let value = self.stack[n - 1].clone();
-                    match value {
-                        Value::BOOL(x) => self.stack[n - 1] = Value::BOOL(!x),
+                    match self.stack[n - 1].as_bool() {
+                        Some(x) => self.stack[n - 1] = NanBox::bool(!x),
                         _ => return Err(Error::RUNTIME_ERROR("TypeError: Unsupported operand types for `!`".into(), self.chunk.get_line(self.ip)))
                     }  
                 },
@@ -114,7 +541,7 @@ impl VM {
                     }
                     let value = binary_op!(self, +);
                     if let Ok(x) = value {
-                        self.stack.push(x.clone());
+                        self.stack.push(self.heap.manage(x));
                     } else {
                         return Err(Error::RUNTIME_ERROR("TypeError: Unsupported operand types for `+`".into(), self.chunk.get_line(self.ip)));
                     }
@@ -125,7 +552,7 @@ impl VM {
                     }
                     let value = binary_op!(self, |);
                     if let Ok(x) = value {
-                        self.stack.push(x.clone());
+                        self.stack.push(self.heap.manage(x));
                     } else {
                         return Err(Error::RUNTIME_ERROR("TypeError: Unsupported operand types for `or`".into(), self.chunk.get_line(self.ip)));
                     }
@@ -136,7 +563,7 @@ impl VM {
                     }
                     let value = binary_op!(self, &);
                     if let Ok(x) = value {
-                        self.stack.push(x.clone());
+                        self.stack.push(self.heap.manage(x));
                     } else {
                         return Err(Error::RUNTIME_ERROR("TypeError: Unsupported operand types for `and`".into(), self.chunk.get_line(self.ip)));
                     }
@@ -147,7 +574,7 @@ impl VM {
                     }
                     let value = binary_op!(self, -);
                     if let Ok(x) = value {
-                        self.stack.push(x.clone());
+                        self.stack.push(self.heap.manage(x));
                     } else {
                         return Err(Error::RUNTIME_ERROR("TypeError: Unsupported operand types for `-`".into(), self.chunk.get_line(self.ip)));
                     }
@@ -156,9 +583,10 @@ impl VM {
                     if self.stack.len() < 2 {
                         return Err(Error::RUNTIME_ERROR("IndexError: Stack index out of range".into(), self.chunk.get_line(self.ip)));
                     }
+                    self.check_repeat()?;
                     let value = binary_op!(self, *);
                     if let Ok(x) = value {
-                        self.stack.push(x.clone());
+                        self.stack.push(self.heap.manage(x));
                     } else {
                         return Err(Error::RUNTIME_ERROR("TypeError: Unsupported operand types for `*`".into(), self.chunk.get_line(self.ip)));
                     }
@@ -169,7 +597,7 @@ impl VM {
                     }
                     let value = binary_op!(self, /);
                     match value {
-                        Ok(x) => self.stack.push(x.clone()),
+                        Ok(x) => self.stack.push(self.heap.manage(x)),
                         Err(e) => {
                             match e {
                                 Error::DIVIDE_BY_ZERO => return Err(Error::RUNTIME_ERROR("DivideByZero Error".into(), self.chunk.get_line(self.ip))),
@@ -183,43 +611,176 @@ impl VM {
                         return Err(Error::RUNTIME_ERROR("IndexError: Stack index out of range".into(), self.chunk.get_line(self.ip)));
                     }
                     let value = binary_op!(self, %)?;
-                    self.stack.push(value);
+                    self.stack.push(self.heap.manage(value));
                 },
                 OpCode::EQUAL => {
                     if self.stack.len() < 2 {
                         return Err(Error::RUNTIME_ERROR("IndexError: Stack index out of range".into(), self.chunk.get_line(self.ip)));
                     }
                     let value = binary_op!(self, ==);
-                    self.stack.push(Value::BOOL(value));
+                    self.stack.push(NanBox::bool(value));
                 },
                 OpCode::GREATER => {
                     if self.stack.len() < 2 {
                         return Err(Error::RUNTIME_ERROR("IndexError: Stack index out of range".into(), self.chunk.get_line(self.ip)));
                     }
                     let value = binary_op!(self, >);
-                    self.stack.push(Value::BOOL(value));
+                    self.stack.push(NanBox::bool(value));
                 },
                 OpCode::LESS => {
                     if self.stack.len() < 2 {
                         return Err(Error::RUNTIME_ERROR("IndexError: Stack index out of range".into(), self.chunk.get_line(self.ip)));
                     }
                     let value = binary_op!(self, <);
-                    self.stack.push(Value::BOOL(value));
+                    self.stack.push(NanBox::bool(value));
+                },
+                // negated comparisons keep the semantics of the `BANG` they replace, `NaN <= 1` is `!(NaN > 1)`
+                OpCode::NOT_EQUAL => {
+                    if self.stack.len() < 2 {
+                        return Err(Error::RUNTIME_ERROR("IndexError: Stack index out of range".into(), self.chunk.get_line(self.ip)));
+                    }
+                    let value = binary_op!(self, ==);
+                    self.stack.push(NanBox::bool(!value));
+                },
+                OpCode::LESS_EQUAL => {
+                    if self.stack.len() < 2 {
+                        return Err(Error::RUNTIME_ERROR("IndexError: Stack index out of range".into(), self.chunk.get_line(self.ip)));
+                    }
+                    let value = binary_op!(self, >);
+                    self.stack.push(NanBox::bool(!value));
+                },
+                OpCode::GREATER_EQUAL => {
+                    if self.stack.len() < 2 {
+                        return Err(Error::RUNTIME_ERROR("IndexError: Stack index out of range".into(), self.chunk.get_line(self.ip)));
+                    }
+                    let value = binary_op!(self, <);
+                    self.stack.push(NanBox::bool(!value));
+                },
+                OpCode::POP_JUMP_IF_FALSE(jaddr) => {
+                    if self.stack.pop().unwrap().is_false() {
+                        self.jump(*jaddr)?;
+                    }
+                },
+                OpCode::INC_LOCAL(slot, addr) => {
+                    let value = self.stack[*slot] + self.constants[*addr];
+                    match value {
+                        Ok(x) => self.stack[*slot] = self.heap.manage(x),
+                        Err(_) => return Err(Error::RUNTIME_ERROR("TypeError: Unsupported operand types for `+`".into(), self.chunk.get_line(self.ip)))
+                    }
+                },
+                OpCode::LESS_LOCAL_LOCAL(a, b) => {
+                    let value = self.stack[*a] < self.stack[*b];
+                    self.stack.push(NanBox::bool(value));
+                },
+                OpCode::LESS_LOCAL_CONSTANT(slot, addr) => {
+                    let value = self.stack[*slot] < self.constants[*addr];
+                    self.stack.push(NanBox::bool(value));
+                },
+                OpCode::CALL(argc) => {
+                    self.check_interrupt()?;
+                    let line = self.chunk.get_line(self.ip);
+                    let callee = self.stack.len() - *argc - 1;
+                    let value = self.stack[callee];
+                    let native = match value.as_native() {
+                        Some(x) => x,
+                        None => return Err(Error::RUNTIME_ERROR(format!("TypeError: `{}` is not callable", value.repr()), line))
+                    };
+                    if !native.arity.accepts(*argc) {
+                        return Err(Error::RUNTIME_ERROR(format!("TypeError: `{}` takes {} but {} were given", native.name, native.arity, argc), line));
+                    }
+                    if let Err(e) = self.require_effect(native.capability, &self.stack[callee + 1..]) {
+                        return Err(native::at_line(e, &native.name, line));
+                    }
+                    // callee and arguments stay on the stack during the call, so they are roots of the heap
+                    let args: Vec<Value> = self.stack[callee + 1..].iter().map(|x| x.to_value()).collect();
+                    match (native.function)(self, &args) {
+                        Ok(x) => {
+                            self.stack.truncate(callee);
+                            let value = self.heap.manage(x);
+                            self.stack.push(value);
+                        },
+                        Err(e) => return Err(native::at_line(e, &native.name, line))
+                    }
+                },
+                // the object and arguments stay on the stack during calls of the host, like for natives
+                OpCode::GET_PROPERTY(addr) => {
+                    let line = self.chunk.get_line(self.ip);
+                    let (object, key) = (*self.stack.last().unwrap(), self.constants[*addr]);
+                    let name = key.as_str().unwrap();
+                    let userdata = Self::receiver(&object, name, "property", line)?;
+                    let class = userdata.class();
+                    let getter = match class.getters.get(name) {
+                        Some(x) => *x,
+                        None if class.methods.contains_key(name) => return Err(Error::RUNTIME_ERROR(format!("TypeError: method `{}` of `{}` must be called", name, class.name()), line)),
+                        None => return Err(Error::RUNTIME_ERROR(format!("AttributeError: `{}` object has no property `{}`", class.name(), name), line))
+                    };
+                    match getter(self, userdata) {
+                        Ok(x) => *self.stack.last_mut().unwrap() = self.heap.manage(x),
+                        Err(e) => return Err(native::at_line(e, name, line))
+                    }
+                },
+                OpCode::SET_PROPERTY(addr) => {
+                    let line = self.chunk.get_line(self.ip);
+                    let n = self.stack.len();
+                    let (object, value, key) = (self.stack[n - 2], self.stack[n - 1], self.constants[*addr]);
+                    let name = key.as_str().unwrap();
+                    let userdata = Self::receiver(&object, name, "property", line)?;
+                    let class = userdata.class();
+                    let setter = match class.setters.get(name) {
+                        Some(x) => *x,
+                        None if class.getters.contains_key(name) => return Err(Error::RUNTIME_ERROR(format!("AttributeError: property `{}` of `{}` is read-only", name, class.name()), line)),
+                        None => return Err(Error::RUNTIME_ERROR(format!("AttributeError: `{}` object has no property `{}`", class.name(), name), line))
+                    };
+                    if let Err(e) = setter(self, userdata, value.to_value()) {
+                        return Err(native::at_line(e, name, line));
+                    }
+                    // assignment is an expression, its value stays on the stack
+                    self.stack.truncate(n - 2);
+                    self.stack.push(value);
+                },
+                OpCode::INVOKE(addr, argc) => {
+                    self.check_interrupt()?;
+                    let line = self.chunk.get_line(self.ip);
+                    let base = self.stack.len() - *argc - 1;
+                    let (object, key) = (self.stack[base], self.constants[*addr]);
+                    let name = key.as_str().unwrap();
+                    let userdata = Self::receiver(&object, name, "method", line)?;
+                    let class = userdata.class();
+                    let (arity, capability, method) = match class.methods.get(name) {
+                        Some(x) => *x,
+                        None => return Err(Error::RUNTIME_ERROR(format!("AttributeError: `{}` object has no method `{}`", class.name(), name), line))
+                    };
+                    if !arity.accepts(*argc) {
+                        return Err(Error::RUNTIME_ERROR(format!("TypeError: `{}` takes {} but {} were given", name, arity, argc), line));
+                    }
+                    if let Err(e) = self.require_effect(capability, &self.stack[base + 1..]) {
+                        return Err(native::at_line(e, name, line));
+                    }
+                    let args: Vec<Value> = self.stack[base + 1..].iter().map(|x| x.to_value()).collect();
+                    match method(self, userdata, &args) {
+                        Ok(x) => {
+                            self.stack.truncate(base);
+                            let value = self.heap.manage(x);
+                            self.stack.push(value);
+                        },
+                        Err(e) => return Err(native::at_line(e, name, line))
+                    }
                 },
                 OpCode::IF(jaddr) => {
-                    if *self.stack.last().unwrap() == Value::BOOL(false) {
-                        self.ip = *jaddr;
+                    if self.stack.last().unwrap().is_false() {
+                        self.jump(*jaddr)?;
                     }
                 },
                 OpCode::IFN(jaddr) => {
-                    if *self.stack.last().unwrap() != Value::BOOL(false) {
-                        self.ip = *jaddr;
+                    if !self.stack.last().unwrap().is_false() {
+                        self.jump(*jaddr)?;
                     }
                 },
                 OpCode::JMP(jaddr) => {
-                    self.ip = *jaddr;
+                    self.jump(*jaddr)?;
                 },
             };
         }
     }
-}
\ No newline at end of file
+}
+